@@ -0,0 +1,123 @@
+//! Chat message deserialization and formatting throughput, as a baseline for
+//! parser and output work.
+//!
+//! Run with `cargo bench --bench parser`.
+
+use std::hint::black_box;
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+
+// The binary crate has no library target, so the parser and format modules
+// are included directly. Only the chat message parts of them are used here.
+#[allow(dead_code, unused_imports)]
+#[path = "../src/data.rs"]
+mod data;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/format.rs"]
+mod format;
+
+use data::ChatMessage;
+use format::OutputFormat;
+
+/// Chat message payload as sent by the server.
+fn payload(username: &str, msg: &str, meta: serde_json::Value) -> String {
+    serde_json::json!({
+        "username": username,
+        "msg": msg,
+        "meta": meta,
+        "time": 1760634672025u64
+    })
+    .to_string()
+}
+
+fn payloads() -> Vec<(&'static str, String)> {
+    let emote = "<img class=\"channel-emote\" src=\"https://example.com/harmony.png\" title=\":harmony:\" />";
+    vec![
+        (
+            "plain",
+            payload("Yuu", "It's hip to be square.", serde_json::json!({})),
+        ),
+        (
+            "greentext_team",
+            payload(
+                "PotF",
+                "&gt;XD <span style=\"display:none\" class=\"teamColorSpan\">-teamwg-</span>",
+                serde_json::json!({"addClass": "greentext"}),
+            ),
+        ),
+        (
+            "image_link",
+            payload(
+                "ChetBaker",
+                "<a href=\"https://example.com/image.jpg?ex=1234&amp;is=5678\" target=\"_blank\">\
+                    <img src=\"https://example.com/image.jpg?ex=1234&amp;is=5678\" /></a>",
+                serde_json::json!({}),
+            ),
+        ),
+        (
+            "quotes_and_links",
+            payload(
+                "Quot",
+                "\"He'll be fine\" <a href=\"http://example.com\" target=\"_blank\" rel=\"noopener noreferrer\">\
+                    http://example.com</a> <span style=\"display:none\" class=\"teamColorSpan\">-teamvst-</span>",
+                serde_json::json!({}),
+            ),
+        ),
+        (
+            "emote_spam",
+            payload(
+                "ChatSpammer",
+                &format!(
+                    "{} <span style=\"display:none\" class=\"teamColorSpan\">-teamck-</span>",
+                    vec![emote; 40].join(" ")
+                ),
+                serde_json::json!({}),
+            ),
+        ),
+    ]
+}
+
+fn deserialize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("deserialize");
+    for (name, json) in payloads() {
+        group.throughput(Throughput::Bytes(json.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| serde_json::from_str::<ChatMessage>(black_box(&json)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// Formatting into a new `String` per message versus a reused buffer, as the
+/// chat log does.
+fn format(c: &mut Criterion) {
+    let mut group = c.benchmark_group("format");
+    let chats: Vec<ChatMessage> = payloads()
+        .iter()
+        .map(|(_, json)| serde_json::from_str(json).unwrap())
+        .collect();
+    for format in [OutputFormat::Tsv, OutputFormat::Ndjson] {
+        let name = format!("{:?}", format).to_lowercase();
+        group.bench_function(format!("{}_new", name), |b| {
+            b.iter(|| {
+                for (seq, chat) in chats.iter().enumerate() {
+                    black_box(format.format_chat(chat, Some(seq as u64), &[]));
+                }
+            })
+        });
+        group.bench_function(format!("{}_reused", name), |b| {
+            let mut buffer = String::new();
+            b.iter(|| {
+                for (seq, chat) in chats.iter().enumerate() {
+                    buffer.clear();
+                    format.write_chat(&mut buffer, chat, Some(seq as u64), &[]);
+                    black_box(&buffer);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, deserialize, format);
+criterion_main!(benches);
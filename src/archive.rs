@@ -0,0 +1,94 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use serde::Serialize;
+
+use crate::format::FileMeta;
+
+/// Description of an archived chat log, stored as `manifest.json`.
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    #[serde(flatten)]
+    pub meta: FileMeta,
+    pub file: String,
+    pub messages: u64,
+}
+
+/// Path of the archive for a chat log.
+pub fn archive_path(path: &Path) -> PathBuf {
+    let mut archive = path.as_os_str().to_owned();
+    archive.push(".tar.gz");
+    archive.into()
+}
+
+/// Compress a finished chat log and its manifest into a `.tar.gz` archive
+/// next to it, with the given Unix permission bits, returning the path of the archive.
+pub fn create(path: &Path, manifest: &Manifest, mode: Option<u32>) -> anyhow::Result<PathBuf> {
+    let archive = archive_path(path);
+    let file = crate::utils::create_options(mode)
+        .open(&archive)
+        .with_context(|| format!("Could not create archive {}", archive.display()))?;
+    write(file, path, manifest)
+        .with_context(|| format!("Could not write archive {}", archive.display()))?;
+    Ok(archive)
+}
+
+fn write(file: std::fs::File, path: &Path, manifest: &Manifest) -> std::io::Result<()> {
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    let content = serde_json::to_vec_pretty(manifest)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(chrono::Utc::now().timestamp() as u64);
+    builder.append_data(&mut header, "manifest.json", content.as_slice())?;
+    builder.append_path_with_name(path, &manifest.file)?;
+    builder.into_inner()?.finish()?.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::Manifest;
+    use crate::format::FileMeta;
+
+    #[test]
+    fn create() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chat.tsv");
+        std::fs::write(&path, "1760631669671\tNULL\tYuu\tone\n").unwrap();
+        let manifest = Manifest {
+            meta: FileMeta::new("cytu.be", "test"),
+            file: "chat.tsv".into(),
+            messages: 1,
+        };
+        let archive = super::create(&path, &manifest, None).unwrap();
+        assert_eq!(archive, super::archive_path(&path));
+
+        let file = std::fs::File::open(&archive).unwrap();
+        let mut entries = Vec::new();
+        for entry in tar::Archive::new(GzDecoder::new(file)).entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().display().to_string();
+            let mut content = String::new();
+            entry.read_to_string(&mut content).unwrap();
+            entries.push((name, content));
+        }
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, "manifest.json");
+        let manifest: serde_json::Value = serde_json::from_str(&entries[0].1).unwrap();
+        assert_eq!(manifest["channel"], "test");
+        assert_eq!(manifest["domain"], "cytu.be");
+        assert_eq!(manifest["file"], "chat.tsv");
+        assert_eq!(manifest["messages"], 1);
+        assert_eq!(
+            entries[1],
+            ("chat.tsv".into(), "1760631669671\tNULL\tYuu\tone\n".into())
+        );
+    }
+}
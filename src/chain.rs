@@ -0,0 +1,190 @@
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/// Hash that the first line of a chain links to.
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Running SHA-256 hash chain over the lines of a chat log.
+///
+/// Each line gets the hash of the previous line's hash and its own content:
+/// as a trailing tab-separated field in TSV, as a `hash` field in JSON.
+pub struct HashChain {
+    last: String,
+}
+
+impl HashChain {
+    /// Start a chain, continuing from the given hash if any.
+    pub fn new(seed: Option<String>) -> Self {
+        Self {
+            last: seed.unwrap_or_else(|| GENESIS.to_string()),
+        }
+    }
+
+    /// Add the chained hash to every line of a record.
+    pub fn seal(&mut self, record: &str) -> String {
+        record
+            .split('\n')
+            .map(|line| {
+                self.last = link(&self.last, line);
+                attach(line, &self.last)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Hash of a line linked to the previous hash.
+fn link(previous: &str, line: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(previous.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(line.as_bytes());
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn attach(line: &str, hash: &str) -> String {
+    if let Ok(Value::Object(mut object)) = serde_json::from_str::<Value>(line) {
+        object.insert("hash".into(), Value::from(hash));
+        return Value::Object(object).to_string();
+    }
+    format!("{}\t{}", line, hash)
+}
+
+/// Split a sealed line into its original content and hash.
+fn detach(line: &str) -> Option<(String, String)> {
+    let (content, hash) = match serde_json::from_str::<Value>(line) {
+        Ok(Value::Object(mut object)) => {
+            let hash = match object.remove("hash")? {
+                Value::String(hash) => hash,
+                _ => return None,
+            };
+            (Value::Object(object).to_string(), hash)
+        }
+        _ => {
+            let (content, hash) = line.rsplit_once('\t')?;
+            (content.to_string(), hash.to_string())
+        }
+    };
+    let valid = hash.len() == GENESIS.len() && hash.bytes().all(|b| b.is_ascii_hexdigit());
+    valid.then_some((content, hash))
+}
+
+/// Hash of the last line of a sealed chat log, for continuing its chain.
+pub fn last_hash(content: &str) -> Option<String> {
+    let line = content.lines().rev().find(|line| !line.is_empty())?;
+    detach(line).map(|(_, hash)| hash)
+}
+
+/// First line of a chat log that does not continue the hash chain.
+#[derive(Debug, PartialEq)]
+pub struct BrokenLink {
+    pub line: usize,
+    pub reason: &'static str,
+}
+
+/// Walk the hash chain of a chat log and return the number of verified lines.
+pub fn verify(content: &str) -> Result<usize, BrokenLink> {
+    let mut last = GENESIS.to_string();
+    let mut verified = 0;
+    for (index, line) in content.lines().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        let broken = |reason| BrokenLink {
+            line: index + 1,
+            reason,
+        };
+        let (content, hash) = detach(line).ok_or_else(|| broken("line has no hash"))?;
+        if link(&last, &content) != hash {
+            return Err(broken("hash does not match the line and the previous hash"));
+        }
+        last = hash;
+        verified += 1;
+    }
+    Ok(verified)
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::{BrokenLink, GENESIS, HashChain};
+
+    fn sealed(records: &[&str]) -> String {
+        let mut chain = HashChain::new(None);
+        records
+            .iter()
+            .map(|record| format!("{}\n", chain.seal(record)))
+            .collect()
+    }
+
+    #[test]
+    fn seal_tsv() {
+        let mut chain = HashChain::new(None);
+        let line = chain.seal("1760634672025\twg\tPotF\thello");
+        let (content, hash) = line.rsplit_once('\t').unwrap();
+        assert_eq!(content, "1760634672025\twg\tPotF\thello");
+        assert_eq!(hash.len(), 64);
+        assert_ne!(hash, GENESIS);
+    }
+
+    #[test]
+    fn seal_json() {
+        let mut chain = HashChain::new(None);
+        let line = chain.seal("{\"text\":\"hello\",\"time\":1}");
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["text"], "hello");
+        assert_eq!(value["hash"].as_str().unwrap().len(), 64);
+    }
+
+    #[test_case(&["# cupcake 0.3.0\n# channel: test", "1\twg\tPotF\thi\tthere"], 3; "tsv")]
+    #[test_case(&["{\"_meta\":{}}", "{\"text\":\"hi\",\"time\":1}"], 2; "ndjson")]
+    fn verify(records: &[&str], lines: usize) {
+        assert_eq!(super::verify(&sealed(records)), Ok(lines));
+    }
+
+    #[test]
+    fn verify_tampered() {
+        let log = sealed(&["1\t\tPotF\tone", "2\t\tPotF\ttwo", "3\t\tPotF\tthree"]);
+        let tampered = log.replacen("two", "2", 1);
+        assert_eq!(
+            super::verify(&tampered),
+            Err(BrokenLink {
+                line: 2,
+                reason: "hash does not match the line and the previous hash"
+            })
+        );
+    }
+
+    #[test]
+    fn verify_removed_line() {
+        let log = sealed(&["1\t\tPotF\tone", "2\t\tPotF\ttwo", "3\t\tPotF\tthree"]);
+        let mut lines: Vec<&str> = log.lines().collect();
+        lines.remove(1);
+        assert_eq!(super::verify(&lines.join("\n")).unwrap_err().line, 2);
+    }
+
+    #[test]
+    fn verify_unsealed_line() {
+        let log = sealed(&["1\t\tPotF\tone"]) + "2\t\tPotF\ttwo\n";
+        assert_eq!(
+            super::verify(&log),
+            Err(BrokenLink {
+                line: 2,
+                reason: "line has no hash"
+            })
+        );
+    }
+
+    #[test]
+    fn continue_from_last_hash() {
+        let first = sealed(&["1\t\tPotF\tone"]);
+        let mut chain = HashChain::new(super::last_hash(&first));
+        let log = format!("{}{}\n", first, chain.seal("2\t\tPotF\ttwo"));
+        assert_eq!(super::verify(&log), Ok(2));
+    }
+}
@@ -2,6 +2,9 @@ use crate::Event;
 
 const MESSAGE_BUFFER_SIZE: usize = 64;
 
+/// Buffer utilization percentage at which the backlog is reported.
+const BUFFER_REPORT_THRESHOLD: usize = 75;
+
 #[cfg(feature = "tokio_channels")]
 pub type EventTx = tokio::sync::mpsc::Sender<Event>;
 #[cfg(feature = "tokio_channels")]
@@ -39,12 +42,104 @@ pub async fn read_event(rx: &mut EventRx) -> Option<Event> {
 pub async fn read_event(rx: &mut EventRx) -> Option<Event> {
     rx.recv().await
 }
+
+/// Take the events currently waiting in the channel buffer without waiting
+/// for new ones, so that events sent before shutdown are not lost.
+pub fn drain_buffered(rx: &mut EventRx) -> Vec<Event> {
+    let mut events = Vec::with_capacity(buffer_depth(rx));
+    while events.len() < events.capacity()
+        && let Ok(event) = rx.try_recv()
+    {
+        events.push(event);
+    }
+    events
+}
+
+/// Number of events waiting in the channel buffer.
+pub fn buffer_depth(rx: &EventRx) -> usize {
+    rx.len()
+}
+
+/// Buffered event count and capacity if the buffer is filled past the
+/// report threshold, meaning that senders are close to being blocked.
+pub fn buffer_backlog(rx: &EventRx) -> Option<(usize, usize)> {
+    let depth = buffer_depth(rx);
+    match depth * 100 >= MESSAGE_BUFFER_SIZE * BUFFER_REPORT_THRESHOLD {
+        true => Some((depth, MESSAGE_BUFFER_SIZE)),
+        false => None,
+    }
+}
+
+#[cfg(feature = "broadcast")]
+pub type BroadcastTx = tokio::sync::broadcast::Sender<Event>;
+#[cfg(feature = "broadcast")]
+pub type BroadcastRx = tokio::sync::broadcast::Receiver<Event>;
+
+/// Create a channel that delivers every event to all of its receivers.
+///
+/// Additional receivers are created with `BroadcastTx::subscribe`.
+#[cfg(feature = "broadcast")]
+pub fn broadcast_channel() -> (BroadcastTx, BroadcastRx) {
+    log::debug!(
+        "Creating tokio broadcast channel with buffer size {}",
+        MESSAGE_BUFFER_SIZE
+    );
+    tokio::sync::broadcast::channel(MESSAGE_BUFFER_SIZE)
+}
+
+/// Read the next event from a broadcast receiver. Events dropped because
+/// the receiver fell behind are logged and skipped.
+#[cfg(feature = "broadcast")]
+pub async fn read_broadcast_event(rx: &mut BroadcastRx) -> Option<Event> {
+    use tokio::sync::broadcast::error::RecvError;
+    loop {
+        match rx.recv().await {
+            Ok(event) => return Some(event),
+            Err(RecvError::Lagged(count)) => {
+                log::warn!("Receiver lagged behind, dropped {} events", count);
+            }
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+/// Relay the events of a channel through a broadcast channel. Returns a
+/// receiver that is fed from one subscription, keeping the mpsc API for
+/// the manager task, and a second subscription for another consumer.
+#[cfg(feature = "broadcast")]
+pub fn fan_out(mut rx: EventRx) -> (EventRx, BroadcastRx) {
+    let (broadcast_tx, mut writer_rx) = broadcast_channel();
+    let consumer_rx = broadcast_tx.subscribe();
+    let (writer_tx, manager_rx) = mpsc_channel();
+    tokio::spawn(async move {
+        while let Some(event) = read_event(&mut rx).await {
+            // Sending only fails once every receiver is gone.
+            if broadcast_tx.send(event).is_err() {
+                break;
+            }
+        }
+    });
+    tokio::spawn(async move {
+        while let Some(event) = read_broadcast_event(&mut writer_rx).await {
+            if writer_tx.send(event).await.is_err() {
+                break;
+            }
+        }
+    });
+    (manager_rx, consumer_rx)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{Event, mpsc_channel, read_event};
+    use super::{
+        Event, MESSAGE_BUFFER_SIZE, buffer_backlog, buffer_depth, drain_buffered, mpsc_channel,
+        read_event,
+    };
+    use crate::summary::Summary;
     use serde_json::json;
 
     #[tokio::test]
+    #[allow(clippy::needless_return)]
     async fn channel() {
         let (tx, mut rx) = mpsc_channel();
 
@@ -64,7 +159,7 @@ mod tests {
         tx.send(Event::Login(vec![json!("{}")]))
             .await
             .expect("Failed to send event");
-        tx.send(Event::Chat(vec![json!("{\"time\": 123456789}")]))
+        tx.send(Event::Chat(vec![json!("{\"time\": 123456789}")], 0))
             .await
             .expect("Failed to send event");
         tx.send(Event::Terminate)
@@ -73,6 +168,173 @@ mod tests {
 
         let results = manager.await.unwrap();
         assert!(matches!(results[0], Event::Login(_)));
-        assert!(matches!(results[1], Event::Chat(_)));
+        assert!(matches!(results[1], Event::Chat(..)));
+    }
+
+    #[tokio::test]
+    async fn drain_after_terminate() {
+        let (tx, mut rx) = mpsc_channel();
+
+        // Everything is buffered before the manager starts, including events
+        // that the socket handlers send after the termination signal.
+        tx.send(Event::Login(vec![json!("{}")]))
+            .await
+            .expect("Failed to send event");
+        tx.send(Event::Chat(vec![json!("{\"time\": 123456789}")], 0))
+            .await
+            .expect("Failed to send event");
+        tx.send(Event::Terminate)
+            .await
+            .expect("Failed to send event");
+        tx.send(Event::UserCount(3))
+            .await
+            .expect("Failed to send event");
+        tx.send(Event::Chat(vec![json!("{\"time\": 123456790}")], 0))
+            .await
+            .expect("Failed to send event");
+
+        let manager = tokio::spawn(async move {
+            let mut results: Vec<Event> = Vec::new();
+            let mut drained: Option<std::vec::IntoIter<Event>> = None;
+            loop {
+                let event = match &mut drained {
+                    Some(events) => events.next(),
+                    None => read_event(&mut rx).await,
+                };
+                match event {
+                    Some(Event::Terminate) => drained = Some(drain_buffered(&mut rx).into_iter()),
+                    Some(event) => results.push(event),
+                    None => break,
+                }
+            }
+            results
+        });
+
+        let results = manager.await.unwrap();
+        assert_eq!(results.len(), 4);
+        assert!(matches!(results[0], Event::Login(_)));
+        assert!(matches!(results[1], Event::Chat(..)));
+        assert!(matches!(results[2], Event::UserCount(3)));
+        assert!(matches!(results[3], Event::Chat(..)));
+        drop(tx);
+    }
+
+    #[tokio::test]
+    async fn stop_at_message_limit() {
+        let (tx, mut rx) = mpsc_channel();
+        for time in 0..5 {
+            tx.send(Event::Chat(vec![json!({"time": time})], 0))
+                .await
+                .expect("Failed to send event");
+        }
+
+        let manager = tokio::spawn(async move {
+            let mut summary = Summary::default();
+            while let Some(event) = read_event(&mut rx).await {
+                if let Event::Chat(..) = event {
+                    summary.messages += 1;
+                }
+                if summary.limit_reached(Some(3)) {
+                    break;
+                }
+            }
+            (summary.messages, buffer_depth(&rx))
+        });
+
+        assert_eq!(manager.await.unwrap(), (3, 2));
+        drop(tx);
+    }
+
+    #[tokio::test]
+    async fn backlog() {
+        let (tx, mut rx) = mpsc_channel();
+        assert_eq!(buffer_backlog(&rx), None);
+
+        for count in 0..48 {
+            tx.send(Event::UserCount(count))
+                .await
+                .expect("Failed to send event");
+        }
+        assert_eq!(buffer_backlog(&rx), Some((48, MESSAGE_BUFFER_SIZE)));
+
+        read_event(&mut rx).await;
+        assert_eq!(buffer_backlog(&rx), None);
+    }
+    #[cfg(feature = "broadcast")]
+    #[tokio::test]
+    async fn broadcast() {
+        use super::{broadcast_channel, read_broadcast_event};
+
+        let (tx, mut rx1) = broadcast_channel();
+        let mut rx2 = tx.subscribe();
+
+        tx.send(Event::Login(vec![json!("{}")]))
+            .expect("Failed to send event");
+        tx.send(Event::UserCount(3)).expect("Failed to send event");
+        drop(tx);
+
+        for rx in [&mut rx1, &mut rx2] {
+            assert!(matches!(
+                read_broadcast_event(rx).await,
+                Some(Event::Login(_))
+            ));
+            assert!(matches!(
+                read_broadcast_event(rx).await,
+                Some(Event::UserCount(3))
+            ));
+            assert!(read_broadcast_event(rx).await.is_none());
+        }
+    }
+
+    #[cfg(feature = "broadcast")]
+    #[tokio::test]
+    async fn broadcast_lagged() {
+        use super::{broadcast_channel, read_broadcast_event};
+
+        let (tx, mut rx) = broadcast_channel();
+        for count in 0..MESSAGE_BUFFER_SIZE as u32 + 1 {
+            tx.send(Event::UserCount(count))
+                .expect("Failed to send event");
+        }
+        assert!(matches!(
+            read_broadcast_event(&mut rx).await,
+            Some(Event::UserCount(1))
+        ));
+    }
+
+    #[cfg(feature = "broadcast")]
+    #[tokio::test]
+    async fn fan_out() {
+        use super::{fan_out, read_broadcast_event};
+
+        let (tx, rx) = mpsc_channel();
+        let (mut manager_rx, mut consumer_rx) = fan_out(rx);
+
+        tx.send(Event::Login(vec![json!("{}")]))
+            .await
+            .expect("Failed to send event");
+        tx.send(Event::UserCount(3))
+            .await
+            .expect("Failed to send event");
+        drop(tx);
+
+        assert!(matches!(
+            read_event(&mut manager_rx).await,
+            Some(Event::Login(_))
+        ));
+        assert!(matches!(
+            read_event(&mut manager_rx).await,
+            Some(Event::UserCount(3))
+        ));
+        assert!(read_event(&mut manager_rx).await.is_none());
+        assert!(matches!(
+            read_broadcast_event(&mut consumer_rx).await,
+            Some(Event::Login(_))
+        ));
+        assert!(matches!(
+            read_broadcast_event(&mut consumer_rx).await,
+            Some(Event::UserCount(3))
+        ));
+        assert!(read_broadcast_event(&mut consumer_rx).await.is_none());
     }
 }
@@ -0,0 +1,176 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::Command;
+use serde::Deserialize;
+
+/// Options read from a TOML config file given with `--config`.
+///
+/// The values are used as argument defaults, so flags given on the
+/// command line take precedence over the file.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub domain: Option<String>,
+    pub channel: Option<String>,
+    pub guest_login: Option<String>,
+    pub log_level: Option<String>,
+    pub format: Option<String>,
+    pub skip_class: Option<Vec<String>>,
+    pub apply_filters: Option<bool>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read config file {}", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Could not parse config file {}", path.display()))
+    }
+
+    /// Use the config values as defaults for the command's arguments.
+    pub fn apply(self, mut command: Command) -> Command {
+        let values = [
+            ("domain", self.domain),
+            ("channel", self.channel),
+            ("guest_login", self.guest_login),
+            ("log_level", self.log_level),
+            ("format", self.format),
+            ("apply_filters", self.apply_filters.map(|v| v.to_string())),
+        ];
+        for (id, value) in values {
+            if let Some(value) = value {
+                command = command.mut_arg(id, |arg| arg.default_value(value).required(false));
+            }
+        }
+        if let Some(classes) = self.skip_class {
+            command = command.mut_arg("skip_class", |arg| arg.default_values(classes));
+        }
+        command
+    }
+}
+
+/// Find the `--config` path from the raw command line arguments, which
+/// need to be known before the arguments can be parsed.
+pub fn find_path<I: IntoIterator<Item = OsString>>(args: I) -> Option<PathBuf> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsString;
+    use std::path::PathBuf;
+
+    use clap::{CommandFactory, FromArgMatches, Parser};
+    use test_case::test_case;
+
+    use super::Config;
+
+    #[derive(Debug, Parser)]
+    struct TestArgs {
+        domain: String,
+        channel: String,
+        #[clap(long)]
+        guest_login: Option<String>,
+        #[clap(long, default_value_t = log::LevelFilter::Info)]
+        log_level: log::LevelFilter,
+        #[clap(long, default_value = "tsv")]
+        format: String,
+        #[clap(long, default_value = "server-whisper")]
+        skip_class: Vec<String>,
+        #[clap(long)]
+        apply_filters: bool,
+    }
+
+    fn parse(config: Config, args: &[&str]) -> TestArgs {
+        let command = config.apply(TestArgs::command());
+        let matches = command
+            .try_get_matches_from(std::iter::once("cupcake").chain(args.iter().copied()))
+            .unwrap();
+        TestArgs::from_arg_matches(&matches).unwrap()
+    }
+
+    fn config() -> Config {
+        toml::from_str(
+            r#"
+            domain = "cytu.be"
+            channel = "test"
+            guest_login = "cupcake"
+            log_level = "debug"
+            skip_class = ["server-whisper", "shout"]
+            apply_filters = true
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn apply_file_values() {
+        let args = parse(config(), &[]);
+        assert_eq!(args.domain, "cytu.be");
+        assert_eq!(args.channel, "test");
+        assert_eq!(args.guest_login.as_deref(), Some("cupcake"));
+        assert_eq!(args.log_level, log::LevelFilter::Debug);
+        assert_eq!(args.format, "tsv");
+        assert_eq!(args.skip_class, vec!["server-whisper", "shout"]);
+        assert!(args.apply_filters);
+    }
+
+    #[test]
+    fn apply_cli_overrides() {
+        let args = parse(
+            config(),
+            &[
+                "example.com",
+                "other",
+                "--guest-login",
+                "muffin",
+                "--skip-class",
+                "action",
+            ],
+        );
+        assert_eq!(args.domain, "example.com");
+        assert_eq!(args.channel, "other");
+        assert_eq!(args.guest_login.as_deref(), Some("muffin"));
+        assert_eq!(args.log_level, log::LevelFilter::Debug);
+        assert_eq!(args.skip_class, vec!["action"]);
+    }
+
+    #[test]
+    fn unknown_field() {
+        assert!(toml::from_str::<Config>("chanel = \"test\"").is_err());
+    }
+
+    #[test_case(&["cupcake", "--config", "cupcake.toml"], Some("cupcake.toml"); "separate value")]
+    #[test_case(&["cupcake", "--config=cupcake.toml"], Some("cupcake.toml"); "equals sign")]
+    #[test_case(&["cupcake", "cytu.be", "test"], None; "missing")]
+    fn find_path(args: &[&str], expected: Option<&str>) {
+        let args = args.iter().map(OsString::from);
+        assert_eq!(super::find_path(args), expected.map(PathBuf::from));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn find_path_non_utf8() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let path = OsString::from_vec(b"caf\xe9.toml".to_vec());
+        let args = [
+            OsString::from("cupcake"),
+            OsString::from_vec(b"--output=\xff".to_vec()),
+            OsString::from("--config"),
+            path.clone(),
+        ];
+        assert_eq!(super::find_path(args), Some(PathBuf::from(path)));
+    }
+}
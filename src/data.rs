@@ -1,6 +1,92 @@
+use std::borrow::Cow;
 use std::fmt::Display;
+use std::sync::LazyLock;
 
-use serde::{Deserialize, Deserializer, de};
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Value, json};
+
+/// Template of `ChatMessage::short_format`.
+const DEFAULT_TEMPLATE: &str = "<{username}> {text}";
+
+/// Template of `ChatMessage::short_format` for `/me` actions.
+const ACTION_TEMPLATE: &str = "* {username} {text}";
+
+/// Escape control characters such as newlines and tabs so that a single
+/// value cannot break a line or a TSV column. Backslashes are escaped too, so
+/// that the escaping can be reversed.
+pub fn escape_control(value: &str) -> Cow<'_, str> {
+    if !value.chars().any(|c| c == '\\' || c.is_control()) {
+        return Cow::Borrowed(value);
+    }
+    let mut escaped = String::with_capacity(value.len() + 8);
+    for c in value.chars() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            '\0' => escaped.push_str("\\0"),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Decode the HTML entities that Cytube uses when escaping message text.
+fn decode_entities(text: &str) -> Cow<'_, str> {
+    if !text.contains('&') {
+        return Cow::Borrowed(text);
+    }
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest.find(';').map(|end| (&rest[1..end], end));
+        let character = entity.and_then(|(name, _)| match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => match name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                None => name
+                    .strip_prefix('#')
+                    .and_then(|decimal| decimal.parse().ok())
+                    .and_then(char::from_u32),
+            },
+        });
+        match (character, entity) {
+            (Some(character), Some((_, end))) => {
+                decoded.push(character);
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    Cow::Owned(decoded)
+}
+
+static LINK_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"<a\s[^>]*?href="([^"]*)""#).unwrap());
+
+/// URL has one of the given hosts, ignoring case.
+fn has_host(link: &str, hosts: &[String]) -> bool {
+    url::Url::parse(link)
+        .ok()
+        .and_then(|url| {
+            url.host_str()
+                .map(|host| hosts.iter().any(|h| h.eq_ignore_ascii_case(host)))
+        })
+        .unwrap_or(false)
+}
 
 #[derive(Debug, Deserialize, PartialEq)]
 pub struct ChatMessage {
@@ -12,43 +98,722 @@ pub struct ChatMessage {
 }
 
 impl ChatMessage {
+    /// Parse a `chatMsg` payload, either as sent by Cytube or as a record
+    /// written by [`ChatMessage::to_json`], e.g. from a dump file.
+    pub fn from_value(value: Value) -> serde_json::Result<Self> {
+        match value.get("msg") {
+            Some(_) => serde_json::from_value(value),
+            None => serde_json::from_value::<ChatRecord>(value).map(Self::from),
+        }
+    }
+
+    /// Decoded message text.
+    pub fn text(&self) -> &str {
+        &self.msg.text
+    }
+
+    /// Replace the decoded message text.
+    pub fn map_text<F: FnOnce(&str) -> String>(&mut self, f: F) {
+        self.msg.text = f(&self.msg.text);
+    }
+
+    /// Name of the user's team, if one is set.
+    pub fn team_name(&self) -> Option<&str> {
+        match &self.msg.team {
+            Team::Empty => None,
+            Team::Named(name) => Some(name),
+        }
+    }
+
+    /// JSON representation of the message for ndjson logs. Includes the
+    /// timestamp as a UTC ISO-8601 `datetime` next to the raw `time`, and
+    /// the message metadata so that the record can be parsed back.
+    pub fn to_json(&self) -> Value {
+        let datetime = chrono::DateTime::from_timestamp_millis(self.time as i64)
+            .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Millis, true));
+        json!({
+            "time": self.time,
+            "datetime": datetime,
+            "team": self.msg.team,
+            "username": self.username,
+            "rank": self.rank().map(|rank| rank.to_string()),
+            "username_color": self.rank().and_then(Rank::name_color),
+            "text": self.msg.text,
+            "type": match self.is_action() {
+                true => "action",
+                false => "message",
+            },
+            "edited": self.is_edited(),
+            "edit_time": self.meta.edit_time,
+            "meta": self.meta,
+        })
+    }
+
+    /// JSON representation of the message with a `links` field listing the
+    /// links to any of the given hosts, unless no hosts are given.
+    pub fn to_json_with_links(&self, link_hosts: &[String]) -> Value {
+        let mut value = self.to_json();
+        if !link_hosts.is_empty() {
+            value["links"] = json!(self.links_to(link_hosts));
+        }
+        value
+    }
+
+    /// Targets of the links in the message, with HTML entities decoded.
+    pub fn links(&self) -> Vec<String> {
+        LINK_PATTERN
+            .captures_iter(&self.msg.text)
+            .map(|captures| decode_entities(&captures[1]).into_owned())
+            .collect()
+    }
+
+    /// Targets of the links to any of the given hosts. Hosts are compared
+    /// exactly, ignoring case.
+    pub fn links_to(&self, hosts: &[String]) -> Vec<String> {
+        self.links()
+            .into_iter()
+            .filter(|link| has_host(link, hosts))
+            .collect()
+    }
+
     /// Short format of the message for logging purposes.
     pub fn short_format(&self) -> String {
-        format!("<{}> {}", self.username, self.msg.text)
+        match self.is_action() {
+            true => self.format_with(ACTION_TEMPLATE),
+            false => self.format_with(DEFAULT_TEMPLATE),
+        }
     }
 
-    /// Message is a server whisper and should not be logged.
-    pub fn should_be_skipped(&self) -> bool {
-        if let Some(add_class) = &self.meta.add_class {
-            return add_class == "server-whisper";
+    /// Format the message with a template containing `{time}`, `{username}`,
+    /// `{text}` and `{team}` placeholders. Unknown placeholders are kept as is.
+    pub fn format_with(&self, template: &str) -> String {
+        let mut output = String::with_capacity(template.len() + self.msg.text.len());
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            output.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let Some(end) = rest.find('}') else {
+                break;
+            };
+            match &rest[1..end] {
+                "time" => output.push_str(&self.time.to_string()),
+                "username" => output.push_str(&escape_control(&self.username)),
+                "text" => output.push_str(&escape_control(&self.msg.text)),
+                "team" => output.push_str(&escape_control(self.msg.team.as_str())),
+                _ => output.push_str(&rest[..=end]),
+            }
+            rest = &rest[end + 1..];
         }
-        false
+        output.push_str(rest);
+        output
+    }
+
+    /// Message has one of the given classes and should not be logged.
+    pub fn should_be_skipped(&self, skip_classes: &[String]) -> bool {
+        skip_classes.iter().any(|class| self.meta.has_class(class))
+    }
+
+    /// Message text starts with the given chat bot command prefix, such as `!`.
+    /// The text is checked as plain text, as Cytube escapes characters like `>`.
+    pub fn is_command(&self, prefix: &str) -> bool {
+        !prefix.is_empty() && plain_text(&self.msg.text).starts_with(prefix)
+    }
+
+    /// Message was sent by one of the given users, ignoring case like Cytube.
+    pub fn is_from_any(&self, names: &[String]) -> bool {
+        names
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(&self.username))
+    }
+
+    /// Message is a server whisper, e.g. a voteskip tally.
+    pub fn is_server_whisper(&self) -> bool {
+        self.meta.has_class("server-whisper")
+    }
+
+    /// Message is a `/me` action.
+    pub fn is_action(&self) -> bool {
+        self.meta.has_class("action")
+    }
+
+    /// Message is a `/sh` shout, highlighted for everyone in the channel.
+    pub fn is_shout(&self) -> bool {
+        self.meta.has_class("shout") && self.meta.add_class_to_name_and_timestamp
+    }
+
+    /// Rank of the sender, if known.
+    pub fn rank(&self) -> Option<Rank> {
+        self.meta.rank
+    }
+
+    /// Set the rank of the sender, e.g. from the tracked channel user list.
+    pub fn set_rank(&mut self, rank: Rank) {
+        self.meta.rank = Some(rank);
+    }
+
+    /// Message was sent by a site-wide administrator.
+    pub fn is_from_superadmin(&self) -> bool {
+        self.meta.superadmin_flair.is_some()
+    }
+
+    /// Message was edited after sending, as flagged by a relay or bridge.
+    pub fn is_edited(&self) -> bool {
+        self.meta.edited || self.meta.edit_time.is_some()
     }
 }
 
+/// TSV line of the message. `/me` actions keep their `/me ` prefix, which
+/// other messages cannot start with, as Cytube turns them into actions.
 impl Display for ChatMessage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "{}\t{}\t{}\t{}",
-            self.time, self.msg.team, self.username, self.msg.text
+            "{}\t{}\t{}\t{}{}",
+            self.time,
+            escape_control(self.msg.team.as_str()),
+            escape_control(&self.username),
+            if self.is_action() { "/me " } else { "" },
+            escape_control(&self.msg.text)
         )
     }
 }
 
+/// Chat message record of the ndjson logs. Only the fields needed to
+/// rebuild the [`ChatMessage`] are read; the derived ones are ignored.
+#[derive(Debug, Deserialize)]
+struct ChatRecord {
+    time: u64,
+    username: String,
+    text: String,
+    team: Team,
+    meta: ChatMeta,
+}
+
+impl From<ChatRecord> for ChatMessage {
+    fn from(record: ChatRecord) -> Self {
+        Self {
+            time: record.time,
+            username: record.username,
+            msg: MessageContainer {
+                text: record.text,
+                team: record.team,
+            },
+            meta: record.meta,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, PartialEq)]
+pub struct ChatFilter {
+    pub name: String,
+    pub source: String,
+    #[serde(default)]
+    pub flags: String,
+    pub replace: String,
+    pub active: bool,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct Emote {
+    pub name: String,
+    pub image: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ChatMeta {
+    #[serde(skip_serializing_if = "Option::is_none")]
     add_class: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    add_class_to_name_and_timestamp: bool,
+    /// Flair of site administrators, kept as sent as only its presence is used.
+    #[serde(rename = "superadminflair", skip_serializing_if = "Option::is_none")]
+    superadmin_flair: Option<Value>,
+    /// Rank of the sender, included if they show their moderator flair.
+    #[serde(rename = "modflair", skip_serializing_if = "Option::is_none")]
+    rank: Option<Rank>,
+    /// Set on relayed or bridged messages that were edited at the source.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    edited: bool,
+    /// Time of the last edit in milliseconds, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    edit_time: Option<u64>,
+}
+
+impl ChatMeta {
+    fn has_class(&self, class: &str) -> bool {
+        self.add_class.as_deref() == Some(class)
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct ErrorMsg {
+    pub msg: String,
+}
+
+/// Structured payload of a Socket.IO `error` event.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct ErrorPayload {
+    pub message: String,
+    #[serde(default)]
+    pub code: Option<String>,
+}
+
+/// Cause of a socket error, classified from its code or message.
+#[derive(Debug, PartialEq)]
+pub enum ErrorKind {
+    /// Banned from the channel or the server.
+    Banned,
+    /// The channel does not exist or cannot be joined.
+    InvalidChannel,
+    /// Any other error, which does not end the capture.
+    Other,
+}
+
+impl ErrorPayload {
+    pub fn kind(&self) -> ErrorKind {
+        let text = format!(
+            "{} {}",
+            self.code.as_deref().unwrap_or_default(),
+            self.message
+        )
+        .to_lowercase();
+        if text.contains("banned") {
+            return ErrorKind::Banned;
+        }
+        if text.contains("channel")
+            && ["invalid", "not found", "does not exist"]
+                .iter()
+                .any(|reason| text.contains(reason))
+        {
+            return ErrorKind::InvalidChannel;
+        }
+        ErrorKind::Other
+    }
+}
+
+/// Site-wide announcement broadcast by the server administrators.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct Announcement {
+    pub title: String,
+    #[serde(deserialize_with = "deserialize_html_text")]
+    pub text: String,
+    #[serde(default)]
+    pub from: Option<String>,
+}
+
+fn deserialize_html_text<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let html: String = Deserialize::deserialize(deserializer)?;
+    Ok(html_to_text(&html))
+}
+
+fn deserialize_poll_options<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let html: Vec<String> = Deserialize::deserialize(deserializer)?;
+    Ok(html.iter().map(|html| html_to_text(html)).collect())
+}
+
+/// Channel poll from a `newPoll` or `updatePoll` event.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct Poll {
+    #[serde(deserialize_with = "deserialize_html_text")]
+    pub title: String,
+    #[serde(deserialize_with = "deserialize_poll_options")]
+    pub options: Vec<String>,
+    #[serde(default)]
+    pub counts: Vec<PollCount>,
+    pub initiator: String,
+}
+
+/// Votes for a poll option, sent as `?` while the results of a poll are hidden.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum PollCount {
+    Votes(u64),
+    Hidden(String),
+}
+
+impl Display for PollCount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PollCount::Votes(votes) => write!(f, "{}", votes),
+            PollCount::Hidden(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+impl Display for Poll {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} by {}:", self.title, self.initiator)?;
+        for (i, option) in self.options.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            match self.counts.get(i) {
+                Some(count) => write!(f, "{}{} ({})", separator, option, count)?,
+                None => write!(f, "{}{}", separator, option)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Plain text of an HTML fragment on a single line, such as a poll option or
+/// an announcement, with entities decoded, line breaks and block elements
+/// turned into spaces and whitespace collapsed.
+pub fn html_to_text(html: &str) -> String {
+    plain_text(html)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Plain text of an HTML fragment with entities decoded, keeping whitespace.
+fn plain_text(html: &str) -> Cow<'_, str> {
+    if !html.contains('<') {
+        return decode_entities(html);
+    }
+    match html_parser::Dom::parse(html) {
+        Ok(dom) => {
+            let mut text = String::new();
+            append_nodes(html, dom.children, Markup::Plain, &mut text);
+            Cow::Owned(text)
+        }
+        Err(e) => {
+            log::debug!("Could not parse HTML, using raw text: {}", e);
+            decode_entities(html)
+        }
+    }
+}
+
+/// Reference to a chat message deleted by a moderator.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct ChatDeletion {
+    pub time: u64,
+    pub username: String,
+    #[serde(default)]
+    pub msg: Option<String>,
+}
+
+impl ChatDeletion {
+    /// Identify the deleted message by its timestamp and sender, and a hash
+    /// of its text as written to the chat log if included, so that it can be
+    /// matched to the chat log.
+    pub fn reference(&self) -> String {
+        match &self.msg {
+            Some(msg) => {
+                let text = MessageContainer::parse(msg).text;
+                format!("{}\t{}\t{:016x}", self.time, self.username, fnv1a(&text))
+            }
+            None => format!("{}\t{}", self.time, self.username),
+        }
+    }
+}
+
+/// 64-bit FNV-1a hash, which unlike the standard library hasher is stable
+/// across releases.
+fn fnv1a(text: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in text.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct Kick {
+    pub reason: String,
+}
+
+/// Cytube user rank, mapped from the numeric rank used by the server.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(from = "i64", into = "i64")]
+pub enum Rank {
+    Guest,
+    User,
+    Moderator,
+    Admin,
+    Owner,
+    Unknown(i64),
+}
+
+impl From<i64> for Rank {
+    fn from(rank: i64) -> Self {
+        match rank {
+            0 => Rank::Guest,
+            1 => Rank::User,
+            2 => Rank::Moderator,
+            3 => Rank::Admin,
+            4 => Rank::Owner,
+            other => Rank::Unknown(other),
+        }
+    }
+}
+
+impl From<Rank> for i64 {
+    fn from(rank: Rank) -> Self {
+        match rank {
+            Rank::Guest => 0,
+            Rank::User => 1,
+            Rank::Moderator => 2,
+            Rank::Admin => 3,
+            Rank::Owner => 4,
+            Rank::Unknown(other) => other,
+        }
+    }
+}
+
+impl Rank {
+    /// CSS class that Cytube uses to color the names of users with this rank.
+    pub fn name_color(self) -> Option<&'static str> {
+        match self {
+            Rank::Guest => Some("userlist_guest"),
+            Rank::User => None,
+            Rank::Moderator | Rank::Admin => Some("userlist_op"),
+            Rank::Owner => Some("userlist_owner"),
+            Rank::Unknown(rank) if rank >= 255 => Some("userlist_siteadmin"),
+            Rank::Unknown(rank) if rank >= 4 => Some("userlist_owner"),
+            Rank::Unknown(_) => None,
+        }
+    }
+}
+
+impl Display for Rank {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rank::Guest => write!(f, "guest"),
+            Rank::User => write!(f, "user"),
+            Rank::Moderator => write!(f, "moderator"),
+            Rank::Admin => write!(f, "admin"),
+            Rank::Owner => write!(f, "owner"),
+            Rank::Unknown(rank) => write!(f, "rank {}", rank),
+        }
+    }
+}
+
+/// Channel user from `userlist`, `addUser` and `setUserRank` events.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct User {
+    pub name: String,
+    pub rank: Rank,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct UserLeave {
+    pub name: String,
+}
+
+/// Private message from a `pm` event, sent to or by the logged-in user.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct Whisper {
+    #[serde(rename(deserialize = "username"))]
+    pub from: String,
+    #[serde(skip_serializing)]
+    pub to: String,
+    pub time: u64,
+    #[serde(
+        rename(deserialize = "msg"),
+        deserialize_with = "deserialize_html_text"
+    )]
+    pub text: String,
+}
+
+/// Changed AFK state of a user from a `setAFK` event.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct AfkState {
+    pub name: String,
+    pub afk: bool,
+}
+
+/// Changed user state from a `setUserMeta` event.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct UserMeta {
+    pub name: String,
+    pub meta: UserMetaFlags,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+pub struct UserMetaFlags {
+    #[serde(default)]
+    pub afk: bool,
+    #[serde(default)]
+    pub muted: bool,
+    /// User is shadow-muted: their messages are only shown to moderators and themselves.
+    #[serde(default)]
+    pub smuted: bool,
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
 pub struct Login {
     pub error: Option<String>,
+    #[serde(default)]
+    pub guest: bool,
     pub name: Option<String>,
     pub success: bool,
 }
 
+impl Login {
+    /// Session after the login, or None if the login failed.
+    pub fn session(&self) -> Option<Session> {
+        match (self.success, self.guest) {
+            (false, _) => None,
+            (true, true) => Some(Session::Guest),
+            (true, false) => Some(Session::Registered),
+        }
+    }
+}
+
+/// Login state of the connection.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Session {
+    #[default]
+    Anonymous,
+    Guest,
+    Registered,
+}
+
+impl Session {
+    /// A registered session was replaced by a guest one, e.g. because the
+    /// server-side session expired.
+    pub fn is_downgraded_to(self, next: Session) -> bool {
+        self == Session::Registered && next == Session::Guest
+    }
+}
+
+/// Guest login failed because the name is registered or already used in
+/// the channel, so another name may succeed.
+pub fn is_name_taken(error: &str) -> bool {
+    let error = error.to_lowercase();
+    error.contains("is registered") || error.contains("already in use")
+}
+
+/// Server-side rate limiting notice from a `cooldown` or `spamFiltered` event.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct RateLimit {
+    pub reason: String,
+    /// Chat cooldown in milliseconds.
+    #[serde(default)]
+    pub cooldown: Option<u64>,
+}
+
+impl RateLimit {
+    /// Chat cooldown given as a bare number of milliseconds in `cooldown` events.
+    pub fn from_cooldown(cooldown: u64) -> Self {
+        Self {
+            reason: "COOLDOWN".into(),
+            cooldown: Some(cooldown),
+        }
+    }
+}
+
+impl Display for RateLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.cooldown {
+            Some(cooldown) => write!(f, "{} ({} ms)", self.reason, cooldown),
+            None => write!(f, "{}", self.reason),
+        }
+    }
+}
+
+/// Voteskip tally parsed from a `[voteskip]` server whisper.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Voteskip {
+    pub time: u64,
+    pub skipped: u32,
+    pub eligible: u32,
+    pub ratio: f64,
+}
+
+static VOTESKIP_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^Voteskip \w+: (\d+)/\d+ skipped; eligible voters: (\d+) .*; ratio = ([\d.]+)$")
+        .unwrap()
+});
+
+impl Voteskip {
+    /// Parse the voteskip tally from a chat message. Returns `None` for
+    /// messages that are not voteskip server whispers.
+    pub fn from_chat(chat: &ChatMessage) -> Option<Self> {
+        if chat.username != "[voteskip]" || !chat.is_server_whisper() {
+            return None;
+        }
+        let captures = VOTESKIP_PATTERN.captures(chat.text())?;
+        Some(Self {
+            time: chat.time,
+            skipped: captures[1].parse().ok()?,
+            eligible: captures[2].parse().ok()?,
+            ratio: captures[3].parse().ok()?,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct Media {
+    pub id: String,
+    pub title: String,
+    #[serde(rename = "type")]
+    pub media_type: String,
+    pub seconds: f64,
+}
+
+/// Periodic playback position of the current media.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaUpdate {
+    pub current_time: f64,
+    pub paused: bool,
+}
+
+/// Single entry in the playback log.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PlaybackEntry {
+    pub time: i64,
+    pub current_time: f64,
+    pub paused: bool,
+}
+
+impl PlaybackEntry {
+    pub fn new(update: MediaUpdate, time: i64) -> Self {
+        Self {
+            time,
+            current_time: update.current_time,
+            paused: update.paused,
+        }
+    }
+}
+
+/// Single entry in the media timeline log.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct MediaEntry {
+    pub start_time: i64,
+    pub title: String,
+    pub id: String,
+    #[serde(rename = "type")]
+    pub media_type: String,
+    pub duration: u64,
+}
+
+impl MediaEntry {
+    pub fn new(media: Media, start_time: i64) -> Self {
+        Self {
+            start_time,
+            title: media.title,
+            id: media.id,
+            media_type: media.media_type,
+            duration: media.seconds.round() as u64,
+        }
+    }
+
+    /// Entry is a repeat of the previous entry. Cytube sometimes sends
+    /// `changeMedia` twice for the same media within a short time.
+    pub fn is_repeat_of(&self, previous: &MediaEntry) -> bool {
+        self.id == previous.id && self.start_time - previous.start_time < 1000
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct MessageContainer {
     text: String,
@@ -61,14 +826,43 @@ impl MessageContainer {
         D: Deserializer<'de>,
     {
         let v: String = Deserialize::deserialize(deserializer)?;
-        let dom = html_parser::Dom::parse(&v).map_err(de::Error::custom)?;
+        Ok(Self::parse(&v))
+    }
+
+    /// Message text and team from the HTML sent by Cytube.
+    fn parse(v: &str) -> Self {
+        match v.contains('<') {
+            true => Self::from_html(v),
+            false => Self::from_text(v),
+        }
+    }
+
+    /// Fast path for messages without markup, which need no DOM walk.
+    fn from_text(v: &str) -> Self {
+        MessageContainer {
+            text: v.trim().replace('"', "&quot;"),
+            team: Team::Empty,
+        }
+    }
+
+    fn from_html(v: &str) -> Self {
+        let dom = match html_parser::Dom::parse(v) {
+            Ok(dom) => dom,
+            Err(e) => {
+                // Keep malformed messages in the log instead of dropping them.
+                log::debug!("Could not parse message HTML, using raw text: {}", e);
+                return MessageContainer {
+                    text: v.trim().to_string(),
+                    team: Team::Empty,
+                };
+            }
+        };
         let mut text = String::new();
         let mut team = Team::Empty;
+        let mut cursor = 0;
         for child in dom.children {
+            keep_gap(v, &mut cursor, &child, &mut text);
             match child {
-                html_parser::Node::Text(t) => {
-                    text += &t.replace('"', "&quot;");
-                }
                 html_parser::Node::Element(element)
                     if element.name == "span" && element.classes == ["teamColorSpan"] =>
                 {
@@ -78,69 +872,257 @@ impl MessageContainer {
                         team = named
                     }
                 }
-                html_parser::Node::Element(element) => {
-                    text += &element.source_span.text;
-                }
-                other => {
-                    log::debug!("Found an unexpected member in message: {:?}", other)
+                html_parser::Node::Comment(comment) => {
+                    log::debug!("Found an unexpected comment in message: {:?}", comment)
                 }
+                child => append_node(child, Markup::KeepLinks, &mut text),
             }
         }
-        Ok(MessageContainer {
+        MessageContainer {
             text: text.trim().to_string(),
             team,
-        })
+        }
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// How `append_node` treats markup.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Markup {
+    /// Keep links and images as HTML and the text HTML-escaped, like the
+    /// message text in the chat log.
+    KeepLinks,
+    /// Decode entities and turn line breaks and block elements into spaces.
+    Plain,
+}
+
+/// Append the text of the nodes parsed from the source, keeping the
+/// whitespace between them.
+fn append_nodes(source: &str, nodes: Vec<html_parser::Node>, markup: Markup, text: &mut String) {
+    let mut cursor = 0;
+    for node in nodes {
+        keep_gap(source, &mut cursor, &node, text);
+        append_node(node, markup, text);
+    }
+}
+
+/// Append the text of a node, stripping formatting such as `<b>` or `<em>`
+/// from elements.
+fn append_node(node: html_parser::Node, markup: Markup, text: &mut String) {
+    let element = match node {
+        html_parser::Node::Text(t) => {
+            match markup {
+                Markup::KeepLinks => *text += &t.replace('"', "&quot;"),
+                Markup::Plain => *text += &decode_entities(&t),
+            }
+            return;
+        }
+        html_parser::Node::Element(element) => element,
+        html_parser::Node::Comment(_) => return,
+    };
+    let name = element.name.to_ascii_lowercase();
+    let source = element.source_span.text;
+    if markup == Markup::KeepLinks && ["a", "img"].contains(&name.as_str()) {
+        *text += &source;
+        return;
+    }
+    // Children follow the opening tag, whose attributes may contain their text.
+    let start = source.find('>').map_or(0, |end| end + 1);
+    append_nodes(&source[start..], element.children, markup, text);
+    if markup == Markup::Plain && ["br", "p", "div", "li"].contains(&name.as_str()) {
+        text.push(' ');
+    }
+}
+
+/// Keep whitespace between a node and the previous one as a single space, as
+/// the parser drops whitespace-only text between elements. The cursor tracks
+/// the position in the source after the previous node.
+fn keep_gap(source: &str, cursor: &mut usize, node: &html_parser::Node, text: &mut String) {
+    let fragment = match node {
+        html_parser::Node::Text(t) => t.as_str(),
+        html_parser::Node::Element(element) => element.source_span.text.as_str(),
+        html_parser::Node::Comment(_) => return,
+    };
+    let rest = &source[*cursor..];
+    let Some(start) = rest.find(fragment) else {
+        return;
+    };
+    *cursor += start + fragment.len();
+    let gap = &rest[..start];
+    if !gap.is_empty()
+        && gap.chars().all(char::is_whitespace)
+        && !text.is_empty()
+        && !text.ends_with(char::is_whitespace)
+    {
+        text.push(' ');
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 enum Team {
     Empty,
     Named(String),
 }
 
-impl Display for Team {
+impl Team {
+    /// Team name, or `NULL` for teamless users like in the TSV column.
+    fn as_str(&self) -> &str {
+        match self {
+            Team::Empty => "NULL",
+            Team::Named(name) => name,
+        }
+    }
+}
+
+impl Display for Team {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Teamless users serialize as `null` so that they cannot be confused with a
+/// team named `NULL`, unlike in the TSV column.
+impl Serialize for Team {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Team::Empty => serializer.serialize_none(),
+            Team::Named(name) => serializer.serialize_str(name),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Team {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match Option::<String>::deserialize(deserializer)? {
+            Some(name) => Team::Named(name),
+            None => Team::Empty,
+        })
+    }
+}
+
+impl Team {
+    /// Convert span text into a team name.
+    fn named_from_element(text: &str) -> Option<Self> {
+        if !text.starts_with("-team") {
+            return None;
+        }
+        if !text.ends_with('-') {
+            return None;
+        }
+        let name = &text[5..text.len() - 1];
+        if name.is_empty() {
+            return None;
+        }
+        Some(Self::Named(name.to_string()))
+    }
+}
+
+/// Reason for being disconnected from the Socket.IO server.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DisconnectReason {
+    /// The server closed the namespace connection, e.g. when restarting.
+    ServerDisconnect,
+    /// Any other reason given by the server.
+    Other(String),
+}
+
+impl DisconnectReason {
+    /// Map the payload of a Socket.IO close event to a reason. The client
+    /// library only emits the event for namespace disconnects by the server,
+    /// without a reason; connection losses are reconnected silently.
+    pub fn from_payload(values: &[Value]) -> Self {
+        match values.iter().find_map(Value::as_str).unwrap_or_default() {
+            "" | "io server disconnect" => DisconnectReason::ServerDisconnect,
+            other => DisconnectReason::Other(other.to_string()),
+        }
+    }
+
+    /// The server may have moved, so the socket config should be looked up
+    /// again before reconnecting. Other reasons reconnect to the same server.
+    pub fn needs_lookup(&self) -> bool {
+        *self == DisconnectReason::ServerDisconnect
+    }
+}
+
+impl Display for DisconnectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisconnectReason::ServerDisconnect => write!(f, "server closed the connection"),
+            DisconnectReason::Other(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// Record of a socket event in a `--raw-log` file.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RawRecord {
+    pub event: String,
+    pub time: i64,
+    pub payload: Vec<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SocketConfig {
+    pub servers: Vec<SocketConfigServer>,
+}
+
+/// Server to connect to out of the ones in the socket config.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServerSelection {
+    First,
+    Index(usize),
+    Host(String),
+}
+
+impl std::fmt::Display for ServerSelection {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Team::Empty => write!(f, "NULL"),
-            Team::Named(name) => write!(f, "{}", name),
+            ServerSelection::First => write!(f, "first server"),
+            ServerSelection::Index(index) => write!(f, "server index {}", index),
+            ServerSelection::Host(host) => write!(f, "server host {}", host),
         }
     }
 }
 
-impl Team {
-    /// Convert span text into a team name.
-    fn named_from_element(text: &str) -> Option<Self> {
-        if !text.starts_with("-team") {
-            return None;
-        }
-        if !text.ends_with('-') {
-            return None;
-        }
-        let name = &text[5..text.len() - 1];
-        if name.is_empty() {
-            return None;
+impl SocketConfig {
+    /// Pick a server from the config.
+    pub fn select(&self, selection: &ServerSelection) -> Option<&SocketConfigServer> {
+        match selection {
+            ServerSelection::First => self.servers.first(),
+            ServerSelection::Index(index) => self.servers.get(*index),
+            ServerSelection::Host(host) => self.servers.iter().find(|server| {
+                url::Url::parse(&server.url)
+                    .is_ok_and(|url| url.host_str().is_some_and(|h| h.eq_ignore_ascii_case(host)))
+            }),
         }
-        Some(Self::Named(name.to_string()))
     }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct SocketConfig {
-    pub servers: Vec<SocketConfigServer>,
-}
-
 #[derive(Debug, Deserialize)]
 pub struct SocketConfigServer {
     pub url: String,
 }
 
+/// Public channel as listed by the server's channel list endpoint.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct ChannelListEntry {
+    pub name: String,
+    pub usercount: u64,
+    #[serde(default)]
+    pub mediatitle: String,
+}
+
 #[cfg(test)]
 mod tests {
     use test_case::test_case;
 
-    use super::{ChatMessage, ChatMeta, Login, MessageContainer, Team};
-    use serde_json::json;
+    use super::{
+        AfkState, Announcement, ChannelListEntry, ChatDeletion, ChatFilter, ChatMessage, ChatMeta,
+        DEFAULT_TEMPLATE, DisconnectReason, Emote, ErrorKind, ErrorMsg, ErrorPayload, Kick, Login,
+        Media, MediaEntry, MediaUpdate, MessageContainer, PlaybackEntry, Poll, PollCount, Rank,
+        RateLimit, RawRecord, ServerSelection, Session, SocketConfig, Team, User, UserMeta,
+        UserMetaFlags, Voteskip, Whisper,
+    };
+    use serde_json::{Value, json};
 
     #[test]
     fn chat_message_deserialize_image() {
@@ -163,7 +1145,7 @@ mod tests {
                         <img src=\"https://example.com/image.jpg?ex=1234&amp;is=5678\" /></a>".into(),
                     team: Team::Empty,
                 },
-                meta: ChatMeta { add_class: None },
+                meta: ChatMeta::default(),
             }
         )
     }
@@ -190,7 +1172,8 @@ mod tests {
                     team: Team::Named("wg".into()),
                 },
                 meta: ChatMeta {
-                    add_class: Some("greentext".into())
+                    add_class: Some("greentext".into()),
+                    ..Default::default()
                 },
             }
         )
@@ -215,7 +1198,7 @@ mod tests {
                     text: ":harmony: :harmony:".into(),
                     team: Team::Named("ck".into()),
                 },
-                meta: ChatMeta { add_class: None },
+                meta: ChatMeta::default(),
             }
         )
     }
@@ -239,7 +1222,7 @@ mod tests {
                     text: "It's hip to be square.".into(),
                     team: Team::Empty,
                 },
-                meta: ChatMeta { add_class: None },
+                meta: ChatMeta::default(),
             }
         )
     }
@@ -271,7 +1254,12 @@ mod tests {
                     team: Team::Empty,
                 },
                 meta: ChatMeta {
-                    add_class: Some("server-whisper".into())
+                    add_class: Some("server-whisper".into()),
+                    add_class_to_name_and_timestamp: true,
+                    superadmin_flair: None,
+                    rank: None,
+                    edited: false,
+                    edit_time: None,
                 },
             }
         )
@@ -296,7 +1284,7 @@ mod tests {
                     text: "&quot;He'll be fine&quot;".into(),
                     team: Team::Empty,
                 },
-                meta: ChatMeta { add_class: None },
+                meta: ChatMeta::default(),
             }
         )
     }
@@ -323,83 +1311,780 @@ mod tests {
                         http://example.com</a>".into(),
                     team: Team::Named("vst".into()),
                 },
-                meta: ChatMeta { add_class: None },
+                meta: ChatMeta::default(),
+            }
+        )
+    }
+
+    #[test_case("It's hip to be square." ; "plain")]
+    #[test_case("\"He'll be fine\"" ; "quotes")]
+    #[test_case("&gt;XD &amp; :harmony:" ; "entities")]
+    #[test_case("  padded   text  " ; "whitespace")]
+    #[test_case("" ; "empty")]
+    fn message_container_text_fast_path(msg: &str) {
+        assert_eq!(
+            MessageContainer::from_text(msg),
+            MessageContainer::from_html(msg)
+        );
+    }
+
+    #[test_case("hello <b>world</b>", "hello world" ; "bold")]
+    #[test_case(
+        "<i>really <b>very</b> \"bold\"</i> text",
+        "really very &quot;bold&quot; text" ;
+        "bold inside italic"
+    )]
+    #[test_case("<em>see</em> <code>x &lt; y</code>", "see x &lt; y" ; "emphasis and code")]
+    #[test_case(
+        "<strong><a href=\"http://example.com\" target=\"_blank\">http://example.com</a></strong>",
+        "<a href=\"http://example.com\" target=\"_blank\">http://example.com</a>" ;
+        "link inside bold"
+    )]
+    #[test_case(
+        "<i><img class=\"channel-emote\" src=\"https://example.com/a.png\" title=\":a:\" /></i>",
+        "<img class=\"channel-emote\" src=\"https://example.com/a.png\" title=\":a:\" />" ;
+        "image inside italic"
+    )]
+    fn chat_message_deserialize_nested_formatting(msg: &str, expected: &str) {
+        let json = json!({
+            "username": "PotF",
+            "msg": format!("{} <span style=\"display:none\" class=\"teamColorSpan\">-teamwg-</span>", msg),
+            "meta": {},
+            "time": 1760634672025u64
+        });
+        let chat: ChatMessage = serde_json::from_value(json).unwrap();
+        assert_eq!(chat.text(), expected);
+        assert_eq!(chat.team_name(), Some("wg"));
+    }
+
+    #[test_case("<!-- unclosed comment :harmony:" ; "unclosed comment")]
+    #[test_case("<head>hello</head>" ; "head element")]
+    fn chat_message_deserialize_malformed_html(msg: &str) {
+        let timestamp: u64 = 1760634672025;
+        let json = json!({
+            "username": "Broken",
+            "msg": msg,
+            "meta": {},
+            "time": timestamp
+        });
+        let chat: ChatMessage = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            chat,
+            ChatMessage {
+                time: timestamp,
+                username: "Broken".into(),
+                msg: MessageContainer {
+                    text: msg.into(),
+                    team: Team::Empty,
+                },
+                meta: ChatMeta::default(),
+            }
+        )
+    }
+
+    #[test]
+    fn chat_message_display() {
+        let chat = ChatMessage {
+            time: 1760634889806,
+            username: "Dog".into(),
+            msg: MessageContainer {
+                text: "5 &gt; 3".into(),
+                team: Team::Named("vg".into()),
+            },
+            meta: ChatMeta::default(),
+        };
+        assert_eq!(format!("{}", chat), "1760634889806\tvg\tDog\t5 &gt; 3");
+    }
+
+    #[test]
+    fn chat_message_display_action() {
+        let chat = ChatMessage {
+            time: 1760634889806,
+            username: "Dog".into(),
+            msg: MessageContainer {
+                text: "barks".into(),
+                team: Team::Empty,
+            },
+            meta: ChatMeta {
+                add_class: Some("action".into()),
+                ..Default::default()
+            },
+        };
+        assert_eq!(format!("{}", chat), "1760634889806\tNULL\tDog\t/me barks");
+    }
+
+    #[test]
+    fn chat_message_short_format() {
+        let chat = ChatMessage {
+            time: 1760634889806,
+            username: "Dog".into(),
+            msg: MessageContainer {
+                text: ":carlos:".into(),
+                team: Team::Named("m".into()),
+            },
+            meta: ChatMeta::default(),
+        };
+        assert_eq!(format!("{}", chat.short_format()), "<Dog> :carlos:");
+    }
+
+    #[test]
+    fn chat_message_display_escapes_control() {
+        let chat = ChatMessage {
+            time: 1760634889806,
+            username: "Dog\tCat".into(),
+            msg: MessageContainer {
+                text: "first\nsecond\tcolumn\0".into(),
+                team: Team::Empty,
+            },
+            meta: ChatMeta::default(),
+        };
+        assert_eq!(
+            chat.to_string(),
+            "1760634889806\tNULL\tDog\\tCat\tfirst\\nsecond\\tcolumn\\0"
+        );
+        assert_eq!(
+            chat.short_format(),
+            "<Dog\\tCat> first\\nsecond\\tcolumn\\0"
+        );
+    }
+
+    #[test_case("plain", "plain" ; "no control characters")]
+    #[test_case("a\r\nb", "a\\r\\nb" ; "line break")]
+    #[test_case("bell\u{7}", "bell\\u{7}" ; "other control character")]
+    #[test_case("ÄÖ", "ÄÖ" ; "non-ascii")]
+    #[test_case("C:\\ \\n", "C:\\\\ \\\\n" ; "backslash")]
+    #[test_case("\\\n", "\\\\\\n" ; "backslash before line break")]
+    fn escape_control(value: &str, expected: &str) {
+        assert_eq!(super::escape_control(value), expected);
+    }
+
+    #[test_case("{time} [{team}] <{username}> {text}", "1760634889806 [m] <Dog> :carlos:" ; "all placeholders")]
+    #[test_case("{username}: {text} {unknown}", "Dog: :carlos: {unknown}" ; "unknown placeholder")]
+    #[test_case("{username} {text", "Dog {text" ; "unclosed placeholder")]
+    #[test_case("no placeholders", "no placeholders" ; "plain text")]
+    fn chat_message_format_with(template: &str, expected: &str) {
+        let chat = ChatMessage {
+            time: 1760634889806,
+            username: "Dog".into(),
+            msg: MessageContainer {
+                text: ":carlos:".into(),
+                team: Team::Named("m".into()),
+            },
+            meta: ChatMeta::default(),
+        };
+        assert_eq!(chat.format_with(template), expected);
+    }
+
+    #[test]
+    fn chat_message_format_with_placeholder_in_text() {
+        let chat = ChatMessage {
+            time: 1760634889806,
+            username: "{text}".into(),
+            msg: MessageContainer {
+                text: "{username}".into(),
+                team: Team::Empty,
+            },
+            meta: ChatMeta::default(),
+        };
+        assert_eq!(chat.format_with(DEFAULT_TEMPLATE), "<{text}> {username}");
+    }
+
+    #[test]
+    fn chat_message_to_json() {
+        let chat = ChatMessage {
+            time: 1760634889806,
+            username: "Dog".into(),
+            msg: MessageContainer {
+                text: ":carlos:".into(),
+                team: Team::Empty,
+            },
+            meta: ChatMeta::default(),
+        };
+        assert_eq!(
+            chat.to_json().to_string(),
+            "{\"datetime\":\"2025-10-16T17:14:49.806Z\",\"edit_time\":null,\"edited\":false,\
+            \"meta\":{},\"rank\":null,\"team\":null,\
+            \"text\":\":carlos:\",\"time\":1760634889806,\"type\":\"message\",\
+            \"username\":\"Dog\",\"username_color\":null}"
+        );
+    }
+
+    #[test_case(json!({}), false, None; "normal message")]
+    #[test_case(json!({"edited": true}), true, None; "edited flag")]
+    #[test_case(json!({"editTime": 1760634900000u64}), true, Some(1760634900000); "edit time")]
+    fn chat_message_edited(meta: Value, edited: bool, edit_time: Option<u64>) {
+        let chat: ChatMessage = serde_json::from_value(json!({
+            "username": "Dog",
+            "msg": "WOOF",
+            "meta": meta,
+            "time": 1760634889806u64
+        }))
+        .unwrap();
+        assert_eq!(chat.is_edited(), edited);
+        assert_eq!(chat.to_json()["edited"], edited);
+        assert_eq!(chat.to_json()["edit_time"], json!(edit_time));
+    }
+
+    #[test_case(&["i.imgur.com"], json!(["https://i.imgur.com/dog.png"]) ; "matching host")]
+    #[test_case(&["I.IMGUR.com"], json!(["https://i.imgur.com/dog.png"]) ; "different case")]
+    #[test_case(&["imgur.com"], json!([]) ; "parent domain")]
+    #[test_case(&["youtu.be", "i.imgur.com"], json!(["https://i.imgur.com/dog.png", "https://youtu.be/dQw4w9WgXcQ?t=1&s=2"]) ; "multiple hosts")]
+    #[test_case(&["example.com"], json!([]) ; "no matching host")]
+    fn chat_message_to_json_with_links(hosts: &[&str], expected: Value) {
+        let chat: ChatMessage = serde_json::from_value(json!({
+            "username": "Dog",
+            "msg": "look <a href=\"https://i.imgur.com/dog.png\" target=\"_blank\">\
+                https://i.imgur.com/dog.png</a> and <a href=\"https://youtu.be/dQw4w9WgXcQ?t=1&amp;s=2\" \
+                target=\"_blank\">https://youtu.be/dQw4w9WgXcQ?t=1&amp;s=2</a>",
+            "meta": {},
+            "time": 1760634889806u64
+        }))
+        .unwrap();
+        assert_eq!(chat.to_json_with_links(&[]).get("links"), None);
+        let hosts: Vec<String> = hosts.iter().map(|host| host.to_string()).collect();
+        assert_eq!(chat.to_json_with_links(&hosts)["links"], expected);
+    }
+
+    #[test]
+    fn chat_message_to_json_action() {
+        let chat = ChatMessage {
+            time: 1760634889806,
+            username: "Dog".into(),
+            msg: MessageContainer {
+                text: "barks".into(),
+                team: Team::Empty,
+            },
+            meta: ChatMeta {
+                add_class: Some("action".into()),
+                ..Default::default()
+            },
+        };
+        assert_eq!(chat.to_json()["type"], "action");
+        assert_eq!(chat.to_json()["text"], "barks");
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
+    fn chat_message_should_be_skipped_server_whisper() {
+        let chat = ChatMessage {
+            time: 1760634889806,
+            username: "[voteskip]".into(),
+            msg: MessageContainer {
+                text: "Voteskip passed".into(),
+                team: Team::Empty,
+            },
+            meta: ChatMeta {
+                add_class: Some("server-whisper".into()),
+                ..Default::default()
+            },
+        };
+        assert_eq!(chat.should_be_skipped(&["server-whisper".into()]), true);
+    }
+
+    #[test_case("!roll 2d6", "!", true ; "command")]
+    #[test_case(" !roll", "!", false ; "prefix after space")]
+    #[test_case("wow !roll", "!", false ; "prefix inside text")]
+    #[test_case("&gt;&gt;help", ">>", true ; "escaped prefix")]
+    #[test_case("!roll", "", false ; "empty prefix")]
+    fn chat_message_is_command(text: &str, prefix: &str, expected: bool) {
+        let chat = ChatMessage {
+            time: 1760634889806,
+            username: "Dog".into(),
+            msg: MessageContainer {
+                text: text.into(),
+                team: Team::Empty,
+            },
+            meta: ChatMeta::default(),
+        };
+        assert_eq!(chat.is_command(prefix), expected);
+    }
+
+    #[test_case("DJBot", true ; "same case")]
+    #[test_case("djbot", true ; "different case")]
+    #[test_case("Dog", false ; "other user")]
+    fn chat_message_is_from_any(username: &str, expected: bool) {
+        let chat = ChatMessage {
+            time: 1760634889806,
+            username: username.into(),
+            msg: MessageContainer {
+                text: "Now playing: Carlos".into(),
+                team: Team::Empty,
+            },
+            meta: ChatMeta::default(),
+        };
+        assert_eq!(chat.is_from_any(&["DJBot".into()]), expected);
+    }
+
+    #[test_case("5 &gt; 3", "5 > 3" ; "named")]
+    #[test_case("&#39;hi&#x27; &#40;", "'hi' (" ; "numeric")]
+    #[test_case("fish & chips &amp", "fish & chips &amp" ; "not an entity")]
+    #[test_case("&unknown; &#xZZ;", "&unknown; &#xZZ;" ; "unknown")]
+    fn decode_entities(text: &str, expected: &str) {
+        assert_eq!(super::decode_entities(text), expected);
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
+    fn chat_message_should_be_skipped_no_class() {
+        let chat = ChatMessage {
+            time: 1760634889806,
+            username: "Dog".into(),
+            msg: MessageContainer {
+                text: "5 &gt; 3".into(),
+                team: Team::Named("vg".into()),
+            },
+            meta: ChatMeta::default(),
+        };
+        assert_eq!(chat.should_be_skipped(&["server-whisper".into()]), false);
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
+    fn chat_message_should_be_skipped_wrong_class() {
+        let chat = ChatMessage {
+            time: 1760634889806,
+            username: "Dog".into(),
+            msg: MessageContainer {
+                text: "5 &gt; 3".into(),
+                team: Team::Named("vg".into()),
+            },
+            meta: ChatMeta {
+                add_class: Some("greentext".into()),
+                ..Default::default()
+            },
+        };
+        assert_eq!(chat.should_be_skipped(&["server-whisper".into()]), false);
+    }
+
+    #[test]
+    fn chat_message_should_be_skipped_custom_class() {
+        let chat = ChatMessage {
+            time: 1760634889806,
+            username: "Dog".into(),
+            msg: MessageContainer {
+                text: "5 &gt; 3".into(),
+                team: Team::Named("vg".into()),
+            },
+            meta: ChatMeta {
+                add_class: Some("greentext".into()),
+                ..Default::default()
+            },
+        };
+        assert!(chat.should_be_skipped(&["server-whisper".into(), "greentext".into()]));
+    }
+
+    #[test]
+    fn chat_message_should_be_skipped_no_classes() {
+        let chat = ChatMessage {
+            time: 1760634889806,
+            username: "[voteskip]".into(),
+            msg: MessageContainer {
+                text: "Voteskip passed".into(),
+                team: Team::Empty,
+            },
+            meta: ChatMeta {
+                add_class: Some("server-whisper".into()),
+                ..Default::default()
+            },
+        };
+        assert!(!chat.should_be_skipped(&[]));
+    }
+
+    #[test]
+    fn chat_message_deserialize_superadmin() {
+        let timestamp: u64 = 1760634672025;
+        let json = json!({
+            "username": "calzoneman",
+            "msg": "Server restarting in 5 minutes",
+            "meta": {
+                "addClass": "shout",
+                "addClassToNameAndTimestamp": true,
+                "superadminflair": {
+                    "labelclass": "label-danger",
+                    "icon": "glyphicon-globe"
+                },
+                "modflair": 255
+            },
+            "time": timestamp
+        });
+        let chat: ChatMessage = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            chat.meta,
+            ChatMeta {
+                add_class: Some("shout".into()),
+                add_class_to_name_and_timestamp: true,
+                superadmin_flair: Some(json!({
+                    "labelclass": "label-danger",
+                    "icon": "glyphicon-globe"
+                })),
+                rank: Some(Rank::Unknown(255)),
+                edited: false,
+                edit_time: None,
             }
-        )
+        );
+        assert!(chat.is_shout());
+        assert!(chat.is_from_superadmin());
     }
 
-    #[test]
-    fn chat_message_display() {
+    #[test_case(Some("action"), true ; "action")]
+    #[test_case(None, false ; "no class")]
+    #[test_case(Some("greentext"), false ; "wrong class")]
+    fn chat_message_is_action(add_class: Option<&str>, expected: bool) {
         let chat = ChatMessage {
             time: 1760634889806,
             username: "Dog".into(),
             msg: MessageContainer {
-                text: "5 &gt; 3".into(),
-                team: Team::Named("vg".into()),
+                text: "barks".into(),
+                team: Team::Empty,
+            },
+            meta: ChatMeta {
+                add_class: add_class.map(String::from),
+                ..Default::default()
             },
-            meta: ChatMeta { add_class: None },
         };
-        assert_eq!(format!("{}", chat), "1760634889806\tvg\tDog\t5 &gt; 3");
+        assert_eq!(chat.is_action(), expected);
     }
 
-    #[test]
-    fn chat_message_short_format() {
+    #[test_case(true, true ; "shout")]
+    #[test_case(false, false ; "class without name highlight")]
+    fn chat_message_is_shout(add_class_to_name_and_timestamp: bool, expected: bool) {
         let chat = ChatMessage {
             time: 1760634889806,
             username: "Dog".into(),
             msg: MessageContainer {
-                text: ":carlos:".into(),
-                team: Team::Named("m".into()),
+                text: "WOOF".into(),
+                team: Team::Empty,
+            },
+            meta: ChatMeta {
+                add_class: Some("shout".into()),
+                add_class_to_name_and_timestamp,
+                superadmin_flair: None,
+                rank: None,
+                edited: false,
+                edit_time: None,
             },
-            meta: ChatMeta { add_class: None },
         };
-        assert_eq!(format!("{}", chat.short_format()), "<Dog> :carlos:");
+        assert_eq!(chat.is_shout(), expected);
     }
 
     #[test]
-    fn chat_message_should_be_skipped_server_whisper() {
+    fn chat_message_short_format_action() {
         let chat = ChatMessage {
             time: 1760634889806,
-            username: "[voteskip]".into(),
+            username: "Dog".into(),
             msg: MessageContainer {
-                text: "Voteskip passed".into(),
+                text: "barks".into(),
                 team: Team::Empty,
             },
             meta: ChatMeta {
-                add_class: Some("server-whisper".into()),
+                add_class: Some("action".into()),
+                ..Default::default()
             },
         };
-        assert_eq!(chat.should_be_skipped(), true);
+        assert_eq!(chat.short_format(), "* Dog barks");
     }
 
     #[test]
-    fn chat_message_should_be_skipped_no_class() {
-        let chat = ChatMessage {
+    fn emote_deserialize() {
+        let json = json!({
+            "name": "Kappa",
+            "image": "https://example.com/kappa.png",
+            "source": "(^|\\s)Kappa(?!\\S)"
+        });
+        let emote: Emote = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            emote,
+            Emote {
+                name: "Kappa".into(),
+                image: "https://example.com/kappa.png".into(),
+            }
+        )
+    }
+
+    #[test]
+    fn chat_filter_deserialize() {
+        let json = json!({
+            "name": "monospace",
+            "source": "`(.+?)`",
+            "flags": "g",
+            "replace": "<code>\\1</code>",
+            "active": true,
+            "filterlinks": false
+        });
+        let filter: ChatFilter = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            filter,
+            ChatFilter {
+                name: "monospace".into(),
+                source: "`(.+?)`".into(),
+                flags: "g".into(),
+                replace: "<code>\\1</code>".into(),
+                active: true,
+            }
+        )
+    }
+
+    #[test]
+    fn chat_message_map_text() {
+        let mut chat = ChatMessage {
             time: 1760634889806,
             username: "Dog".into(),
             msg: MessageContainer {
-                text: "5 &gt; 3".into(),
-                team: Team::Named("vg".into()),
+                text: "woof".into(),
+                team: Team::Empty,
             },
-            meta: ChatMeta { add_class: None },
+            meta: ChatMeta::default(),
         };
-        assert_eq!(chat.should_be_skipped(), false);
+        chat.map_text(|text| text.to_uppercase());
+        assert_eq!(chat.text(), "WOOF");
     }
 
     #[test]
-    fn chat_message_should_be_skipped_wrong_class() {
-        let chat = ChatMessage {
-            time: 1760634889806,
-            username: "Dog".into(),
-            msg: MessageContainer {
-                text: "5 &gt; 3".into(),
-                team: Team::Named("vg".into()),
-            },
-            meta: ChatMeta {
-                add_class: Some("greentext".into()),
-            },
+    fn chat_deletion_deserialize() {
+        let json = json!({
+            "time": 1760634889806u64,
+            "username": "Dog",
+            "msg": "spam"
+        });
+        let deletion: ChatDeletion = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            deletion,
+            ChatDeletion {
+                time: 1760634889806,
+                username: "Dog".into(),
+                msg: Some("spam".into()),
+            }
+        );
+        assert_eq!(deletion.reference(), "1760634889806\tDog\t8b7dbd19093ccbc8");
+    }
+
+    #[test_case("spam" ; "plain")]
+    #[test_case("<strong>spam</strong> &amp; \"eggs\"" ; "formatted")]
+    #[test_case("<span class=\"teamColorSpan\">-teamfoo-</span>spam" ; "team")]
+    fn chat_deletion_reference_matches_logged_text(msg: &str) {
+        let json = json!({"time": 1760634889806u64, "username": "Dog", "msg": msg, "meta": {}});
+        let chat: ChatMessage = serde_json::from_value(json.clone()).unwrap();
+        let deletion: ChatDeletion = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            deletion.reference(),
+            format!(
+                "{}\t{}\t{:016x}",
+                chat.time,
+                chat.username,
+                super::fnv1a(chat.text())
+            )
+        );
+    }
+
+    #[test]
+    fn chat_deletion_reference_without_msg() {
+        let deletion: ChatDeletion =
+            serde_json::from_value(json!({"time": 1760634889806u64, "username": "Dog"})).unwrap();
+        assert_eq!(deletion.reference(), "1760634889806\tDog");
+    }
+
+    #[test]
+    fn error_payload_deserialize() {
+        let json = json!({
+            "message": "You are banned from this channel.",
+            "code": "BANNED"
+        });
+        let error: ErrorPayload = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            error,
+            ErrorPayload {
+                message: "You are banned from this channel.".into(),
+                code: Some("BANNED".into()),
+            }
+        )
+    }
+
+    #[test_case(Some("BANNED"), "Access denied", ErrorKind::Banned ; "banned code")]
+    #[test_case(None, "You are banned from this channel.", ErrorKind::Banned ; "banned message")]
+    #[test_case(Some("INVALID_CHANNEL"), "Cannot join", ErrorKind::InvalidChannel ; "invalid channel code")]
+    #[test_case(None, "Channel does not exist", ErrorKind::InvalidChannel ; "missing channel message")]
+    #[test_case(None, "Rate limit exceeded", ErrorKind::Other ; "other")]
+    fn error_payload_kind(code: Option<&str>, message: &str, expected: ErrorKind) {
+        let error = ErrorPayload {
+            message: message.into(),
+            code: code.map(String::from),
         };
-        assert_eq!(chat.should_be_skipped(), false);
+        assert_eq!(error.kind(), expected);
+    }
+
+    #[test]
+    fn error_msg_deserialize() {
+        let json = json!({
+            "msg": "\"#test\" is not a valid channel name."
+        });
+        let error: ErrorMsg = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            error,
+            ErrorMsg {
+                msg: "\"#test\" is not a valid channel name.".into()
+            }
+        )
+    }
+
+    #[test]
+    fn kick_deserialize() {
+        let json = json!({
+            "reason": "You're banned!"
+        });
+        let kick: Kick = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            kick,
+            Kick {
+                reason: "You're banned!".into()
+            }
+        )
+    }
+
+    #[test]
+    fn rate_limit_deserialize() {
+        let json = json!({
+            "reason": "NEW_USER_CHAT"
+        });
+        let rate_limit: RateLimit = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            rate_limit,
+            RateLimit {
+                reason: "NEW_USER_CHAT".into(),
+                cooldown: None
+            }
+        );
+        assert_eq!(rate_limit.to_string(), "NEW_USER_CHAT");
+    }
+
+    #[test]
+    fn rate_limit_from_cooldown() {
+        let rate_limit = RateLimit::from_cooldown(2000);
+        assert_eq!(rate_limit.to_string(), "COOLDOWN (2000 ms)");
+    }
+
+    #[test_case(0, Rank::Guest; "guest")]
+    #[test_case(1, Rank::User; "user")]
+    #[test_case(2, Rank::Moderator; "moderator")]
+    #[test_case(3, Rank::Admin; "admin")]
+    #[test_case(4, Rank::Owner; "owner")]
+    #[test_case(255, Rank::Unknown(255); "site admin")]
+    #[test_case(-1, Rank::Unknown(-1); "negative")]
+    fn rank_from_number(number: i64, expected: Rank) {
+        assert_eq!(Rank::from(number), expected);
+    }
+
+    #[test]
+    fn chat_message_to_json_rank() {
+        let mut chat: ChatMessage = serde_json::from_value(json!({
+            "username": "Dog",
+            "msg": "hi",
+            "meta": {"modflair": 2},
+            "time": 1760634889806u64
+        }))
+        .unwrap();
+        assert_eq!(chat.to_json()["rank"], "moderator");
+        assert_eq!(chat.to_json()["username_color"], "userlist_op");
+        chat.set_rank(Rank::Owner);
+        assert_eq!(chat.to_json()["rank"], "owner");
+        assert_eq!(chat.to_json()["username_color"], "userlist_owner");
+    }
+
+    #[test_case(Rank::Guest, Some("userlist_guest"); "guest")]
+    #[test_case(Rank::User, None; "user")]
+    #[test_case(Rank::Admin, Some("userlist_op"); "admin")]
+    #[test_case(Rank::Unknown(255), Some("userlist_siteadmin"); "site admin")]
+    #[test_case(Rank::Unknown(-1), None; "negative")]
+    fn rank_name_color(rank: Rank, expected: Option<&str>) {
+        assert_eq!(rank.name_color(), expected);
+    }
+
+    #[test]
+    fn user_deserialize() {
+        let json = json!({
+            "name": "Yuu",
+            "rank": 2,
+            "profile": {"image": "", "text": ""},
+            "meta": {"afk": false, "muted": false}
+        });
+        let user: User = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            user,
+            User {
+                name: "Yuu".into(),
+                rank: Rank::Moderator
+            }
+        )
+    }
+
+    #[test]
+    fn user_meta_deserialize() {
+        let json = json!({
+            "name": "cupcake",
+            "meta": {"afk": false, "muted": true, "smuted": true}
+        });
+        let user_meta: UserMeta = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            user_meta,
+            UserMeta {
+                name: "cupcake".into(),
+                meta: UserMetaFlags {
+                    afk: false,
+                    muted: true,
+                    smuted: true
+                }
+            }
+        )
+    }
+
+    #[test]
+    fn whisper_deserialize() {
+        let json = json!({
+            "username": "Dog",
+            "msg": "<strong>are you</strong> a bot? &lt;3 &amp; <a href=\"https://i.imgur.com/dog.png\" \
+                target=\"_blank\">https://i.imgur.com/dog.png</a>",
+            "meta": {},
+            "time": 1760634889806u64,
+            "to": "cupcake"
+        });
+        let whisper: Whisper = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            whisper,
+            Whisper {
+                from: "Dog".into(),
+                to: "cupcake".into(),
+                time: 1760634889806,
+                text: "are you a bot? <3 & https://i.imgur.com/dog.png".into(),
+            }
+        );
+        assert_eq!(
+            serde_json::to_string(&whisper).unwrap(),
+            "{\"from\":\"Dog\",\"time\":1760634889806,\
+            \"text\":\"are you a bot? <3 & https://i.imgur.com/dog.png\"}"
+        );
+    }
+
+    #[test]
+    fn afk_state_deserialize() {
+        let json = json!({"name": "cupcake", "afk": true});
+        let afk_state: AfkState = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            afk_state,
+            AfkState {
+                name: "cupcake".into(),
+                afk: true
+            }
+        )
+    }
+
+    #[test]
+    fn user_meta_deserialize_missing_flags() {
+        let json = json!({
+            "name": "cupcake",
+            "meta": {"afk": true}
+        });
+        let user_meta: UserMeta = serde_json::from_value(json).unwrap();
+        assert!(user_meta.meta.afk);
+        assert!(!user_meta.meta.smuted);
     }
 
     #[test]
@@ -413,12 +2098,29 @@ mod tests {
             login,
             Login {
                 error: Some("That username is registered.".into()),
+                guest: false,
                 name: None,
                 success: false,
             }
         )
     }
 
+    #[test_case(Session::Registered, json!({"name": "cupcake", "success": true}), false ; "registered again")]
+    #[test_case(Session::Registered, json!({"guest": true, "name": "cupcake", "success": true}), true ; "registered to guest")]
+    #[test_case(Session::Guest, json!({"guest": true, "name": "cupcake1", "success": true}), false ; "guest again")]
+    #[test_case(Session::Anonymous, json!({"guest": true, "name": "cupcake", "success": true}), false ; "first guest login")]
+    fn session_downgrade(session: Session, login: Value, expected: bool) {
+        let login: Login = serde_json::from_value(login).unwrap();
+        assert_eq!(session.is_downgraded_to(login.session().unwrap()), expected);
+    }
+
+    #[test_case("That username is registered.", true ; "registered")]
+    #[test_case("That name is already in use on this channel.", true ; "in use")]
+    #[test_case("Guest logins are disabled", false ; "other")]
+    fn is_name_taken(error: &str, expected: bool) {
+        assert_eq!(super::is_name_taken(error), expected);
+    }
+
     #[test]
     fn login_deserialize_success() {
         let json = json!({
@@ -431,12 +2133,163 @@ mod tests {
             login,
             Login {
                 error: None,
+                guest: true,
                 name: Some("cupcake1".into()),
                 success: true,
             }
         )
     }
 
+    #[test]
+    fn media_deserialize() {
+        let json = json!({
+            "id": "dQw4w9WgXcQ",
+            "title": "Rick Astley - Never Gonna Give You Up",
+            "seconds": 213,
+            "duration": "03:33",
+            "type": "yt",
+            "meta": {},
+            "currentTime": -3,
+            "paused": false
+        });
+        let media: Media = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            media,
+            Media {
+                id: "dQw4w9WgXcQ".into(),
+                title: "Rick Astley - Never Gonna Give You Up".into(),
+                media_type: "yt".into(),
+                seconds: 213.0,
+            }
+        )
+    }
+
+    #[test]
+    fn media_entry_serialize() {
+        let media = Media {
+            id: "dQw4w9WgXcQ".into(),
+            title: "Rick Astley - Never Gonna Give You Up".into(),
+            media_type: "yt".into(),
+            seconds: 212.6,
+        };
+        let entry = MediaEntry::new(media, 1760634889806);
+        assert_eq!(
+            serde_json::to_string(&entry).unwrap(),
+            "{\"start_time\":1760634889806,\"title\":\"Rick Astley - Never Gonna Give You Up\",\
+            \"id\":\"dQw4w9WgXcQ\",\"type\":\"yt\",\"duration\":213}"
+        );
+    }
+
+    #[test_case(json!({"currentTime": 42.31, "paused": false}), 42.31, false ; "playing")]
+    #[test_case(json!({"currentTime": -3, "paused": true}), -3.0, true ; "paused before start")]
+    fn media_update_deserialize(json: Value, current_time: f64, paused: bool) {
+        let update: MediaUpdate = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            update,
+            MediaUpdate {
+                current_time,
+                paused
+            }
+        );
+    }
+
+    #[test]
+    fn media_update_deserialize_missing_paused() {
+        let json = json!({"currentTime": 42.31});
+        assert!(serde_json::from_value::<MediaUpdate>(json).is_err());
+    }
+
+    #[test]
+    fn playback_entry_serialize() {
+        let update = MediaUpdate {
+            current_time: 42.5,
+            paused: true,
+        };
+        let entry = PlaybackEntry::new(update, 1760634889806);
+        assert_eq!(
+            serde_json::to_string(&entry).unwrap(),
+            "{\"time\":1760634889806,\"current_time\":42.5,\"paused\":true}"
+        );
+    }
+
+    #[test_case("abc", 1760634889806, true ; "same id within a second")]
+    #[test_case("abc", 1760634890806, false ; "same id after a second")]
+    #[test_case("def", 1760634889806, false ; "different id")]
+    fn media_entry_is_repeat_of(id: &str, start_time: i64, expected: bool) {
+        let previous = MediaEntry {
+            start_time: 1760634889500,
+            title: "Title".into(),
+            id: "abc".into(),
+            media_type: "yt".into(),
+            duration: 60,
+        };
+        let entry = MediaEntry {
+            start_time,
+            title: "Title".into(),
+            id: id.into(),
+            media_type: "yt".into(),
+            duration: 60,
+        };
+        assert_eq!(entry.is_repeat_of(&previous), expected);
+    }
+
+    fn voteskip_chat(username: &str, msg: &str) -> ChatMessage {
+        serde_json::from_value(json!({
+            "username": username,
+            "msg": msg,
+            "meta": {
+                "addClass": "server-whisper",
+                "addClassToNameAndTimestamp": true
+            },
+            "time": 1761058613150u64
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn voteskip_from_chat() {
+        let chat = voteskip_chat(
+            "[voteskip]",
+            "Voteskip passed: 3/7 skipped; eligible voters: 7 = \
+            total (9) - AFK (1) - no permission (1); ratio = 0.4",
+        );
+        assert_eq!(
+            Voteskip::from_chat(&chat),
+            Some(Voteskip {
+                time: 1761058613150,
+                skipped: 3,
+                eligible: 7,
+                ratio: 0.4,
+            })
+        );
+    }
+
+    #[test_case("[voteskip]", "Voteskip passed, but in a new format" ; "unknown format")]
+    #[test_case(
+        "[server]",
+        "Voteskip passed: 1/2 skipped; eligible voters: 2 = \
+        total (2) - AFK (0) - no permission (0); ratio = 0.5" ;
+        "other user"
+    )]
+    fn voteskip_from_chat_none(username: &str, msg: &str) {
+        let chat = voteskip_chat(username, msg);
+        assert_eq!(Voteskip::from_chat(&chat), None);
+    }
+
+    #[test]
+    fn voteskip_serialize() {
+        let voteskip = Voteskip {
+            time: 1761058613150,
+            skipped: 1,
+            eligible: 2,
+            ratio: 0.5,
+        };
+        assert_eq!(
+            serde_json::to_string(&voteskip).unwrap(),
+            "{\"time\":1761058613150,\"skipped\":1,\"eligible\":2,\"ratio\":0.5}"
+        );
+    }
+
     #[test_case(Team::Empty, "NULL" ; "empty")]
     #[test_case(Team::Named("vg".into()), "vg" ; "named")]
     fn team_display(team: Team, expected: &str) {
@@ -444,6 +2297,13 @@ mod tests {
         assert_eq!(output, expected);
     }
 
+    #[test_case(Team::Empty, "null" ; "empty")]
+    #[test_case(Team::Named("vg".into()), "\"vg\"" ; "named")]
+    #[test_case(Team::Named("NULL".into()), "\"NULL\"" ; "named null")]
+    fn team_serialize(team: Team, expected: &str) {
+        assert_eq!(serde_json::to_string(&team).unwrap(), expected);
+    }
+
     #[test_case("-team-", None ; "blank")]
     #[test_case("-team1999-", Some(Team::Named("1999".into())) ; "numerical")]
     #[test_case("-teama-", Some(Team::Named("a".into())) ; "short")]
@@ -454,4 +2314,142 @@ mod tests {
         let team = Team::named_from_element(text);
         assert_eq!(team, expected);
     }
+
+    #[test]
+    fn channel_list_deserialize() {
+        let json = json!([
+            {"name": "cup", "usercount": 12, "mediatitle": "Cup News", "pagetitle": "cup"},
+            {"name": "quiet", "usercount": 0}
+        ]);
+        let channels: Vec<ChannelListEntry> = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            channels,
+            vec![
+                ChannelListEntry {
+                    name: "cup".into(),
+                    usercount: 12,
+                    mediatitle: "Cup News".into(),
+                },
+                ChannelListEntry {
+                    name: "quiet".into(),
+                    usercount: 0,
+                    mediatitle: String::new(),
+                },
+            ]
+        );
+    }
+
+    #[test_case(ServerSelection::First, Some("https://a.cytu.be:443"); "first")]
+    #[test_case(ServerSelection::Index(1), Some("https://b.cytu.be:443"); "index")]
+    #[test_case(ServerSelection::Index(2), None; "index out of range")]
+    #[test_case(ServerSelection::Host("B.cytu.be".into()), Some("https://b.cytu.be:443"); "host")]
+    #[test_case(ServerSelection::Host("c.cytu.be".into()), None; "unknown host")]
+    fn socket_config_select(selection: ServerSelection, expected: Option<&str>) {
+        let config: SocketConfig = serde_json::from_value(json!({
+            "servers": [
+                {"url": "https://a.cytu.be:443", "secure": true},
+                {"url": "https://b.cytu.be:443", "secure": true}
+            ]
+        }))
+        .unwrap();
+        let selected = config.select(&selection).map(|server| server.url.as_str());
+        assert_eq!(selected, expected);
+    }
+
+    #[test]
+    fn raw_record_round_trip() {
+        let line = r#"{"event":"usercount","payload":[12],"time":1760634889806}"#;
+        let record: RawRecord = serde_json::from_str(line).unwrap();
+        assert_eq!(
+            record,
+            RawRecord {
+                event: "usercount".into(),
+                time: 1760634889806,
+                payload: vec![json!(12)],
+            }
+        );
+        assert_eq!(
+            serde_json::to_string(&record).unwrap(),
+            r#"{"event":"usercount","time":1760634889806,"payload":[12]}"#
+        );
+    }
+
+    #[test_case(&[json!("")], DisconnectReason::ServerDisconnect ; "library server disconnect")]
+    #[test_case(&[json!("io server disconnect")], DisconnectReason::ServerDisconnect ; "server disconnect")]
+    #[test_case(&[json!("parse error")], DisconnectReason::Other("parse error".into()) ; "other")]
+    #[test_case(&[], DisconnectReason::ServerDisconnect ; "no payload")]
+    fn disconnect_reason_from_payload(values: &[Value], expected: DisconnectReason) {
+        assert_eq!(DisconnectReason::from_payload(values), expected);
+    }
+
+    #[test_case(DisconnectReason::ServerDisconnect, true ; "server disconnect")]
+    #[test_case(DisconnectReason::Other("parse error".into()), false ; "other")]
+    fn disconnect_reason_needs_lookup(reason: DisconnectReason, expected: bool) {
+        assert_eq!(reason.needs_lookup(), expected);
+    }
+
+    #[test]
+    fn announcement_deserialize() {
+        let json = json!({
+            "title": "Scheduled maintenance",
+            "text": "The server restarts at <strong>03:00 UTC</strong>.<br>Expect\n a short outage.",
+            "from": "calzoneman"
+        });
+        let announcement: Announcement = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            announcement,
+            Announcement {
+                title: "Scheduled maintenance".into(),
+                text: "The server restarts at 03:00 UTC. Expect a short outage.".into(),
+                from: Some("calzoneman".into()),
+            }
+        );
+    }
+
+    #[test]
+    fn poll_deserialize() {
+        let json = json!({
+            "title": "Next &amp; last movie?",
+            "options": ["Alien", "<b>Aliens</b>"],
+            "counts": [3, 5],
+            "initiator": "PotF",
+            "timestamp": 1760634672025u64
+        });
+        let poll: Poll = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            poll,
+            Poll {
+                title: "Next & last movie?".into(),
+                options: vec!["Alien".into(), "Aliens".into()],
+                counts: vec![PollCount::Votes(3), PollCount::Votes(5)],
+                initiator: "PotF".into(),
+            }
+        );
+        assert_eq!(
+            poll.to_string(),
+            "Next & last movie? by PotF: Alien (3), Aliens (5)"
+        );
+    }
+
+    #[test]
+    fn poll_display_hidden_counts() {
+        let json = json!({
+            "title": "Pick one",
+            "options": ["a", "b"],
+            "counts": ["?", "?"],
+            "initiator": "PotF"
+        });
+        let poll: Poll = serde_json::from_value(json).unwrap();
+        assert_eq!(poll.to_string(), "Pick one by PotF: a (?), b (?)");
+    }
+
+    #[test_case("plain text", "plain text" ; "plain")]
+    #[test_case("<p>one</p><p>two</p>", "one two" ; "paragraphs")]
+    #[test_case("<a href=\"https://cytu.be\">link</a>!", "link!" ; "inline element")]
+    #[test_case("<i>fish <b>&amp;</b></i> chips", "fish & chips" ; "nested elements")]
+    #[test_case("5 &gt; 3", "5 > 3" ; "entities")]
+    #[test_case("", "" ; "empty")]
+    fn html_to_text(html: &str, expected: &str) {
+        assert_eq!(super::html_to_text(html), expected);
+    }
 }
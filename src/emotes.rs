@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::data::Emote;
+
+/// Load emote names and image URLs from a JSON file of `{"name": "url"}` pairs.
+pub fn load_file(path: &Path) -> anyhow::Result<HashMap<String, String>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read emote file {}", path.display()))?;
+    let emotes: HashMap<String, String> = serde_json::from_str(&content).with_context(|| {
+        format!(
+            "Emote file {} must be a JSON object of emote names to image URLs",
+            path.display()
+        )
+    })?;
+    if emotes.contains_key("") {
+        anyhow::bail!("Emote file {} contains an empty emote name", path.display());
+    }
+    Ok(emotes)
+}
+
+/// Merge the channel's emote list into the emotes loaded from a file.
+/// Emotes from the channel take precedence over ones with the same name.
+pub fn merge(file: &HashMap<String, String>, channel: Vec<Emote>) -> HashMap<String, String> {
+    let mut emotes = file.clone();
+    emotes.extend(channel.into_iter().map(|emote| (emote.name, emote.image)));
+    emotes
+}
+
+/// Replace whitespace-separated emote names in message text with their image URLs.
+pub fn expand(emotes: &HashMap<String, String>, text: &str) -> String {
+    text.split_inclusive(char::is_whitespace)
+        .map(|token| {
+            let word = token.trim_end_matches(char::is_whitespace);
+            match emotes.get(word) {
+                Some(image) => format!("{}{}", image, &token[word.len()..]),
+                None => token.to_string(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Write;
+
+    use test_case::test_case;
+
+    use crate::data::Emote;
+
+    fn emotes() -> HashMap<String, String> {
+        HashMap::from([
+            (":carlos:".into(), "https://example.com/carlos.png".into()),
+            ("Kappa".into(), "https://example.com/kappa.png".into()),
+        ])
+    }
+
+    #[test_case(":carlos:", "https://example.com/carlos.png"; "whole message")]
+    #[test_case(
+        "hi :carlos:  Kappa\n",
+        "hi https://example.com/carlos.png  https://example.com/kappa.png\n";
+        "whitespace preserved"
+    )]
+    #[test_case("Kappa123 a:carlos:", "Kappa123 a:carlos:"; "part of a word")]
+    #[test_case("", ""; "empty")]
+    fn expand(text: &str, expected: &str) {
+        assert_eq!(super::expand(&emotes(), text), expected);
+    }
+
+    #[test]
+    fn merge_prefers_channel() {
+        let channel = vec![Emote {
+            name: "Kappa".into(),
+            image: "https://cytu.be/kappa.png".into(),
+        }];
+        let merged = super::merge(&emotes(), channel);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged["Kappa"], "https://cytu.be/kappa.png");
+        assert_eq!(merged[":carlos:"], "https://example.com/carlos.png");
+    }
+
+    fn write_file(content: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn load_file() {
+        let file = write_file(r#"{"Kappa": "https://example.com/kappa.png"}"#);
+        let emotes = super::load_file(file.path()).unwrap();
+        assert_eq!(
+            emotes,
+            HashMap::from([("Kappa".into(), "https://example.com/kappa.png".into())])
+        );
+    }
+
+    #[test_case(r#"["Kappa"]"#; "array")]
+    #[test_case(r#"{"Kappa": 1}"#; "non-string URL")]
+    #[test_case(r#"{"": "https://example.com/kappa.png"}"#; "empty name")]
+    fn load_file_malformed(content: &str) {
+        let file = write_file(content);
+        assert!(super::load_file(file.path()).is_err());
+    }
+}
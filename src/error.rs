@@ -0,0 +1,92 @@
+use std::fmt::Display;
+
+/// Failure causes that are reported to the caller with a dedicated exit code.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Fetching or parsing the Cytube socket config failed.
+    ConfigLookup,
+    /// Connecting to the Socket.IO server failed.
+    Connection,
+    /// Kicked or banned from the channel.
+    Kicked(String),
+    /// Logging in failed.
+    Login(String),
+    /// No channel data was received within the given number of seconds after joining.
+    JoinTimeout(u64),
+    /// The server reported that the channel cannot be joined.
+    Channel(String),
+}
+
+impl Error {
+    /// Process exit code for the failure.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::ConfigLookup => 2,
+            Error::Connection => 3,
+            Error::Kicked(_) => 4,
+            Error::Login(_) => 5,
+            Error::JoinTimeout(_) => 6,
+            Error::Channel(_) => 7,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::ConfigLookup => write!(f, "Socket config lookup failed"),
+            Error::Connection => write!(f, "Connection failed"),
+            Error::Kicked(reason) => write!(f, "Kicked from channel: {}", reason),
+            Error::Login(error) => write!(f, "Login failed: {}", error),
+            Error::JoinTimeout(seconds) => write!(
+                f,
+                "No channel data received within {} seconds of joining; does the channel exist?",
+                seconds
+            ),
+            Error::Channel(error) => write!(f, "Channel unavailable: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Process exit code for an error returned from the main task.
+/// Errors without a dedicated cause exit with 1.
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    match err.downcast_ref::<Error>() {
+        Some(e) => e.exit_code(),
+        None => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+    use test_case::test_case;
+
+    use super::Error;
+
+    #[test_case(Error::ConfigLookup, 2 ; "config lookup")]
+    #[test_case(Error::Connection, 3 ; "connection")]
+    #[test_case(Error::Kicked("Banned".into()), 4 ; "kicked")]
+    #[test_case(Error::Login("That username is registered.".into()), 5 ; "login")]
+    #[test_case(Error::JoinTimeout(30), 6 ; "join timeout")]
+    #[test_case(Error::Channel("Channel does not exist".into()), 7 ; "channel")]
+    fn exit_code(error: Error, expected: i32) {
+        assert_eq!(error.exit_code(), expected);
+    }
+
+    #[test]
+    fn exit_code_context() {
+        let err = Err::<(), _>(anyhow::anyhow!("timed out"))
+            .context(Error::Connection)
+            .unwrap_err();
+        assert_eq!(super::exit_code(&err), 3);
+    }
+
+    #[test]
+    fn exit_code_other() {
+        let err = anyhow::anyhow!("Could not create output file");
+        assert_eq!(super::exit_code(&err), 1);
+    }
+}
@@ -0,0 +1,211 @@
+use std::process::Stdio;
+
+use anyhow::Context;
+use futures_util::FutureExt;
+use futures_util::future::BoxFuture;
+use tokio::io::AsyncWriteExt;
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+
+use crate::data::ChatMessage;
+use crate::format::OutputFormat;
+use crate::sink::MessageSink;
+
+const EXEC_QUEUE_SIZE: usize = 256;
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Running command with its piped standard input.
+struct Process {
+    child: Child,
+    stdin: ChildStdin,
+}
+
+impl Process {
+    /// Start the command through the platform's shell.
+    fn start(command: &str) -> anyhow::Result<Self> {
+        #[cfg(windows)]
+        let mut shell = {
+            let mut shell = Command::new("cmd");
+            shell.arg("/C");
+            shell
+        };
+        #[cfg(not(windows))]
+        let mut shell = {
+            let mut shell = Command::new("sh");
+            shell.arg("-c");
+            shell
+        };
+        let mut child = shell
+            .arg(command)
+            .stdin(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Could not start command {}", command))?;
+        let stdin = child
+            .stdin
+            .take()
+            .context("Command has no standard input")?;
+        log::info!("Started command {}", command);
+        Ok(Self { child, stdin })
+    }
+
+    async fn write(&mut self, line: &str) -> std::io::Result<()> {
+        self.stdin.write_all(line.as_bytes()).await?;
+        self.stdin.flush().await
+    }
+
+    /// Close the standard input and wait for the command to exit.
+    async fn finish(self, command: &str) {
+        let Self { mut child, stdin } = self;
+        drop(stdin);
+        match child.wait().await {
+            Ok(status) if status.success() => log::debug!("Command {} exited", command),
+            Ok(status) => log::warn!("Command {} exited with {}", command, status),
+            Err(e) => log::warn!("Could not wait for command {}: {}", command, e),
+        }
+    }
+}
+
+/// Writes logged chat messages to the standard input of a long-running
+/// command, one line per message, without blocking the manager task.
+pub struct Exec {
+    format: OutputFormat,
+    link_hosts: Vec<String>,
+    tx: Option<mpsc::Sender<String>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl Exec {
+    /// Start the command, restarting it on the next message if it exits and
+    /// `restart` is set.
+    pub fn spawn(
+        command: String,
+        format: OutputFormat,
+        link_hosts: Vec<String>,
+        restart: bool,
+    ) -> anyhow::Result<Self> {
+        let process = Process::start(&command)?;
+        let (tx, rx) = mpsc::channel::<String>(EXEC_QUEUE_SIZE);
+        let task = tokio::spawn(feed(command, process, rx, restart));
+        Ok(Self {
+            format,
+            link_hosts,
+            tx: Some(tx),
+            task: Some(task),
+        })
+    }
+}
+
+/// Pass queued lines to the command until the queue is closed.
+async fn feed(command: String, process: Process, mut rx: mpsc::Receiver<String>, restart: bool) {
+    let mut process = Some(process);
+    while let Some(line) = rx.recv().await {
+        if process.is_none() {
+            if !restart {
+                continue;
+            }
+            tokio::time::sleep(RESTART_DELAY).await;
+            process = Process::start(&command)
+                .inspect_err(|e| log::error!("{:#}", e))
+                .ok();
+        }
+        let Some(running) = &mut process else {
+            continue;
+        };
+        if let Err(e) = running.write(&line).await {
+            log::error!("Command {} stopped reading messages: {}", command, e);
+            if let Some(stopped) = process.take() {
+                stopped.finish(&command).await;
+            }
+            match restart {
+                true => log::info!("Restarting command {} on the next message", command),
+                false => log::warn!("No longer passing messages to command {}", command),
+            }
+        }
+    }
+    if let Some(process) = process {
+        process.finish(&command).await;
+    }
+    log::debug!("Ending command task");
+}
+
+impl MessageSink for Exec {
+    fn write<'a>(
+        &'a mut self,
+        chat: &'a ChatMessage,
+        seq: Option<u64>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        let mut line = String::new();
+        self.format
+            .write_chat(&mut line, chat, seq, &self.link_hosts);
+        line.push('\n');
+        if let Some(tx) = &self.tx
+            && tx.try_send(line).is_err()
+        {
+            log::warn!("Command queue is full, dropping message");
+        }
+        async { Ok(()) }.boxed()
+    }
+
+    /// Notices are not passed to the command.
+    fn write_notice<'a>(
+        &'a mut self,
+        _time: i64,
+        _event: &'a str,
+        _detail: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        async { Ok(()) }.boxed()
+    }
+
+    fn flush(&mut self) -> BoxFuture<'_, ()> {
+        async {}.boxed()
+    }
+
+    /// Pass the queued lines, then close the command's input and wait for it to exit.
+    fn close(&mut self) -> BoxFuture<'_, anyhow::Result<()>> {
+        async move {
+            drop(self.tx.take());
+            if let Some(task) = self.task.take() {
+                task.await
+                    .map_err(|e| anyhow::anyhow!("Command task failed: {}", e))?;
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::Exec;
+    use crate::data::ChatMessage;
+    use crate::format::OutputFormat;
+    use crate::sink::MessageSink;
+
+    fn chat(text: &str) -> ChatMessage {
+        serde_json::from_value(serde_json::json!({
+            "username": "Yuu",
+            "msg": text,
+            "meta": {},
+            "time": 1760631669671u64
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn exec_writes_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("exec.tsv");
+        let command = format!("cat > '{}'", path.display());
+        let mut exec = Exec::spawn(command, OutputFormat::Tsv, Vec::new(), false).unwrap();
+        exec.write(&chat("one"), None).await.unwrap();
+        exec.write(&chat("two"), Some(2)).await.unwrap();
+        exec.close().await.unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            content,
+            "1760631669671\tNULL\tYuu\tone\n2\t1760631669671\tNULL\tYuu\ttwo\n"
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use regex::{Regex, RegexBuilder};
+
+use crate::data::ChatFilter;
+
+/// Channel chat filter with its pattern compiled.
+#[derive(Debug)]
+pub struct CompiledFilter {
+    name: String,
+    regex: Regex,
+    replace: String,
+    global: bool,
+}
+
+/// Compile active chat filters, skipping inactive and invalid ones.
+pub fn compile(filters: Vec<ChatFilter>) -> Vec<CompiledFilter> {
+    filters
+        .into_iter()
+        .filter(|filter| filter.active)
+        .filter_map(|filter| {
+            let regex = RegexBuilder::new(&filter.source)
+                .case_insensitive(filter.flags.contains('i'))
+                .build();
+            match regex {
+                Ok(regex) => Some(CompiledFilter {
+                    replace: translate_replacement(&filter.replace),
+                    global: filter.flags.contains('g'),
+                    name: filter.name,
+                    regex,
+                }),
+                Err(e) => {
+                    log::warn!("Could not compile chat filter {}: {}", filter.name, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Translate a JavaScript replacement string into the syntax of the regex
+/// crate. JavaScript uses `$&` for the whole match and `$1` for groups, for
+/// which Cytube also accepts `\1`, while any other `$` is literal.
+fn translate_replacement(replace: &str) -> String {
+    let mut translated = String::with_capacity(replace.len());
+    let mut chars = replace.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek().copied()) {
+            ('$', Some('&')) => {
+                chars.next();
+                translated.push_str("${0}");
+            }
+            ('$', Some('$')) => {
+                chars.next();
+                translated.push_str("$$");
+            }
+            ('$' | '\\', Some(digit)) if digit.is_ascii_digit() => {
+                chars.next();
+                translated.push_str(&format!("${{{}}}", digit));
+            }
+            ('$', _) => translated.push_str("$$"),
+            (c, _) => translated.push(c),
+        }
+    }
+    translated
+}
+
+/// Apply the filters to message text in order.
+pub fn apply(filters: &[CompiledFilter], text: &str) -> String {
+    let mut text = text.to_string();
+    for filter in filters {
+        let replaced = match filter.global {
+            true => filter.regex.replace_all(&text, filter.replace.as_str()),
+            false => filter.regex.replace(&text, filter.replace.as_str()),
+        };
+        if replaced != text {
+            log::debug!("Applied chat filter {}", filter.name);
+            text = replaced.into_owned();
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use crate::data::ChatFilter;
+
+    fn filter(source: &str, flags: &str, replace: &str, active: bool) -> ChatFilter {
+        ChatFilter {
+            name: "test".into(),
+            source: source.into(),
+            flags: flags.into(),
+            replace: replace.into(),
+            active,
+        }
+    }
+
+    #[test]
+    fn apply_word_replacement() {
+        let filters = super::compile(vec![filter("darn", "gi", "heck", true)]);
+        assert_eq!(
+            super::apply(&filters, "Darn it, darn it all"),
+            "heck it, heck it all"
+        );
+    }
+
+    #[test]
+    fn apply_not_global() {
+        let filters = super::compile(vec![filter("darn", "", "heck", true)]);
+        assert_eq!(
+            super::apply(&filters, "darn it, darn it all"),
+            "heck it, darn it all"
+        );
+    }
+
+    #[test]
+    fn apply_whole_match() {
+        let filters = super::compile(vec![filter("cupcake", "g", "<b>$&</b>", true)]);
+        assert_eq!(super::apply(&filters, "a cupcake"), "a <b>cupcake</b>");
+    }
+
+    #[test_case("<b>$&</b>", "<b>${0}</b>" ; "whole match")]
+    #[test_case("$1-$2", "${1}-${2}" ; "dollar group")]
+    #[test_case("\\1a", "${1}a" ; "backslash group")]
+    #[test_case("$$5 \\n", "$$5 \\n" ; "literals")]
+    #[test_case("$name $", "$$name $$" ; "lone dollar")]
+    fn translate_replacement(replace: &str, expected: &str) {
+        assert_eq!(super::translate_replacement(replace), expected);
+    }
+
+    #[test]
+    fn apply_backreference() {
+        let filters = super::compile(vec![filter("(\\w+)@(\\w+)", "g", "\\2 at \\1", true)]);
+        assert_eq!(super::apply(&filters, "dog@cytube"), "cytube at dog");
+    }
+
+    #[test]
+    fn compile_skips_inactive_and_invalid() {
+        let filters = super::compile(vec![
+            filter("darn", "g", "heck", false),
+            filter("(unclosed", "g", "", true),
+            filter("gosh", "g", "golly", true),
+        ]);
+        assert_eq!(filters.len(), 1);
+        assert_eq!(super::apply(&filters, "darn gosh"), "darn golly");
+    }
+}
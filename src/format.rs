@@ -0,0 +1,301 @@
+use std::fmt::Write;
+
+use serde::Serialize;
+use serde_json::json;
+
+use crate::data::{ChatMessage, escape_control};
+
+/// File format of the chat log.
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Tab-separated values with one message per line.
+    #[default]
+    Tsv,
+    /// Newline-delimited JSON objects.
+    Ndjson,
+    /// A single JSON array of message objects.
+    JsonArray,
+}
+
+impl OutputFormat {
+    /// File extension of chat logs in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Tsv => "txt",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::JsonArray => "json",
+        }
+    }
+
+    /// Text written at the start of a chat log file.
+    pub fn opening(self) -> &'static str {
+        match self {
+            OutputFormat::JsonArray => "[",
+            OutputFormat::Tsv | OutputFormat::Ndjson => "",
+        }
+    }
+
+    /// Text written at the end of a chat log file.
+    pub fn closing(self) -> &'static str {
+        match self {
+            OutputFormat::JsonArray => "\n]\n",
+            OutputFormat::Tsv | OutputFormat::Ndjson => "",
+        }
+    }
+
+    /// Text written before and after a record, separating JSON array elements with commas.
+    pub fn delimiters(self, first: bool) -> (&'static str, &'static str) {
+        match (self, first) {
+            (OutputFormat::JsonArray, true) => ("\n", ""),
+            (OutputFormat::JsonArray, false) => (",\n", ""),
+            (OutputFormat::Tsv | OutputFormat::Ndjson, _) => ("", "\n"),
+        }
+    }
+
+    /// Format a chat message as a record, prefixed by the sequence number if
+    /// given. JSON records list the links to `link_hosts` if any are given.
+    pub fn format_chat(
+        self,
+        chat: &ChatMessage,
+        seq: Option<u64>,
+        link_hosts: &[String],
+    ) -> String {
+        let mut record = String::new();
+        self.write_chat(&mut record, chat, seq, link_hosts);
+        record
+    }
+
+    /// Append a chat message record to a reused buffer instead of allocating one.
+    pub fn write_chat(
+        self,
+        out: &mut String,
+        chat: &ChatMessage,
+        seq: Option<u64>,
+        link_hosts: &[String],
+    ) {
+        // Writing to a String cannot fail.
+        let _ = match (self, seq) {
+            (OutputFormat::Tsv, Some(seq)) => write!(out, "{}\t{}", seq, chat),
+            (OutputFormat::Tsv, None) => write!(out, "{}", chat),
+            (OutputFormat::Ndjson | OutputFormat::JsonArray, seq) => {
+                let mut value = chat.to_json_with_links(link_hosts);
+                if let Some(seq) = seq {
+                    value["seq"] = json!(seq);
+                }
+                write!(out, "{}", value)
+            }
+        };
+    }
+
+    /// Format a notice about a non-chat event so that it stands out from
+    /// chat messages: a `#`-prefixed line in TSV, an `_event` object in ndjson.
+    pub fn format_notice(self, time: i64, event: &str, detail: &str) -> String {
+        match self {
+            OutputFormat::Tsv => format!("# {}\t{}\t{}", time, event, escape_control(detail)),
+            OutputFormat::Ndjson | OutputFormat::JsonArray => {
+                json!({"_event": event, "time": time, "detail": detail}).to_string()
+            }
+        }
+    }
+
+    /// Format the metadata header of a chat log. TSV headers are `#`-prefixed
+    /// comment lines so that parsers can skip them.
+    pub fn format_meta(self, meta: &FileMeta) -> String {
+        match self {
+            OutputFormat::Tsv => format!(
+                "# cupcake {}\n# domain: {}\n# channel: {}\n# started: {}",
+                meta.version, meta.domain, meta.channel, meta.started
+            ),
+            OutputFormat::Ndjson | OutputFormat::JsonArray => json!({"_meta": meta}).to_string(),
+        }
+    }
+}
+
+/// Representative chat message record in the JSON formats, built from a
+/// sample message so that it matches what is actually written.
+pub fn sample_record() -> serde_json::Result<String> {
+    let chat: ChatMessage = serde_json::from_value(json!({
+        "username": "PotF",
+        "msg": "&gt;XD <span style=\"display:none\" class=\"teamColorSpan\">-teamwg-</span>",
+        "meta": {"modflair": 2},
+        "time": 1760634672025u64
+    }))?;
+    let record = OutputFormat::Ndjson.format_chat(&chat, Some(1), &[]);
+    let value: serde_json::Value = serde_json::from_str(&record)?;
+    serde_json::to_string_pretty(&value)
+}
+
+/// Metadata describing which server and channel produced a chat log.
+#[derive(Debug, Serialize)]
+pub struct FileMeta {
+    pub version: &'static str,
+    pub domain: String,
+    pub channel: String,
+    pub started: String,
+}
+
+impl FileMeta {
+    pub fn new(domain: &str, channel: &str) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            domain: domain.to_string(),
+            channel: channel.to_string(),
+            started: chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+    use test_case::test_case;
+
+    use super::{FileMeta, OutputFormat};
+    use crate::data::ChatMessage;
+
+    fn meta() -> FileMeta {
+        FileMeta {
+            version: "0.3.0",
+            domain: "cytu.be".into(),
+            channel: "test".into(),
+            started: "2025-10-16T17:11:12Z".into(),
+        }
+    }
+
+    #[test_case(OutputFormat::Tsv, None, "1760634672025\twg\tPotF\t&gt;XD"; "tsv")]
+    #[test_case(OutputFormat::Tsv, Some(5), "5\t1760634672025\twg\tPotF\t&gt;XD"; "tsv with seq")]
+    #[test_case(
+        OutputFormat::Ndjson,
+        None,
+        "{\"datetime\":\"2025-10-16T17:11:12.025Z\",\"edit_time\":null,\"edited\":false,\"meta\":{},\"rank\":null,\"team\":\"wg\",\"text\":\"&gt;XD\",\"time\":1760634672025,\"type\":\"message\",\"username\":\"PotF\",\"username_color\":null}";
+        "ndjson"
+    )]
+    #[test_case(
+        OutputFormat::Ndjson,
+        Some(5),
+        "{\"datetime\":\"2025-10-16T17:11:12.025Z\",\"edit_time\":null,\"edited\":false,\"meta\":{},\"rank\":null,\"seq\":5,\"team\":\"wg\",\"text\":\"&gt;XD\",\"time\":1760634672025,\"type\":\"message\",\"username\":\"PotF\",\"username_color\":null}";
+        "ndjson with seq"
+    )]
+    fn format_chat(format: OutputFormat, seq: Option<u64>, expected: &str) {
+        let chat: ChatMessage = serde_json::from_value(json!({
+            "username": "PotF",
+            "msg": "&gt;XD <span style=\"display:none\" class=\"teamColorSpan\">-teamwg-</span>",
+            "meta": {},
+            "time": 1760634672025u64
+        }))
+        .unwrap();
+        assert_eq!(format.format_chat(&chat, seq, &[]), expected);
+        let mut buffer = String::from("previous");
+        buffer.clear();
+        format.write_chat(&mut buffer, &chat, seq, &[]);
+        assert_eq!(buffer, expected);
+    }
+
+    #[test_case(
+        "&gt;XD <span style=\"display:none\" class=\"teamColorSpan\">-teamwg-</span>",
+        json!({"addClass": "greentext"});
+        "greentext with team"
+    )]
+    #[test_case(
+        "\"He'll be fine\" <a href=\"http://example.com\" target=\"_blank\">http://example.com</a>",
+        json!({"modflair": 2, "edited": true, "editTime": 1760634672999u64});
+        "link with flair and edit"
+    )]
+    #[test_case(
+        "Maintenance tonight",
+        json!({
+            "addClass": "shout",
+            "addClassToNameAndTimestamp": true,
+            "superadminflair": {"labelclass": "label-danger", "icon": "glyphicon-globe"},
+            "modflair": 255
+        });
+        "superadmin shout"
+    )]
+    #[test_case("barks", json!({"addClass": "action"}); "action")]
+    #[test_case(
+        "NULL <span style=\"display:none\" class=\"teamColorSpan\">-teamNULL-</span>",
+        json!({});
+        "team named NULL"
+    )]
+    #[test_case("<!-- unclosed comment :harmony:", json!({}); "malformed html")]
+    fn ndjson_round_trip(msg: &str, meta: Value) {
+        let chat: ChatMessage = serde_json::from_value(json!({
+            "username": "PotF",
+            "msg": msg,
+            "meta": meta,
+            "time": 1760634672025u64
+        }))
+        .unwrap();
+        let line = OutputFormat::Ndjson.format_chat(&chat, Some(5), &["example.com".into()]);
+        let record: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(ChatMessage::from_value(record).unwrap(), chat);
+    }
+
+    #[test_case(OutputFormat::Tsv, "NEW_USER_CHAT", "# 1760634672025\trateLimit\tNEW_USER_CHAT"; "tsv")]
+    #[test_case(
+        OutputFormat::Tsv,
+        "Movie night\n# fake\tline",
+        "# 1760634672025\trateLimit\tMovie night\\n# fake\\tline";
+        "tsv control characters"
+    )]
+    #[test_case(
+        OutputFormat::Ndjson,
+        "NEW_USER_CHAT",
+        "{\"_event\":\"rateLimit\",\"detail\":\"NEW_USER_CHAT\",\"time\":1760634672025}";
+        "ndjson"
+    )]
+    fn format_notice(format: OutputFormat, detail: &str, expected: &str) {
+        assert_eq!(
+            format.format_notice(1760634672025, "rateLimit", detail),
+            expected
+        );
+    }
+
+    #[test]
+    fn format_meta_tsv() {
+        assert_eq!(
+            OutputFormat::Tsv.format_meta(&meta()),
+            "# cupcake 0.3.0\n# domain: cytu.be\n# channel: test\n# started: 2025-10-16T17:11:12Z"
+        );
+    }
+
+    #[test]
+    fn format_meta_ndjson() {
+        let line = OutputFormat::Ndjson.format_meta(&meta());
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            value,
+            json!({"_meta": {
+                "version": "0.3.0",
+                "domain": "cytu.be",
+                "channel": "test",
+                "started": "2025-10-16T17:11:12Z"
+            }})
+        );
+    }
+
+    #[test]
+    fn sample_record() {
+        let sample: serde_json::Value =
+            serde_json::from_str(&super::sample_record().unwrap()).unwrap();
+        assert_eq!(sample["seq"], 1);
+        assert_eq!(sample["team"], "wg");
+        assert_eq!(sample["rank"], "moderator");
+    }
+
+    #[test_case(OutputFormat::Tsv, &["a", "b"], "a\nb\n"; "tsv")]
+    #[test_case(OutputFormat::Ndjson, &["{}", "{}"], "{}\n{}\n"; "ndjson")]
+    #[test_case(OutputFormat::JsonArray, &["{}", "{}"], "[\n{},\n{}\n]\n"; "json array")]
+    #[test_case(OutputFormat::JsonArray, &[], "[\n]\n"; "empty json array")]
+    fn delimiters(format: OutputFormat, records: &[&str], expected: &str) {
+        let mut output = format.opening().to_string();
+        for (i, record) in records.iter().enumerate() {
+            let (prefix, suffix) = format.delimiters(i == 0);
+            output.push_str(prefix);
+            output.push_str(record);
+            output.push_str(suffix);
+        }
+        output.push_str(format.closing());
+        assert_eq!(output, expected);
+    }
+}
@@ -0,0 +1,216 @@
+#[cfg(not(feature = "tracing"))]
+use std::io::Write;
+#[cfg(not(feature = "tracing"))]
+use std::path::Path;
+#[cfg(not(feature = "tracing"))]
+use std::sync::Mutex;
+
+#[cfg(not(feature = "tracing"))]
+use anyhow::Context;
+#[cfg(not(feature = "tracing"))]
+use log::{Level, LevelFilter, Log, Metadata, Record};
+#[cfg(not(feature = "tracing"))]
+use serde_json::json;
+#[cfg(not(feature = "tracing"))]
+use simple_logger::SimpleLogger;
+
+/// Target of log records about acknowledged chat commands, which are written
+/// to the diagnostics file regardless of their level.
+pub const COMMAND_TARGET: &str = "cupcake::commands";
+
+/// Log the acknowledgement of a chat command, such as a poll opened with `/poll`.
+pub fn command_ack(message: std::fmt::Arguments) {
+    #[cfg(feature = "tracing")]
+    tracing::info!(target: COMMAND_TARGET, "{}", message);
+    #[cfg(not(feature = "tracing"))]
+    log::info!(target: COMMAND_TARGET, "{}", message);
+}
+
+/// Format of cupcake's own diagnostic logs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Plain,
+    /// One JSON object per line, e.g. for journald.
+    Json,
+}
+
+/// Logger that writes records to stderr as JSON objects. With the `tracing`
+/// feature, the subscriber formats the JSON instead.
+#[cfg(not(feature = "tracing"))]
+struct JsonLogger {
+    level: LevelFilter,
+}
+
+#[cfg(not(feature = "tracing"))]
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let timestamp = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        let line = format_record(record, &timestamp);
+        let _ = writeln!(std::io::stderr().lock(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/// Format a log record as a JSON object.
+#[cfg(not(feature = "tracing"))]
+fn format_record(record: &Record, timestamp: &str) -> String {
+    json!({
+        "timestamp": timestamp,
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+    })
+    .to_string()
+}
+
+/// Logger that also appends warnings, errors and command acknowledgements to
+/// a diagnostics file, regardless of the level of the wrapped logger.
+#[cfg(not(feature = "tracing"))]
+struct DiagLogger {
+    inner: Box<dyn Log>,
+    file: Mutex<std::fs::File>,
+}
+
+#[cfg(not(feature = "tracing"))]
+impl Log for DiagLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        is_diag(metadata) || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.inner.log(record);
+        if !is_diag(record.metadata()) {
+            return;
+        }
+        let timestamp = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        if let Ok(mut file) = self.file.lock() {
+            let _ = writeln!(file, "{}", format_diag(record, &timestamp));
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// Record belongs in the diagnostics file.
+#[cfg(not(feature = "tracing"))]
+fn is_diag(metadata: &Metadata) -> bool {
+    metadata.level() <= Level::Warn || metadata.target() == COMMAND_TARGET
+}
+
+/// Format a log record as a line of the diagnostics file.
+#[cfg(not(feature = "tracing"))]
+fn format_diag(record: &Record, timestamp: &str) -> String {
+    format!(
+        "{} {:<5} [{}] {}",
+        timestamp,
+        record.level(),
+        record.target(),
+        record.args()
+    )
+}
+
+/// Install the logger in the given format, colored if requested, teeing
+/// warnings, errors and command acknowledgements into the diagnostics file
+/// if given. The `RUST_LOG`
+/// environment variable overrides the given level.
+#[cfg(not(feature = "tracing"))]
+pub fn init(
+    level: LevelFilter,
+    format: LogFormat,
+    colors: bool,
+    diag_file: Option<&Path>,
+) -> anyhow::Result<()> {
+    let (logger, level): (Box<dyn Log>, LevelFilter) = match format {
+        LogFormat::Plain => {
+            let logger = SimpleLogger::new()
+                .with_level(level)
+                .with_colors(colors)
+                .env();
+            let level = logger.max_level();
+            (Box::new(logger), level)
+        }
+        LogFormat::Json => {
+            let level = std::env::var("RUST_LOG")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(level);
+            (Box::new(JsonLogger { level }), level)
+        }
+    };
+    let (logger, level) = match diag_file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Could not open diagnostics file {}", path.display()))?;
+            let logger: Box<dyn Log> = Box::new(DiagLogger {
+                inner: logger,
+                file: Mutex::new(file),
+            });
+            (logger, level.max(LevelFilter::Info))
+        }
+        None => (logger, level),
+    };
+    log::set_boxed_logger(logger)?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+#[cfg(all(test, not(feature = "tracing")))]
+mod tests {
+    use log::{Level, Metadata, Record};
+    use test_case::test_case;
+
+    #[test]
+    fn format_diag() {
+        let record = Record::builder()
+            .level(Level::Error)
+            .target("cupcake")
+            .args(format_args!("Could not parse chat message payload"))
+            .build();
+        assert_eq!(
+            super::format_diag(&record, "2025-10-16T17:11:12.025Z"),
+            "2025-10-16T17:11:12.025Z ERROR [cupcake] Could not parse chat message payload"
+        );
+    }
+
+    #[test_case(Level::Warn, "cupcake", true ; "warning")]
+    #[test_case(Level::Info, "cupcake", false ; "info")]
+    #[test_case(Level::Info, crate::logger::COMMAND_TARGET, true ; "command acknowledgement")]
+    fn is_diag(level: Level, target: &str, expected: bool) {
+        let metadata = Metadata::builder().level(level).target(target).build();
+        assert_eq!(super::is_diag(&metadata), expected);
+    }
+
+    #[test]
+    fn format_record() {
+        let record = Record::builder()
+            .level(Level::Warn)
+            .target("cupcake")
+            .args(format_args!("Client disconnected from server"))
+            .build();
+        assert_eq!(
+            super::format_record(&record, "2025-10-16T17:11:12.025Z"),
+            "{\"level\":\"WARN\",\"message\":\"Client disconnected from server\",\
+            \"target\":\"cupcake\",\"timestamp\":\"2025-10-16T17:11:12.025Z\"}"
+        );
+    }
+}
@@ -1,207 +1,2199 @@
+mod archive;
+mod chain;
 mod channel;
+mod config;
 mod data;
+mod emotes;
+mod error;
+mod exec;
+mod filters;
+mod format;
+mod logger;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod pretty;
+mod sink;
+mod state;
+mod summary;
 mod utils;
+mod webhook;
 
+use anyhow::Context;
 use chrono::Utc;
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches};
 use futures_util::FutureExt;
-use rust_socketio::asynchronous::{Client, ClientBuilder};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use rust_socketio::asynchronous::{Client, ClientBuilder, ReconnectSettings};
 use rust_socketio::{Payload, TransportType};
 use serde_json::{Value, json};
-use simple_logger::SimpleLogger;
-use tokio::fs::File;
+use sink::MessageSink;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::fs::{File, OpenOptions};
 use tokio::io::{AsyncWriteExt, BufWriter};
 use tokio::select;
 use tokio::signal;
-use tokio::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::{Duration, Instant};
 use tokio_util::sync::CancellationToken;
+#[cfg(feature = "tracing")]
+use tracing::Instrument;
 
 const WRITE_BUFFER_SIZE: usize = 8 * 1024; // 8 KiB
+const BUFFER_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+const MAX_CONNECT_BACKOFF: Duration = Duration::from_secs(60);
+const RECONNECT_RESET_PERIOD: Duration = Duration::from_secs(5 * 60);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+/// Seconds that `cupcake check` waits for channel data unless --join-timeout is given.
+const CHECK_TIMEOUT: u64 = 30;
 
-#[derive(Parser, Debug)]
+/// List the public channels of a Cytube server with their viewer counts.
+#[derive(clap::Parser, Debug)]
+#[command(name = "cupcake list", bin_name = "cupcake list", version)]
+struct ListArgs {
+    /// Cytube server domain.
+    #[clap(value_parser = utils::parse_domain)]
+    domain: utils::Domain,
+
+    /// User agent to send when fetching the channel list.
+    #[clap(long, value_name = "STRING")]
+    user_agent: Option<String>,
+
+    /// Additional header to send when fetching the channel list.
+    ///
+    /// Can be given multiple times.
+    #[clap(long, value_name = "NAME:VALUE", value_parser = utils::parse_header)]
+    header: Vec<(HeaderName, HeaderValue)>,
+
+    /// Application logging level.
+    #[clap(long, value_name = "LEVEL", default_value_t = log::LevelFilter::Info)]
+    log_level: log::LevelFilter,
+}
+
+/// Verify the hash chain of a chat log written with --hash-chain.
+#[derive(clap::Parser, Debug)]
+#[command(name = "cupcake verify", bin_name = "cupcake verify", version)]
+struct VerifyArgs {
+    /// Chat log file.
+    file: std::path::PathBuf,
+}
+
+#[derive(clap::Parser, Debug)]
 #[command(version)]
 struct Args {
+    /// Read default option values from a TOML file.
+    ///
+    /// Supports domain, channel, guest_login, log_level, format, skip_class
+    /// and apply_filters. Options given on the command line take precedence.
+    #[clap(long, value_name = "FILE")]
+    config: Option<std::path::PathBuf>,
+
     /// Cytube server domain.
-    #[clap(value_parser = utils::parse_domain)]
-    domain: url::Host,
+    ///
+    /// Can also be given as a URL to use a scheme other than HTTPS
+    /// or a non-default port, e.g. http://localhost:8080.
+    // Not required by the parser, as --print-schema and --completions are
+    // given without it, but checked before the arguments are built.
+    #[clap(value_parser = utils::parse_domain, required = false)]
+    domain: utils::Domain,
+
+    /// Print a sample JSON chat record and exit.
+    #[clap(long, hide = true)]
+    print_schema: bool,
+
+    /// Print a completion script for the given shell and exit.
+    #[clap(long, hide = true, value_name = "SHELL", value_enum)]
+    completions: Option<clap_complete::Shell>,
 
     /// Cytube channel name.
+    ///
+    /// Can be left out if the domain is a channel URL, e.g. https://cytu.be/r/test.
+    /// A /r/ or /c/ prefix is stripped, as channels are joined by their bare name.
+    #[clap(default_value = "", hide_default_value = true, value_parser = utils::parse_channel)]
     channel: String,
 
     /// Application logging level.
     #[clap(long, value_name = "LEVEL", default_value_t = log::LevelFilter::Info)]
     log_level: log::LevelFilter,
 
+    /// Only log errors. The capture summary is still printed unless --no-summary is given.
+    #[clap(long, conflicts_with = "log_level")]
+    quiet: bool,
+
+    /// Format of cupcake's own logs, separate from the chat log format.
+    #[clap(long, value_name = "FORMAT", value_enum, default_value_t)]
+    log_format: logger::LogFormat,
+
+    /// Also append cupcake's own warnings, errors and command acknowledgements to
+    /// this file, regardless of --log-level.
+    #[clap(long, value_name = "PATH")]
+    diag_file: Option<std::path::PathBuf>,
+
     /// Join as guest with the given name.
     ///
     /// This prevents receiving messages from shadow-banned users and
     /// makes cupcake visible in the cytube channel's member list.
     /// Username must be unique and non-registered for the option to work.
-    #[clap(long, value_name = "USERNAME")]
+    #[clap(long, value_name = "USERNAME", conflicts_with = "username")]
     guest_login: Option<String>,
 
+    /// Number of times to retry --guest-login with a numbered name, such as
+    /// cupcake1, if the name is taken.
+    #[clap(long, value_name = "COUNT", default_value_t = 3)]
+    login_retries: u32,
+
+    /// Log in as the given registered user.
+    ///
+    /// Requires --password or --password-env.
+    #[clap(long, value_name = "USERNAME")]
+    username: Option<String>,
+
+    /// Password for --username.
+    ///
+    /// Prefer --password-env to keep the password out of the shell history and process list.
+    #[clap(long, value_name = "PASSWORD", requires = "username")]
+    password: Option<utils::Secret>,
+
+    /// Read the password for --username from the given environment variable.
+    #[clap(
+        long,
+        value_name = "VARNAME",
+        requires = "username",
+        conflicts_with = "password"
+    )]
+    password_env: Option<String>,
+
+    /// Do not log in again when the session of --username expires and the
+    /// server downgrades the connection to a guest.
+    #[clap(long, requires = "username")]
+    no_reauthenticate: bool,
+
+    /// Mark the logged-in user as AFK after every login, so that it is not
+    /// counted as an eligible voter in the voteskip tallies it may be logging.
+    ///
+    /// This is visible to the channel: the user is shown as AFK in the user
+    /// list. Requires --username or --guest-login, as anonymous users cannot
+    /// use chat commands.
+    #[clap(long)]
+    afk: bool,
+
+    /// Write each logged chat message as a line in the --format to the
+    /// standard input of a long-running shell command.
+    ///
+    /// E.g. for sending notifications or running scripts on new messages.
+    #[clap(long, value_name = "COMMAND")]
+    exec: Option<String>,
+
+    /// Restart the --exec command on the next message if it exits.
+    #[clap(long, requires = "exec")]
+    exec_restart: bool,
+
+    /// Send each logged chat message as a JSON POST request to the given URL.
+    #[clap(long, value_name = "URL")]
+    webhook: Option<utils::Secret>,
+
+    /// Read the webhook URL from the given environment variable.
+    #[clap(long, value_name = "VARNAME", conflicts_with = "webhook")]
+    webhook_env: Option<String>,
+
+    /// Where to write the chat log.
+    ///
+    /// Can be given multiple times to write to several outputs.
+    #[clap(long, value_enum, default_value = "file")]
+    sink: Vec<sink::SinkKind>,
+
+    /// Connect and process messages as usual, but only log them instead of writing any
+    /// files, including the --diag-file.
+    #[clap(long)]
+    dry_run: bool,
+
+    /// File format of the chat log.
+    #[clap(long, value_enum, default_value_t)]
+    format: format::OutputFormat,
+
+    /// Start each chat log file with a header describing the server and channel.
+    ///
+    /// TSV logs get `#`-prefixed comment lines, ndjson logs a single `_meta` object.
+    #[clap(long)]
+    header_comment: bool,
+
+    /// Add a running SHA-256 hash chain to every chat log line for tamper evidence.
+    ///
+    /// Requires the tsv or ndjson format. An existing --output file is appended
+    /// to, continuing its chain. Check a log with `cupcake verify <FILE>`.
+    #[clap(long)]
+    hash_chain: bool,
+
+    /// Keep the time of the last written message and the total message and
+    /// byte counts in a JSON file across restarts.
+    ///
+    /// Messages up to the last written one are skipped as already captured,
+    /// and the shutdown summary includes the totals of all sessions.
+    #[clap(long, value_name = "FILE")]
+    state_file: Option<std::path::PathBuf>,
+
+    /// Start the --state-file over from zero.
+    #[clap(long, requires = "state_file")]
+    reset_state: bool,
+
+    /// Write chat log files under a .tmp name and rename them into place when
+    /// finished, on rotation or clean exit.
+    ///
+    /// Watchers of the output directory never see a partially written file.
+    /// Has no effect on FIFOs or standard output.
+    #[clap(long)]
+    atomic: bool,
+
+    /// Compress each finished chat log file into a .tar.gz archive with a
+    /// manifest of the channel, domain and message count.
+    ///
+    /// Files are archived when they are rotated or on clean exit.
+    #[clap(long)]
+    archive: bool,
+
+    /// Delete chat log files once they were archived.
+    #[clap(long, requires = "archive")]
+    archive_delete: bool,
+
+    /// Octal Unix permissions of newly created chat log, archive, --raw-log and
+    /// --log-whispers files, e.g. 600 to keep them private. Ignored on other platforms.
+    #[clap(long, value_name = "MODE", value_parser = utils::parse_file_mode)]
+    file_mode: Option<u32>,
+
+    /// Collect up to this many records and write them at once, reducing
+    /// writes on busy channels. Standard output and FIFOs are flushed per batch.
+    #[clap(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    batch_size: Option<u64>,
+
+    /// Write a batch that is not full after this long, e.g. "500ms".
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = utils::parse_interval,
+        default_value = "1s",
+        requires = "batch_size"
+    )]
+    batch_interval: Duration,
+
+    /// Write a heartbeat notice to the chat output at this interval, e.g. "1m",
+    /// so that external monitoring can tell a quiet channel from a stuck log.
+    #[clap(long, value_name = "DURATION", value_parser = utils::parse_interval)]
+    heartbeat_line: Option<Duration>,
+
+    /// Prefix each logged message with a sequence number that increases by one per written message.
+    ///
+    /// Gaps in the sequence reveal lines that were lost downstream.
+    /// In ndjson logs the number is stored in a `seq` field instead.
+    #[clap(long)]
+    with_seq: bool,
+
     /// Rotate the chat log file after a certain number of hours.
     #[clap(long, value_name = "HOURS")]
     rotate_file: Option<u64>,
+
+    /// Write the chat log to the given path instead of a timestamped file.
+    ///
+    /// The path can be a named pipe (FIFO) for streaming into another process.
+    #[clap(long, value_name = "PATH", conflicts_with = "rotate_file")]
+    output: Option<std::path::PathBuf>,
+
+    /// PEM client certificate for servers that require mutual TLS for the socket config.
+    #[clap(long, value_name = "PEM", requires = "client_key")]
+    client_cert: Option<std::path::PathBuf>,
+
+    /// PKCS#8 PEM private key of --client-cert.
+    #[clap(long, value_name = "PEM", requires = "client_cert")]
+    client_key: Option<std::path::PathBuf>,
+
+    /// Connect to the server at this index of the socket config server list instead of the first one.
+    #[clap(long, value_name = "INDEX", conflicts_with = "server_host")]
+    server_index: Option<usize>,
+
+    /// Connect to the server with this hostname from the socket config server list.
+    #[clap(long, value_name = "HOST")]
+    server_host: Option<String>,
+
+    /// User agent to send when fetching the socket config.
+    #[clap(long, value_name = "STRING")]
+    user_agent: Option<String>,
+
+    /// Additional header to send when fetching the socket config.
+    ///
+    /// Can be given multiple times. Useful for instances behind a WAF
+    /// that require e.g. a Cloudflare clearance cookie.
+    #[clap(long, value_name = "NAME:VALUE", value_parser = utils::parse_header)]
+    header: Vec<(HeaderName, HeaderValue)>,
+
+    /// HTTP Basic auth credentials to send when fetching the socket config.
+    ///
+    /// Only needed for instances that protect the config endpoint, and
+    /// unrelated to logging in to Cytube with --username.
+    #[clap(long, value_name = "USER:PASS", value_parser = utils::parse_basic_auth)]
+    config_auth: Option<(String, utils::Secret)>,
+
+    /// Path of the socket config on the server, with {channel} replaced by the channel name.
+    #[clap(
+        long,
+        value_name = "TEMPLATE",
+        default_value = "/socketconfig/{channel}.json"
+    )]
+    config_path_template: String,
+
+    /// Fetch the socket config through a Unix domain socket instead of TCP.
+    ///
+    /// Only the config lookup uses the socket: the Socket.IO client does not
+    /// support Unix sockets, so the socket server itself must still be
+    /// reachable over TCP.
+    #[cfg(unix)]
+    #[clap(long, value_name = "PATH", value_parser = utils::parse_unix_socket)]
+    unix_socket: Option<std::path::PathBuf>,
+
+    /// Local IP address to send the socket config lookup from.
+    ///
+    /// The Socket.IO client cannot bind to a local address, so connecting
+    /// fails unless --bind-lookup-only is given as well.
+    #[clap(long, value_name = "IP")]
+    bind_address: Option<std::net::IpAddr>,
+
+    /// Only bind the socket config lookup to --bind-address and connect to the
+    /// Socket.IO server from the default address.
+    #[clap(long, requires = "bind_address")]
+    bind_lookup_only: bool,
+
+    /// Replace part of the socket server's host before connecting.
+    ///
+    /// Can be given multiple times. Useful when the socket config advertises
+    /// an internal host that is not reachable, e.g. internal.example.com=example.com.
+    #[clap(long, value_name = "FROM=TO", value_parser = utils::parse_host_rewrite)]
+    rewrite_host: Vec<(String, String)>,
+
+    /// Transport to use for the socket connection.
+    #[clap(long, value_enum, default_value_t)]
+    transport: Transport,
+
+    /// Socket.IO namespace to connect to.
+    #[clap(long, value_name = "NAMESPACE", default_value = "/", value_parser = utils::parse_namespace)]
+    namespace: String,
+
+    /// Number of times to retry the initial connection to the socket server.
+    #[clap(long, value_name = "COUNT", default_value_t = 3)]
+    connect_retries: u32,
+
+    /// Reconnect if no events are received from the server within the given duration, e.g. "5m".
+    #[clap(long, value_name = "DURATION", value_parser = utils::parse_interval)]
+    idle_timeout: Option<Duration>,
+
+    /// Flush the chat log and exit when disconnected from the server instead of staying alive.
+    #[clap(long, conflicts_with = "idle_timeout")]
+    exit_on_disconnect: bool,
+
+    /// Exit with an error after this many reconnects, or never if 0.
+    ///
+    /// The count is reset after staying connected for 5 minutes.
+    #[clap(long, value_name = "COUNT", default_value_t = 0)]
+    max_reconnects: u32,
+
+    /// Write notices about disconnects and reconnects to the chat log.
+    #[clap(long)]
+    mark_reconnects: bool,
+
+    /// Skip messages with the given message class.
+    ///
+    /// Can be given multiple times. Giving any classes replaces the default
+    /// of skipping server whispers.
+    #[clap(long, value_name = "CLASS", default_value = "server-whisper")]
+    skip_class: Vec<String>,
+
+    /// Skip chat bot commands, i.e. messages starting with --command-prefix.
+    #[clap(long)]
+    skip_commands: bool,
+
+    /// Prefix of chat bot commands for --skip-commands.
+    #[clap(
+        long,
+        value_name = "PREFIX",
+        default_value = "!",
+        requires = "skip_commands"
+    )]
+    command_prefix: String,
+
+    /// Skip messages sent by the given user, such as a chat bot.
+    ///
+    /// Can be given multiple times.
+    #[clap(long, value_name = "USERNAME")]
+    skip_bot: Vec<String>,
+
+    /// Keep server whispers such as voteskip tallies in the chat log.
+    #[clap(long)]
+    keep_server_whispers: bool,
+
+    /// Apply the channel's chat filters to message text before logging.
+    #[clap(long)]
+    apply_filters: bool,
+
+    /// Replace channel emote names in message text with their image URLs.
+    #[clap(long)]
+    expand_emotes: bool,
+
+    /// Read additional emotes for --expand-emotes from a JSON file of {"name": "url"} pairs.
+    ///
+    /// Emotes received from the channel take precedence over the file.
+    #[clap(long, value_name = "FILE", requires = "expand_emotes")]
+    emote_file: Option<std::path::PathBuf>,
+
+    /// Apply Unicode normalization to message text.
+    ///
+    /// Text is captured exactly as received unless a form is given.
+    #[clap(long, value_name = "FORM", value_enum)]
+    normalize: Option<utils::Normalization>,
+
+    /// Truncate message text to the given number of characters, marking cut text with an ellipsis.
+    #[clap(long, value_name = "N")]
+    max_text_len: Option<usize>,
+
+    /// Add the links to the given host to a links field of JSON output.
+    ///
+    /// Can be given multiple times. Hosts are compared exactly, ignoring case,
+    /// so subdomains need to be given separately.
+    #[clap(long, value_name = "DOMAIN")]
+    link_host: Vec<String>,
+
+    /// Write server-side rate limiting notices to the chat log.
+    #[clap(long)]
+    log_rate_limits: bool,
+
+    /// Write notices about chat messages deleted by moderators to the chat log.
+    ///
+    /// Logged messages are left as is; the notice references the deleted
+    /// message by its timestamp, sender and a 64-bit FNV-1a hash of its text
+    /// as written to the chat log.
+    #[clap(long)]
+    log_deletions: bool,
+
+    /// Write site-wide announcements by the server administrators to the chat log.
+    #[clap(long)]
+    log_announcements: bool,
+
+    /// Log acknowledgements of chat commands, such as polls opened and closed
+    /// with /poll, to cupcake's own logs and the --diag-file.
+    #[clap(long)]
+    log_command_acks: bool,
+
+    /// Drop the chat history that the server replays when joining.
+    ///
+    /// Messages are considered history if their timestamp is older than the
+    /// moment cupcake joined, so a server clock that runs behind may cause
+    /// the first live messages to be dropped as well. With --from-dump, the
+    /// time of the first event in the dump is used as the moment of joining.
+    #[clap(long)]
+    skip_history: bool,
+
+    /// Write every received message, including ones that repeat an earlier
+    /// message or are older than the last message.
+    ///
+    /// The server replays recent messages when reconnecting, so these are
+    /// duplicated in the chat log after a reconnect.
+    #[clap(long)]
+    no_dedup: bool,
+
+    /// Do not log the capture summary on shutdown.
+    #[clap(long)]
+    no_summary: bool,
+
+    /// Print chat messages to stderr in a readable, colorized format.
+    #[clap(long)]
+    pretty: bool,
+
+    /// When to color --pretty output and cupcake's own logs. "auto" colors
+    /// only on a terminal and when NO_COLOR is not set.
+    #[clap(long, value_name = "WHEN", value_enum, default_value_t)]
+    color: pretty::ColorChoice,
+
+    /// Template for the --pretty output and the per-message log line.
+    ///
+    /// Supports {time}, {username}, {text} and {team} placeholders,
+    /// e.g. "{time} <{username}> {text}".
+    #[clap(long, value_name = "TEMPLATE")]
+    line_template: Option<String>,
+
+    /// Serve Prometheus metrics on the given address.
+    #[cfg(feature = "metrics")]
+    #[clap(long, value_name = "HOST:PORT")]
+    metrics_addr: Option<std::net::SocketAddr>,
+
+    /// Log the number of connected users to a separate usercount-<CHANNEL> file.
+    #[clap(long)]
+    log_usercount: bool,
+
+    /// Minimum number of seconds between logging unchanged user counts.
+    #[clap(long, value_name = "SECONDS", default_value_t = 60)]
+    usercount_interval: u64,
+
+    /// Write the raw payloads of all handled socket events to the given file as ndjson.
+    ///
+    /// Private messages are only included with --log-whispers.
+    #[clap(long, value_name = "FILE")]
+    raw_log: Option<std::path::PathBuf>,
+
+    /// Process the events of a --raw-log file instead of connecting to the server.
+    ///
+    /// The domain and channel are still used for naming and describing the
+    /// output. Exits once all events have been processed.
+    #[clap(long, value_name = "FILE", conflicts_with = "idle_timeout")]
+    from_dump: Option<std::path::PathBuf>,
+
+    /// Stop capturing after the given duration, e.g. "2h30m", counted from connecting.
+    #[clap(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    duration: Option<Duration>,
+
+    /// Stop capturing after writing the given number of chat messages.
+    #[clap(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_messages: Option<u64>,
+
+    /// Count written messages whose text matches the regular expression and
+    /// report the counts in the shutdown summary. Can be given multiple times.
+    #[clap(long, value_name = "REGEX", value_parser = regex::Regex::new)]
+    count_pattern: Vec<regex::Regex>,
+
+    /// Exit if no channel data is received within the given number of seconds after joining.
+    ///
+    /// Helps to detect misspelled or otherwise unjoinable channels.
+    #[clap(long, value_name = "SECONDS")]
+    join_timeout: Option<u64>,
+
+    /// Log played media to a separate media-<CHANNEL>.ndjson file.
+    #[clap(long)]
+    log_media: bool,
+
+    /// Log the playback position of the current media to a separate playback-<CHANNEL> ndjson file.
+    ///
+    /// Positions are logged when playback is paused or resumed, and otherwise
+    /// at most once per --playback-interval.
+    #[clap(long)]
+    log_playback: bool,
+
+    /// Minimum number of seconds between logging playback positions while playback is unchanged.
+    #[clap(long, value_name = "SECONDS", default_value_t = 30)]
+    playback_interval: u64,
+
+    /// Log voteskip tallies to a separate voteskip-<CHANNEL> ndjson file.
+    ///
+    /// Voteskip server whispers are still skipped from the chat log unless
+    /// --keep-server-whispers is given.
+    #[clap(long)]
+    log_voteskip: bool,
+
+    /// Log private messages sent to the logged-in user to a separate
+    /// whispers-<CHANNEL> ndjson file.
+    ///
+    /// Whispers are only written to this file and never to cupcake's own
+    /// logs, as they may contain sensitive information.
+    #[clap(long)]
+    log_whispers: bool,
+
+    /// Log the difference between the server timestamp and the local receive
+    /// time of each message to a separate skew-<CHANNEL> file.
+    ///
+    /// Lines contain the server timestamp, the receive time and the
+    /// difference in milliseconds, separated by tabs.
+    #[clap(long)]
+    log_skew: bool,
 }
 
-#[derive(Debug)]
+/// Transport used for the Socket.IO connection.
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+enum Transport {
+    /// Polling upgraded to WebSocket if possible.
+    #[default]
+    Any,
+    /// WebSocket only.
+    Websocket,
+    /// HTTP long-polling only.
+    Polling,
+}
+
+impl From<Transport> for TransportType {
+    fn from(transport: Transport) -> Self {
+        match transport {
+            Transport::Any => TransportType::Any,
+            Transport::Websocket => TransportType::Websocket,
+            Transport::Polling => TransportType::Polling,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 enum Event {
-    Chat(Vec<Value>),
-    Disconnect,
+    AddUser(Vec<Value>),
+    Announcement(Vec<Value>),
+    ChangeMedia(Vec<Value>),
+    /// Chat messages and the local time in milliseconds when they were received.
+    Chat(Vec<Value>, i64),
+    ChatFilters(Vec<Value>),
+    ClosePoll,
+    Connect,
+    Cooldown(Vec<Value>),
+    Delete(Vec<Value>),
+    Disconnect(data::DisconnectReason),
+    EmoteList(Vec<Value>),
+    Error(Vec<Value>),
+    ErrorMsg(Vec<Value>),
+    Kick(Vec<Value>),
     Login(Vec<Value>),
+    MediaUpdate(Vec<Value>),
+    NewPoll(Vec<Value>),
+    /// Socket event to write to the --raw-log file, stamped with the time
+    /// it was received.
+    Raw(data::RawRecord),
+    /// Reconnecting after a disconnect by the server failed.
+    ReconnectFailed,
+    /// The Socket.IO client is attempting to reconnect after losing the connection.
+    Reconnecting,
     RotateLog,
+    SetAfk(Vec<Value>),
+    SetMotd,
+    SetUserMeta(Vec<Value>),
+    SetUserRank(Vec<Value>),
+    SpamFiltered(Vec<Value>),
     Terminate,
+    UpdatePoll(Vec<Value>),
+    UserCount(u32),
+    UserLeave(Vec<Value>),
+    UserList(Vec<Value>),
+    Whisper(Vec<Value>),
+}
+
+impl Event {
+    /// Event is scoped to the joined channel, confirming that the join succeeded.
+    fn is_channel_event(&self) -> bool {
+        matches!(
+            self,
+            Event::ChangeMedia(_) | Event::Chat(..) | Event::SetMotd | Event::UserList(_)
+        )
+    }
+
+    /// Event for a Socket.IO event received from the server, or None if the
+    /// event is not handled. Chat messages get the given received time.
+    fn from_raw(name: &str, values: Vec<Value>, received: i64) -> Option<Event> {
+        let event = match name {
+            "addUser" => Event::AddUser(values),
+            "announcement" => Event::Announcement(values),
+            "changeMedia" => Event::ChangeMedia(values),
+            "chatMsg" => Event::Chat(values, received),
+            "chatFilters" => Event::ChatFilters(values),
+            "closePoll" => Event::ClosePoll,
+            "cooldown" => Event::Cooldown(values),
+            "delete" => Event::Delete(values),
+            "emoteList" => Event::EmoteList(values),
+            "error" => Event::Error(values),
+            "errorMsg" => Event::ErrorMsg(values),
+            "kick" => Event::Kick(values),
+            "login" => Event::Login(values),
+            "mediaUpdate" => Event::MediaUpdate(values),
+            "newPoll" => Event::NewPoll(values),
+            "pm" => Event::Whisper(values),
+            "setAFK" => Event::SetAfk(values),
+            "setMotd" => Event::SetMotd,
+            "setUserMeta" => Event::SetUserMeta(values),
+            "setUserRank" => Event::SetUserRank(values),
+            "spamFiltered" => Event::SpamFiltered(values),
+            "updatePoll" => Event::UpdatePoll(values),
+            "userLeave" => Event::UserLeave(values),
+            "userlist" => Event::UserList(values),
+            "usercount" => {
+                let count = values.first().and_then(Value::as_u64);
+                match count.and_then(|count| u32::try_from(count).ok()) {
+                    Some(count) => Event::UserCount(count),
+                    None => {
+                        log::warn!("Could not parse user count from {}", Value::Array(values));
+                        return None;
+                    }
+                }
+            }
+            _ => return None,
+        };
+        Some(event)
+    }
 }
 
+#[derive(Debug)]
 enum SocketAddressError {
     NotFound,
+    NoSuchServer(data::ServerSelection, Vec<String>),
     Parse(serde_json::Error),
     Request(reqwest::Error),
 }
 
-async fn create_chat_log_file(channel: &str) -> File {
-    let filename = format!(
-        "chat-{}-{}Z.txt",
-        channel,
-        Utc::now().format("%Y%m%dT%H%M%S")
-    );
+impl std::fmt::Display for SocketAddressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SocketAddressError::NotFound => {
+                write!(f, "Failed to find socket address in Cytube socket config")
+            }
+            SocketAddressError::NoSuchServer(selection, available) => {
+                write!(
+                    f,
+                    "No {} in Cytube socket config, available servers: {}",
+                    selection,
+                    available.join(", ")
+                )
+            }
+            SocketAddressError::Parse(e) => {
+                write!(f, "Failed to parse Cytube socket config: {}", e)
+            }
+            SocketAddressError::Request(e) if e.is_timeout() => {
+                write!(
+                    f,
+                    "Timed out fetching Cytube socket config after {} seconds",
+                    HTTP_TIMEOUT.as_secs()
+                )
+            }
+            SocketAddressError::Request(e) => {
+                write!(f, "Failed to fetch Cytube socket config: {}", e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SocketAddressError {}
+
+/// Create a new timestamped log file of the given kind.
+async fn create_log_file(kind: &str, channel: &str, extension: &str) -> anyhow::Result<File> {
+    let filename = log_file_name(kind, channel, extension);
     let file = File::create(&filename)
         .await
-        .expect("Could not create output file");
-    log::info!("Created chat log file {}", filename);
-    file
+        .with_context(|| format!("Could not create output file {}", filename))?;
+    log::info!("Created {} log file {}", kind, filename);
+    Ok(file)
+}
+
+/// Timestamped name of a new log file.
+fn log_file_name(kind: &str, channel: &str, extension: &str) -> String {
+    format!(
+        "{}-{}-{}Z.{}",
+        kind,
+        channel,
+        Utc::now().format("%Y%m%dT%H%M%S"),
+        extension
+    )
+}
+
+/// Open the media timeline file for appending.
+async fn open_media_log_file(channel: &str) -> anyhow::Result<File> {
+    let filename = format!("media-{}.ndjson", channel);
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&filename)
+        .await
+        .with_context(|| format!("Could not open media log file {}", filename))?;
+    log::info!("Opened media log file {}", filename);
+    Ok(file)
 }
 
-fn handle_login_event(values: Vec<Value>) {
+/// Start an HTTP client with the request timeout, user agent and
+/// additional headers shared by all server requests.
+fn http_client_builder(
+    user_agent: Option<&str>,
+    header: &[(HeaderName, HeaderValue)],
+) -> reqwest::ClientBuilder {
+    let mut headers = HeaderMap::new();
+    for (name, value) in header {
+        headers.append(name, value.clone());
+    }
+    let builder = reqwest::Client::builder()
+        .default_headers(headers)
+        .timeout(HTTP_TIMEOUT);
+    match user_agent {
+        Some(user_agent) => builder.user_agent(user_agent),
+        None => builder,
+    }
+}
+
+/// Build the HTTP client used for fetching the Cytube socket config. It is
+/// created once and reused for the lookups done when reconnecting.
+fn build_http_client(args: &Args) -> anyhow::Result<reqwest::Client> {
+    let identity = match (&args.client_cert, &args.client_key) {
+        (Some(cert), Some(key)) => Some(utils::load_identity(cert, key)?),
+        _ => None,
+    };
+    let mut builder = http_client_builder(args.user_agent.as_deref(), &args.header);
+    if let Some(address) = args.bind_address {
+        builder = builder.local_address(address);
+    }
+    if let Some(identity) = identity {
+        builder = builder.identity(identity);
+    }
+    #[cfg(unix)]
+    if let Some(path) = &args.unix_socket {
+        builder = builder.unix_socket(path.as_path());
+    }
+    builder.build().context("Failed to create HTTP client")
+}
+
+/// Append media changes to the media timeline file.
+async fn handle_change_media_event(
+    values: Vec<Value>,
+    file: &mut File,
+    last_media: &mut Option<data::MediaEntry>,
+) {
     for value in values {
-        let login: data::Login = match serde_json::from_value(value) {
+        let media: data::Media = match serde_json::from_value(value) {
             Ok(v) => v,
             Err(e) => {
-                log::error!("Could not parse login payload: {}", e);
+                log::error!("Could not parse media payload: {}", e);
                 continue;
             }
         };
+        let entry = data::MediaEntry::new(media, Utc::now().timestamp_millis());
+        if let Some(previous) = last_media
+            && entry.is_repeat_of(previous)
+        {
+            log::debug!("Ignoring repeated media change for {}", entry.id);
+            continue;
+        }
 
-        if login.success {
-            log::info!(
-                "Logged in as guest {}",
-                login.name.unwrap_or("Unknown".into())
-            );
-        } else {
-            log::warn!(
-                "Login failed: {}",
-                login.error.unwrap_or("Unknown error".into())
-            );
+        let line = match serde_json::to_string(&entry) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("Could not serialize media entry: {}", e);
+                continue;
+            }
+        };
+        match file.write_all(format!("{}\n", line).as_bytes()).await {
+            Ok(_) => log::debug!("Media changed to {}", entry.title),
+            Err(e) => log::warn!("Failed to write media entry to file: {}", e),
+        };
+        *last_media = Some(entry);
+    }
+}
+
+/// Append playback positions to the playback log, throttled to pause and
+/// play transitions and one entry per interval while unchanged.
+async fn handle_media_update_event(
+    values: Vec<Value>,
+    file: &mut File,
+    throttle: &mut utils::SampleThrottle<bool>,
+) {
+    for value in values {
+        let update: data::MediaUpdate = match serde_json::from_value(value) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("Could not parse media update payload: {}", e);
+                continue;
+            }
+        };
+        if !throttle.should_log(update.paused, std::time::Instant::now()) {
+            continue;
         }
+        let entry = data::PlaybackEntry::new(update, Utc::now().timestamp_millis());
+        let line = match serde_json::to_string(&entry) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("Could not serialize playback entry: {}", e);
+                continue;
+            }
+        };
+        match file.write_all(format!("{}\n", line).as_bytes()).await {
+            Ok(_) => log::debug!("Playback at {} seconds", entry.current_time),
+            Err(e) => log::warn!("Failed to write playback entry to file: {}", e),
+        };
     }
 }
 
-/// Join a channel on the Cytube server.
-async fn join_channel(client: &Client, channel_name: &str) {
-    match client
-        .emit("joinChannel", json!({"name": channel_name}))
-        .await
-    {
-        Ok(_) => log::info!("Joined channel {}", channel_name),
+/// Write a voteskip tally to the voteskip log file.
+async fn write_voteskip(file: &mut File, voteskip: &data::Voteskip) {
+    let line = match serde_json::to_string(voteskip) {
+        Ok(v) => v,
         Err(e) => {
-            log::error!("Could not join channel {}: {}", channel_name, e);
+            log::error!("Could not serialize voteskip tally: {}", e);
+            return;
         }
     };
+    match file.write_all(format!("{}\n", line).as_bytes()).await {
+        Ok(_) => log::debug!(
+            "Logged voteskip tally of {}/{}",
+            voteskip.skipped,
+            voteskip.eligible
+        ),
+        Err(e) => log::warn!("Failed to write voteskip tally to file: {}", e),
+    };
 }
 
-/// Login as a guest user on the Cytube server.
-async fn login_as_guest(client: &Client, name: &str) {
-    match client.emit("login", json!({"name": name})).await {
-        Ok(_) => log::debug!("Login request sent"),
+/// Parse and compile the channel chat filters.
+fn handle_chat_filters_event(values: Vec<Value>) -> Option<Vec<filters::CompiledFilter>> {
+    let value = values.into_iter().next()?;
+    match serde_json::from_value::<Vec<data::ChatFilter>>(value) {
+        Ok(filters) => {
+            let compiled = filters::compile(filters);
+            log::debug!("Received {} active chat filters", compiled.len());
+            Some(compiled)
+        }
         Err(e) => {
-            log::error!("Could not send login request: {}", e);
+            log::error!("Could not parse chat filters: {}", e);
+            None
+        }
+    }
+}
+
+/// Parse the channel emote list.
+fn handle_emote_list_event(values: Vec<Value>) -> Option<Vec<data::Emote>> {
+    let value = values.into_iter().next()?;
+    match serde_json::from_value::<Vec<data::Emote>>(value) {
+        Ok(emotes) => {
+            log::debug!("Received {} channel emotes", emotes.len());
+            Some(emotes)
+        }
+        Err(e) => {
+            log::error!("Could not parse emote list: {}", e);
+            None
+        }
+    }
+}
+
+/// Parse the poll of a `newPoll` or `updatePoll` event.
+fn parse_poll(values: Vec<Value>) -> Option<data::Poll> {
+    let value = values.into_iter().next()?;
+    match serde_json::from_value(value) {
+        Ok(poll) => Some(poll),
+        Err(e) => {
+            log::error!("Could not parse poll payload: {}", e);
+            None
+        }
+    }
+}
+
+/// Warn about server-side rate limiting and optionally note it in the chat log.
+async fn handle_rate_limits(rate_limits: Vec<data::RateLimit>, sink: Option<&mut sink::MultiSink>) {
+    for rate_limit in &rate_limits {
+        log::warn!("Rate limited by server: {}", rate_limit);
+    }
+    let Some(sink) = sink else {
+        return;
+    };
+    for rate_limit in rate_limits {
+        let time = Utc::now().timestamp_millis();
+        let detail = rate_limit.to_string();
+        if let Err(e) = sink.write_notice(time, "rateLimit", &detail).await {
+            log::warn!("{:#}", e);
         }
+    }
+}
+
+/// Log site-wide announcements and optionally write them to the chat log.
+async fn handle_announcement_event(values: Vec<Value>, sink: Option<&mut sink::MultiSink>) {
+    let announcements: Vec<data::Announcement> = values
+        .into_iter()
+        .filter_map(|value| match serde_json::from_value(value) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                log::error!("Could not parse announcement payload: {}", e);
+                None
+            }
+        })
+        .collect();
+    for announcement in &announcements {
+        log::info!(
+            "Announcement: {}: {}",
+            announcement.title,
+            announcement.text
+        );
+    }
+    let Some(sink) = sink else {
+        return;
     };
+    for announcement in announcements {
+        let time = Utc::now().timestamp_millis();
+        let detail = format!("{}: {}", announcement.title, announcement.text);
+        if let Err(e) = sink.write_notice(time, "announcement", &detail).await {
+            log::warn!("{:#}", e);
+        }
+    }
+}
+
+/// Log deleted chat messages and optionally note them in the chat log.
+async fn handle_delete_event(values: Vec<Value>, sink: Option<&mut sink::MultiSink>) {
+    let deletions: Vec<data::ChatDeletion> = values
+        .into_iter()
+        .filter_map(|value| match serde_json::from_value(value) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                log::error!("Could not parse message deletion payload: {}", e);
+                None
+            }
+        })
+        .collect();
+    for deletion in &deletions {
+        log::info!(
+            "Message from {} at {} was deleted",
+            deletion.username,
+            deletion.time
+        );
+    }
+    let Some(sink) = sink else {
+        return;
+    };
+    for deletion in deletions {
+        let time = Utc::now().timestamp_millis();
+        if let Err(e) = sink
+            .write_notice(time, "delete", &deletion.reference())
+            .await
+        {
+            log::warn!("{:#}", e);
+        }
+    }
+}
+
+/// Note a change in the connection state in the chat log to explain gaps in it.
+async fn mark_connection(sink: &mut sink::MultiSink, event: &str, detail: &str) {
+    let time = Utc::now().timestamp_millis();
+    if let Err(e) = sink.write_notice(time, event, detail).await {
+        log::warn!("{:#}", e);
+    }
+}
+
+/// Write a heartbeat notice and flush it so that the output keeps growing
+/// while the channel is quiet. Not counted as a message or deduplicated.
+async fn write_heartbeat(sink: &mut sink::MultiSink, interval: Duration) {
+    let time = Utc::now().timestamp_millis();
+    let detail = humantime::format_duration(interval).to_string();
+    if let Err(e) = sink.write_notice(time, "heartbeat", &detail).await {
+        log::warn!("{:#}", e);
+    }
+    sink.flush().await;
+}
+
+/// Replace the tracked user ranks with the full channel user list.
+fn handle_user_list_event(values: Vec<Value>, ranks: &mut HashMap<String, data::Rank>) {
+    let Some(value) = values.into_iter().next() else {
+        return;
+    };
+    match serde_json::from_value::<Vec<data::User>>(value) {
+        Ok(users) => {
+            ranks.clear();
+            ranks.extend(users.into_iter().map(|user| (user.name, user.rank)));
+            log::debug!("Received user list with {} users", ranks.len());
+        }
+        Err(e) => log::error!("Could not parse user list: {}", e),
+    }
+}
+
+fn handle_add_user_event(values: Vec<Value>, ranks: &mut HashMap<String, data::Rank>) {
+    for value in values {
+        match serde_json::from_value::<data::User>(value) {
+            Ok(user) => {
+                log::debug!("User {} joined as {}", user.name, user.rank);
+                ranks.insert(user.name, user.rank);
+            }
+            Err(e) => log::error!("Could not parse user payload: {}", e),
+        }
+    }
+}
+
+fn handle_user_leave_event(values: Vec<Value>, ranks: &mut HashMap<String, data::Rank>) {
+    for value in values {
+        match serde_json::from_value::<data::UserLeave>(value) {
+            Ok(user) => {
+                log::debug!("User {} left", user.name);
+                ranks.remove(&user.name);
+            }
+            Err(e) => log::error!("Could not parse user leave payload: {}", e),
+        }
+    }
+}
+
+/// Update the tracked rank of a promoted or demoted user.
+fn handle_set_user_rank_event(values: Vec<Value>, ranks: &mut HashMap<String, data::Rank>) {
+    for value in values {
+        let user: data::User = match serde_json::from_value(value) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("Could not parse user rank payload: {}", e);
+                continue;
+            }
+        };
+        match ranks.insert(user.name.clone(), user.rank) {
+            Some(previous) => log::info!(
+                "Rank of {} changed from {} to {}",
+                user.name,
+                previous,
+                user.rank
+            ),
+            None => log::info!("Rank of {} set to {}", user.name, user.rank),
+        }
+    }
+}
+
+/// Warn if the logged-in user gets muted, as the capture may be degraded.
+fn handle_set_user_meta_event(values: Vec<Value>, login_name: Option<&str>) {
+    for value in values {
+        let user_meta: data::UserMeta = match serde_json::from_value(value) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("Could not parse user meta payload: {}", e);
+                continue;
+            }
+        };
+        if login_name != Some(user_meta.name.as_str()) {
+            continue;
+        }
+        if user_meta.meta.smuted {
+            log::warn!(
+                "User {} has been shadow-muted, the capture may be degraded",
+                user_meta.name
+            );
+        } else if user_meta.meta.muted {
+            log::warn!("User {} has been muted", user_meta.name);
+        }
+    }
+}
+
+/// Track whether the logged-in user is AFK, returning the new state if a
+/// `setAFK` event was about the user.
+fn handle_set_afk_event(values: Vec<Value>, login_name: Option<&str>) -> Option<bool> {
+    let mut own_afk = None;
+    for value in values {
+        let afk_state: data::AfkState = match serde_json::from_value(value) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("Could not parse AFK payload: {}", e);
+                continue;
+            }
+        };
+        if login_name.is_some_and(|name| name.eq_ignore_ascii_case(&afk_state.name)) {
+            match afk_state.afk {
+                true => log::info!("User {} is now AFK", afk_state.name),
+                false => log::info!("User {} is no longer AFK", afk_state.name),
+            }
+            own_afk = Some(afk_state.afk);
+        }
+    }
+    own_afk
+}
+
+/// Write private messages sent to the logged-in user to the whisper log.
+/// Messages sent by the user are echoed back by the server and skipped.
+async fn handle_whisper_event(values: Vec<Value>, login_name: Option<&str>, file: &mut File) {
+    for value in values {
+        let whisper: data::Whisper = match serde_json::from_value(value) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("Could not parse private message payload: {}", e);
+                continue;
+            }
+        };
+        if login_name.is_some_and(|name| !name.eq_ignore_ascii_case(&whisper.to)) {
+            continue;
+        }
+        let line = match serde_json::to_string(&whisper) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("Could not serialize private message: {}", e);
+                continue;
+            }
+        };
+        match file.write_all(format!("{}\n", line).as_bytes()).await {
+            Ok(_) => log::debug!("Logged private message from {}", whisper.from),
+            Err(e) => log::warn!("Failed to write private message to file: {}", e),
+        };
+    }
+}
+
+fn handle_error_msg_event(values: Vec<Value>) {
+    for value in values {
+        match serde_json::from_value::<data::ErrorMsg>(value) {
+            Ok(error) => log::warn!("Server error message: {}", error.msg),
+            Err(e) => log::error!("Could not parse error message payload: {}", e),
+        }
+    }
+}
+
+/// Log socket errors, returning errors that prevent capturing the channel.
+fn handle_error_event(values: Vec<Value>) -> Result<(), error::Error> {
+    for value in values {
+        let payload: data::ErrorPayload = match serde_json::from_value(value.clone()) {
+            Ok(v) => v,
+            Err(_) => {
+                log::warn!("Received error: {}", value);
+                continue;
+            }
+        };
+        match payload.kind() {
+            data::ErrorKind::Banned => return Err(error::Error::Kicked(payload.message)),
+            data::ErrorKind::InvalidChannel => return Err(error::Error::Channel(payload.message)),
+            data::ErrorKind::Other => match payload.code {
+                Some(code) => log::warn!("Server error {}: {}", code, payload.message),
+                None => log::warn!("Server error: {}", payload.message),
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Return the kick reason as an error to end the capture.
+fn handle_kick_event(values: Vec<Value>) -> Result<(), error::Error> {
+    let reason = values
+        .into_iter()
+        .find_map(|value| serde_json::from_value::<data::Kick>(value).ok())
+        .map(|kick| kick.reason)
+        .unwrap_or("Unknown reason".into());
+    Err(error::Error::Kicked(reason))
+}
+
+/// Log login results and track the session, returning whether a registered
+/// session was downgraded to a guest one.
+fn handle_login_event(
+    values: Vec<Value>,
+    session: &mut data::Session,
+) -> Result<bool, error::Error> {
+    let mut downgraded = false;
+    for value in values {
+        let login: data::Login = match serde_json::from_value(value) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("Could not parse login payload: {}", e);
+                continue;
+            }
+        };
+
+        match login.session() {
+            Some(next) => {
+                downgraded |= session.is_downgraded_to(next);
+                *session = next;
+                log::info!("Logged in as {}", login.name.unwrap_or("Unknown".into()));
+            }
+            None => {
+                return Err(error::Error::Login(
+                    login.error.unwrap_or("Unknown error".into()),
+                ));
+            }
+        }
+    }
+    Ok(downgraded)
+}
+
+/// Join a channel on the Cytube server.
+async fn join_channel(client: &Client, channel_name: &str) {
+    match client
+        .emit("joinChannel", json!({"name": channel_name}))
+        .await
+    {
+        Ok(_) => log::info!("Joined channel {}", channel_name),
+        Err(e) => {
+            log::error!("Could not join channel {}: {}", channel_name, e);
+        }
+    };
+}
+
+/// Login on the Cytube server, as a guest user if no password is given.
+async fn login(client: &Client, name: &str, password: Option<&utils::Secret>) {
+    let payload = match password {
+        Some(password) => json!({"name": name, "pw": password.expose()}),
+        None => json!({"name": name}),
+    };
+    match client.emit("login", payload).await {
+        Ok(_) => log::debug!("Login request sent"),
+        Err(e) => {
+            log::error!("Could not send login request: {}", e);
+        }
+    };
+}
+
+/// Toggle the AFK state of the logged-in user with the `/afk` chat command,
+/// which Cytube handles without showing a chat message.
+async fn set_afk(client: &Client) {
+    match client
+        .emit("chatMsg", json!({"msg": "/afk", "meta": {}}))
+        .await
+    {
+        Ok(_) => log::debug!("AFK request sent"),
+        Err(e) => {
+            log::error!("Could not send AFK request: {}", e);
+        }
+    };
+}
+
+/// Fetch the public channels of a Cytube server, most viewed first.
+async fn list_channels(
+    client: &reqwest::Client,
+    domain: &utils::Domain,
+) -> anyhow::Result<Vec<data::ChannelListEntry>> {
+    let url = format!("{}/api/channels", domain.base_url());
+    log::debug!("Fetching channel list from {}", url);
+    let response = client
+        .get(&url)
+        .send()
+        .await
+        .context("Failed to fetch channel list")?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        anyhow::bail!("{} does not expose a public channel list", domain.host);
+    }
+    let response = response
+        .error_for_status()
+        .context("Failed to fetch channel list")?;
+    let content = response
+        .text()
+        .await
+        .context("Failed to fetch channel list")?;
+    let mut channels: Vec<data::ChannelListEntry> =
+        serde_json::from_str(&content).with_context(|| {
+            format!(
+                "{} did not return a channel list; the server may not expose one",
+                domain.host
+            )
+        })?;
+    channels.sort_by(|a, b| b.usercount.cmp(&a.usercount).then(a.name.cmp(&b.name)));
+    Ok(channels)
+}
+
+/// Print the public channels of a Cytube server.
+async fn run_list(args: ListArgs) -> anyhow::Result<()> {
+    let client = http_client_builder(args.user_agent.as_deref(), &args.header)
+        .build()
+        .context("Failed to create HTTP client")?;
+    let channels = list_channels(&client, &args.domain).await?;
+    if channels.is_empty() {
+        log::info!("No public channels on {}", args.domain.host);
+    }
+    for channel in channels {
+        match channel.mediatitle.is_empty() {
+            true => println!("{:>6}  {}", channel.usercount, channel.name),
+            false => println!(
+                "{:>6}  {}  ({})",
+                channel.usercount, channel.name, channel.mediatitle
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Walk the hash chain of a chat log and report the first broken link.
+fn run_verify(args: VerifyArgs) -> anyhow::Result<()> {
+    let content = std::fs::read_to_string(&args.file)
+        .with_context(|| format!("Could not read {}", args.file.display()))?;
+    match chain::verify(&content) {
+        Ok(lines) => {
+            println!("{}: {} lines verified", args.file.display(), lines);
+            Ok(())
+        }
+        Err(broken) => anyhow::bail!(
+            "{}: hash chain broken at line {}: {}",
+            args.file.display(),
+            broken.line,
+            broken.reason
+        ),
+    }
+}
+
+/// Join the channel and disconnect after the first channel event, reporting
+/// the server and latency. No log files are written.
+async fn run_check(mut args: Args) -> anyhow::Result<()> {
+    resolve_secrets(&mut args)?;
+    check_bind_address(&args)?;
+    let started = Instant::now();
+    let http_client = build_http_client(&args)?;
+    let socket_address = resolve_socket_address(&args, &http_client).await?;
+    let (tx, mut rx) = channel::mpsc_channel();
+    let login_name = Arc::new(std::sync::Mutex::new(
+        args.username.clone().or(args.guest_login.clone()),
+    ));
+    let socket = connect_socket(&socket_address, &args, &login_name, &tx)
+        .await
+        .context(error::Error::Connection)?;
+    let timeout = args.join_timeout.unwrap_or(CHECK_TIMEOUT);
+    let deadline = started + Duration::from_secs(timeout);
+    let result = loop {
+        let event = select! {
+            event = channel::read_event(&mut rx) => event,
+            _ = tokio::time::sleep_until(deadline) => break Err(error::Error::JoinTimeout(timeout)),
+        };
+        match event {
+            Some(Event::Connect) => {
+                log::debug!("Connected after {} ms", started.elapsed().as_millis())
+            }
+            Some(Event::Disconnect(reason)) => {
+                log::error!("Disconnected from server: {}", reason);
+                break Err(error::Error::Connection);
+            }
+            Some(Event::Error(values)) => {
+                if let Err(e) = handle_error_event(values) {
+                    break Err(e);
+                }
+            }
+            Some(Event::Kick(values)) => {
+                if let Err(e) = handle_kick_event(values) {
+                    break Err(e);
+                }
+            }
+            Some(Event::Login(values)) => {
+                if let Err(e) = handle_login_event(values, &mut data::Session::default()) {
+                    break Err(e);
+                }
+            }
+            Some(event) if event.is_channel_event() => break Ok(()),
+            Some(_) => {}
+            None => break Err(error::Error::Connection),
+        }
+    };
+    let latency = started.elapsed();
+    if let Err(e) = socket.disconnect().await {
+        log::warn!("Failed to disconnect from server: {}", e);
+    }
+    result?;
+    log::info!(
+        "Joined {} on {} in {} ms",
+        args.channel,
+        socket_address,
+        latency.as_millis()
+    );
+    Ok(())
+}
+
+/// Fetch Cytube socket config and return the URL of the first Socket.IO server.
+async fn lookup_socket_address(
+    client: &reqwest::Client,
+    domain: &utils::Domain,
+    channel: &str,
+    path_template: &str,
+    auth: Option<&(String, utils::Secret)>,
+    selection: &data::ServerSelection,
+) -> Result<String, SocketAddressError> {
+    log::info!("Looking up socket address...");
+    let url = format!(
+        "{}{}",
+        domain.base_url(),
+        utils::expand_config_path(path_template, channel)
+    );
+    log::debug!("Fetching socket config from {}", url);
+    let mut request = client.get(&url);
+    if let Some((user, password)) = auth {
+        log::debug!("Using HTTP Basic auth as {}", user);
+        request = request.basic_auth(user, Some(password.expose()));
+    }
+    let response = request.send().await.map_err(SocketAddressError::Request)?;
+    let content = response.text().await.map_err(SocketAddressError::Request)?;
+    let socket_config: data::SocketConfig =
+        serde_json::from_str(&content).map_err(SocketAddressError::Parse)?;
+    if let Some(server) = socket_config.select(selection) {
+        log::info!("Found {}", server.url);
+        return Ok(server.url.clone());
+    }
+    match selection {
+        data::ServerSelection::First => Err(SocketAddressError::NotFound),
+        _ => Err(SocketAddressError::NoSuchServer(
+            selection.clone(),
+            socket_config
+                .servers
+                .into_iter()
+                .map(|server| server.url)
+                .collect(),
+        )),
+    }
+}
+
+/// Name to log in with, shared with the socket handlers so that reconnects
+/// use the last name tried after a guest name was taken.
+type LoginName = Arc<std::sync::Mutex<Option<String>>>;
+
+fn current_login_name(login_name: &LoginName) -> Option<String> {
+    login_name
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .clone()
+}
+
+/// Connect to the Socket.IO server and forward socket events to the manager task.
+async fn connect_socket(
+    address: &str,
+    args: &Args,
+    login_name: &LoginName,
+    tx: &channel::EventTx,
+) -> Result<Client, rust_socketio::Error> {
+    let channel_name = args.channel.clone();
+    let login_name = login_name.clone();
+    let password = args.password.clone();
+    let connect_tx = tx.clone();
+    let disconnect_tx = tx.clone();
+    let error_tx = tx.clone();
+    let event_tx = tx.clone();
+    let reconnect_tx = tx.clone();
+    let raw_log = args.raw_log.is_some();
+    let raw_whispers = args.log_whispers;
+    #[cfg(feature = "tracing")]
+    let span = tracing::info_span!("connection", channel = %args.channel, server = %address);
+    #[cfg(feature = "tracing")]
+    let connect_span = span.clone();
+    log::debug!(
+        "Using {:?} transport and namespace {}",
+        args.transport,
+        args.namespace
+    );
+    let connect = ClientBuilder::new(address)
+        .transport_type(args.transport.into())
+        .namespace(args.namespace.as_str())
+        .on(rust_socketio::Event::Connect, move |_, client| {
+            let channel_name = channel_name.clone();
+            let login_name = login_name.clone();
+            let password = password.clone();
+            let tx_ = connect_tx.clone();
+            let future = async move {
+                log::info!("Connected to server");
+                #[cfg(feature = "metrics")]
+                metrics::METRICS.record_connect();
+                if let Err(e) = tx_.send(Event::Connect).await {
+                    log::error!("Could not send connect to channel: {}", e);
+                }
+                join_channel(&client, &channel_name).await;
+                if let Some(name) = current_login_name(&login_name) {
+                    login(&client, &name, password.as_ref()).await;
+                }
+            };
+            #[cfg(feature = "tracing")]
+            let future = future.instrument(connect_span.clone());
+            future.boxed()
+        })
+        .on(rust_socketio::Event::Close, move |payload, _| {
+            let tx_ = disconnect_tx.clone();
+            async move {
+                let reason = match payload {
+                    Payload::Text(values) => data::DisconnectReason::from_payload(&values),
+                    other => data::DisconnectReason::Other(format!("{:?}", other)),
+                };
+                if let Err(e) = tx_.send(Event::Disconnect(reason)).await {
+                    log::error!("Could not send disconnect to channel: {}", e);
+                }
+            }
+            .boxed()
+        })
+        .on("error", move |err, _| {
+            let tx_ = error_tx.clone();
+            async move {
+                match err {
+                    Payload::Text(values) => {
+                        if let Err(e) = tx_.send(Event::Error(values)).await {
+                            log::error!("Could not send error to channel: {}", e);
+                        }
+                    }
+                    other => {
+                        log::error!("Received error: {:?}", other);
+                    }
+                }
+            }
+            .boxed()
+        })
+        .on_reconnect(move || {
+            let tx_ = reconnect_tx.clone();
+            async move {
+                // Counted by the manager, as the client retries indefinitely.
+                if let Err(e) = tx_.send(Event::Reconnecting).await {
+                    log::error!("Could not send reconnect attempt to channel: {}", e);
+                }
+                ReconnectSettings::new()
+            }
+            .boxed()
+        })
+        .on_any(move |event, payload, _| {
+            let tx_ = event_tx.clone();
+            async move {
+                let (rust_socketio::Event::Custom(name), Payload::Text(values)) = (event, payload)
+                else {
+                    return;
+                };
+                let received = Utc::now().timestamp_millis();
+                // Whispers are sensitive and only recorded when asked for.
+                let record = (raw_log && (raw_whispers || name != "pm")).then(|| data::RawRecord {
+                    event: name.clone(),
+                    time: received,
+                    payload: values.clone(),
+                });
+                match Event::from_raw(&name, values, received) {
+                    Some(event) => {
+                        if let Some(record) = record
+                            && let Err(e) = tx_.send(Event::Raw(record)).await
+                        {
+                            log::error!("Could not send raw payload to channel: {}", e);
+                        }
+                        if let Err(e) = tx_.send(event).await {
+                            log::error!("Could not send payload to channel: {}", e);
+                        }
+                    }
+                    None => log::trace!("Ignoring {} event", name),
+                }
+            }
+            .boxed()
+        })
+        .connect();
+    #[cfg(feature = "tracing")]
+    let connect = connect.instrument(span);
+    connect.await
+}
+
+/// Connect to the Socket.IO server, retrying with exponential backoff.
+async fn connect_socket_with_retries(
+    address: &str,
+    args: &Args,
+    login_name: &LoginName,
+    tx: &channel::EventTx,
+) -> Result<Client, rust_socketio::Error> {
+    let mut backoff = Duration::from_secs(1);
+    let mut attempt = 0;
+    loop {
+        match connect_socket(address, args, login_name, tx).await {
+            Ok(client) => return Ok(client),
+            Err(e) if attempt < args.connect_retries => {
+                attempt += 1;
+                log::warn!(
+                    "Connection failed: {}. Retrying in {} seconds ({}/{})",
+                    e,
+                    backoff.as_secs(),
+                    attempt,
+                    args.connect_retries
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_CONNECT_BACKOFF);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Periodically send a log rotation event to the main task.
+async fn rotate_file_loop(token: CancellationToken, tx: channel::EventTx, hours: u64) {
+    let rotate_interval = Duration::from_secs(hours * 60 * 60);
+    let mut interval = tokio::time::interval(rotate_interval);
+    interval.tick().await;
+    loop {
+        select! {
+            _ = token.cancelled() => {
+                log::debug!("Ending log rotation task");
+                break;
+            },
+            _ = interval.tick() => {
+                log::debug!("Log rotation interval reached");
+                if let Err(err) = tx.send(Event::RotateLog).await {
+                    log::error!("Failed to send rotate log event: {}", err);
+                }
+            }
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    // Subcommands are dispatched by hand, as the capture arguments have a
+    // positional domain that could also be named like a subcommand.
+    match std::env::args_os()
+        .nth(1)
+        .as_deref()
+        .and_then(std::ffi::OsStr::to_str)
+    {
+        Some("list") => {
+            let args = <ListArgs as clap::Parser>::parse_from(std::env::args_os().skip(1));
+            init_logging(
+                args.log_level,
+                logger::LogFormat::Plain,
+                pretty::ColorChoice::Auto,
+                None,
+            );
+            if let Err(err) = run_list(args).await {
+                log::error!("{:#}", err);
+                std::process::exit(error::exit_code(&err));
+            }
+            return;
+        }
+        Some("check") => {
+            let args = parse_args(check_command(), std::env::args_os().skip(1).collect());
+            let log_level = match args.quiet {
+                true => log::LevelFilter::Error,
+                false => args.log_level,
+            };
+            init_logging(log_level, args.log_format, args.color, None);
+            if let Err(err) = run_check(args).await {
+                log::error!("{:#}", err);
+                std::process::exit(error::exit_code(&err));
+            }
+            return;
+        }
+        Some("verify") => {
+            let args = <VerifyArgs as clap::Parser>::parse_from(std::env::args_os().skip(1));
+            init_logging(
+                log::LevelFilter::Info,
+                logger::LogFormat::Plain,
+                pretty::ColorChoice::Auto,
+                None,
+            );
+            if let Err(err) = run_verify(args) {
+                log::error!("{:#}", err);
+                std::process::exit(error::exit_code(&err));
+            }
+            return;
+        }
+        _ => {}
+    }
+
+    let args = parse_args(Args::command(), std::env::args_os().collect());
+    let log_level = match args.quiet {
+        true => log::LevelFilter::Error,
+        false => args.log_level,
+    };
+    // A dry run writes no files, including the diagnostics file.
+    let diag_file = args.diag_file.as_deref().filter(|_| !args.dry_run);
+    init_logging(log_level, args.log_format, args.color, diag_file);
+
+    if let Err(err) = run(args).await {
+        log::error!("{:#}", err);
+        std::process::exit(error::exit_code(&err));
+    }
+}
+
+/// Command of the `check` subcommand, which takes the capture arguments.
+fn check_command() -> clap::Command {
+    Args::command()
+        .name("cupcake check")
+        .bin_name("cupcake check")
+        .about("Check that a channel can be joined, without logging anything")
+}
+
+/// Command line of all modes, for generating shell completions.
+fn completions_command() -> clap::Command {
+    Args::command()
+        .subcommand(ListArgs::command().name("list"))
+        .subcommand(check_command().name("check"))
+        .subcommand(VerifyArgs::command().name("verify"))
+}
+
+/// Install the logger for cupcake's own diagnostic logs.
+fn init_logging(
+    level: log::LevelFilter,
+    format: logger::LogFormat,
+    color: pretty::ColorChoice,
+    diag_file: Option<&std::path::Path>,
+) {
+    let colors = color.for_stderr();
+    #[cfg(not(feature = "tracing"))]
+    let result = logger::init(level, format, colors, diag_file);
+    #[cfg(feature = "tracing")]
+    let result = init_tracing(level, format, colors, diag_file);
+    if let Err(e) = result {
+        eprintln!("{:#}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Parse the command line arguments with the given command of the capture
+/// arguments, using the config file values as defaults.
+fn parse_args(mut command: clap::Command, args: Vec<std::ffi::OsString>) -> Args {
+    if let Some(path) = config::find_path(args.iter().cloned()) {
+        match config::Config::load(&path) {
+            Ok(config) => command = config.apply(command),
+            Err(e) => {
+                command
+                    .error(clap::error::ErrorKind::Io, format!("{:#}", e))
+                    .exit();
+            }
+        }
+    }
+    let matches = command
+        .try_get_matches_from_mut(args)
+        .unwrap_or_else(|e| e.exit());
+    // Handled before building the arguments, as the domain is not given.
+    if matches.get_flag("print_schema") {
+        match format::sample_record() {
+            Ok(sample) => println!("{}", sample),
+            Err(e) => Args::command()
+                .error(clap::error::ErrorKind::Io, e.to_string())
+                .exit(),
+        }
+        std::process::exit(0);
+    }
+    if let Some(&shell) = matches.get_one::<clap_complete::Shell>("completions") {
+        clap_complete::generate(
+            shell,
+            &mut completions_command(),
+            "cupcake",
+            &mut std::io::stdout(),
+        );
+        std::process::exit(0);
+    }
+    if !matches.contains_id("domain") {
+        command
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "the following required arguments were not provided:\n  <DOMAIN>",
+            )
+            .exit();
+    }
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    with_url_channel(args)
+}
+
+/// Take the channel from a channel URL given as the domain if no channel
+/// was given, exiting with an error if neither was.
+fn with_url_channel(mut args: Args) -> Args {
+    if args.channel.is_empty() {
+        match args.domain.channel.clone() {
+            Some(channel) => args.channel = channel,
+            None => Args::command()
+                .error(
+                    clap::error::ErrorKind::MissingRequiredArgument,
+                    "a channel is required unless the domain is a channel URL such as https://cytu.be/r/<CHANNEL>",
+                )
+                .exit(),
+        }
+    }
+    args
+}
+
+/// Install a `tracing` subscriber that also receives the `log` records.
+#[cfg(feature = "tracing")]
+fn init_tracing(
+    level: log::LevelFilter,
+    format: logger::LogFormat,
+    colors: bool,
+    diag_file: Option<&std::path::Path>,
+) -> anyhow::Result<()> {
+    use tracing_subscriber::Layer;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(level.as_str()));
+    let console = match format {
+        logger::LogFormat::Plain => tracing_subscriber::fmt::layer()
+            .with_ansi(colors)
+            .with_filter(filter)
+            .boxed(),
+        logger::LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_filter(filter)
+            .boxed(),
+    };
+    let diag = match diag_file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Could not open diagnostics file {}", path.display()))?;
+            let layer = tracing_subscriber::fmt::layer()
+                .with_writer(std::sync::Mutex::new(file))
+                .with_ansi(false)
+                .with_filter(tracing_subscriber::filter::filter_fn(|metadata| {
+                    *metadata.level() <= tracing::Level::WARN
+                        || metadata.target() == logger::COMMAND_TARGET
+                }));
+            Some(layer)
+        }
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(console)
+        .with(diag)
+        .try_init()?;
+    Ok(())
+}
+
+/// Wait for SIGINT (Ctrl-C) or, on Unix, SIGTERM and return the signal name.
+async fn shutdown_signal() -> std::io::Result<&'static str> {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+        select! {
+            result = signal::ctrl_c() => result.map(|_| "SIGINT"),
+            _ = terminate.recv() => Ok("SIGTERM"),
+        }
+    }
+    #[cfg(not(unix))]
+    signal::ctrl_c().await.map(|_| "SIGINT")
+}
+
+/// Look up the address of the socket server to connect to.
+async fn resolve_socket_address(
+    args: &Args,
+    http_client: &reqwest::Client,
+) -> anyhow::Result<String> {
+    // Convert Cytube domain and channel name to socket address.
+    let server_selection = match (args.server_index, &args.server_host) {
+        (Some(index), _) => data::ServerSelection::Index(index),
+        (None, Some(host)) => data::ServerSelection::Host(host.clone()),
+        (None, None) => data::ServerSelection::First,
+    };
+    let socket_address = lookup_socket_address(
+        http_client,
+        &args.domain,
+        &args.channel,
+        &args.config_path_template,
+        args.config_auth.as_ref(),
+        &server_selection,
+    )
+    .await
+    .context(error::Error::ConfigLookup)?;
+    Ok(
+        match utils::rewrite_host(&socket_address, &args.rewrite_host) {
+            rewritten if rewritten != socket_address => {
+                log::info!("Rewrote socket address to {}", rewritten);
+                rewritten
+            }
+            _ => socket_address,
+        },
+    )
+}
+
+/// Time at which the first event of a `--raw-log` dump was received.
+fn dump_start(content: &str) -> Option<u64> {
+    content
+        .lines()
+        .find_map(|line| serde_json::from_str::<data::RawRecord>(line).ok())
+        .and_then(|record| u64::try_from(record.time).ok())
+}
+
+/// Feed the events of a `--raw-log` dump to the manager task and terminate it
+/// once all events were sent.
+async fn replay_dump(content: String, tx: channel::EventTx) {
+    for (index, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: data::RawRecord = match serde_json::from_str(line) {
+            Ok(v) => v,
+            Err(e) => {
+                log::warn!("Skipping line {} of dump file: {}", index + 1, e);
+                continue;
+            }
+        };
+        let Some(event) = Event::from_raw(&record.event, record.payload, record.time) else {
+            log::debug!("Skipping unhandled {} event in dump file", record.event);
+            continue;
+        };
+        if let Err(e) = tx.send(event).await {
+            log::error!("Could not send dump event to channel: {}", e);
+            return;
+        }
+    }
+    log::info!("Processed all events of the dump file");
+    if let Err(e) = tx.send(Event::Terminate).await {
+        log::error!("Could not send termination signal: {}", e);
+    }
+}
+
+/// Fail if --bind-address cannot be honored for the Socket.IO connection,
+/// unless the user opted into only binding the socket config lookup.
+fn check_bind_address(args: &Args) -> anyhow::Result<()> {
+    if let Some(address) = args.bind_address
+        && !args.bind_lookup_only
+    {
+        anyhow::bail!(
+            "The Socket.IO client cannot bind its connection to {}; \
+            use --bind-lookup-only to only bind the socket config lookup",
+            address
+        );
+    }
+    Ok(())
+}
+
+/// Resolve secrets given through environment variables.
+fn resolve_secrets(args: &mut Args) -> anyhow::Result<()> {
+    if let Some(name) = &args.password_env {
+        args.password = Some(utils::secret_from_env(name).map_err(anyhow::Error::msg)?);
+    }
+    if let Some(name) = &args.webhook_env {
+        args.webhook = Some(utils::secret_from_env(name).map_err(anyhow::Error::msg)?);
+    }
+    if args.username.is_some() && args.password.is_none() {
+        anyhow::bail!("--username requires --password or --password-env");
+    }
+    Ok(())
 }
 
-/// Fetch Cytube socket config and return the URL of the first Socket.IO server.
-async fn lookup_socket_address(
-    domain: &url::Host,
-    channel: &str,
-) -> Result<String, SocketAddressError> {
-    log::info!("Looking up socket address...");
-    let url = format!("https://{}/socketconfig/{}.json", domain, channel);
-    log::debug!("Fetching socket config from {}", url);
-    let response = reqwest::get(&url)
-        .await
-        .map_err(SocketAddressError::Request)?;
-    let content = response.text().await.map_err(SocketAddressError::Request)?;
-    let socket_config: data::SocketConfig =
-        serde_json::from_str(&content).map_err(SocketAddressError::Parse)?;
-    if let Some(server) = socket_config.servers.into_iter().next() {
-        log::info!("Found {}", server.url);
-        return Ok(server.url);
+/// Fail if --afk is given without logging in, as anonymous users cannot use
+/// chat commands. Not checked by clap, as --guest-login may come from --config.
+fn check_afk(args: &Args) -> anyhow::Result<()> {
+    if args.afk && args.username.is_none() && args.guest_login.is_none() {
+        anyhow::bail!("--afk requires --username or --guest-login");
     }
-    Err(SocketAddressError::NotFound)
+    Ok(())
 }
 
-/// Periodically send a log rotation event to the main task.
-async fn rotate_file_loop(token: CancellationToken, tx: channel::EventTx, hours: u64) {
-    let rotate_interval = Duration::from_secs(hours * 60 * 60);
-    let mut interval = tokio::time::interval(rotate_interval);
-    interval.tick().await;
-    loop {
-        select! {
-            _ = token.cancelled() => {
-                log::debug!("Ending log rotation task");
-                break;
-            },
-            _ = interval.tick() => {
-                log::debug!("Log rotation interval reached");
-                if let Err(err) = tx.send(Event::RotateLog).await {
-                    log::error!("Failed to send rotate log event: {}", err);
-                }
-            }
-        }
+/// Record the channel chat until terminated.
+async fn run(mut args: Args) -> anyhow::Result<()> {
+    resolve_secrets(&mut args)?;
+    check_afk(&args)?;
+
+    if args.dry_run {
+        log::info!("Dry run, no files will be written");
+        args.sink.clear();
+        args.webhook = None;
+        args.exec = None;
+        args.log_media = false;
+        args.log_playback = false;
+        args.state_file = None;
+        args.log_voteskip = false;
+        args.log_whispers = false;
+        args.log_skew = false;
+        args.log_usercount = false;
+        args.raw_log = None;
     }
-}
 
-#[tokio::main]
-async fn main() {
-    let args = Args::parse();
+    if args.hash_chain && args.format == format::OutputFormat::JsonArray {
+        anyhow::bail!("--hash-chain requires the tsv or ndjson format");
+    }
 
-    SimpleLogger::new()
-        .with_level(args.log_level)
-        .env()
-        .init()
-        .unwrap();
+    let file_emotes = match &args.emote_file {
+        Some(path) => emotes::load_file(path)?,
+        None => HashMap::new(),
+    };
 
-    // Convert Cytube domain and channel name to socket address.
-    let socket_address = match lookup_socket_address(&args.domain, &args.channel).await {
-        Ok(address) => address,
-        Err(err) => {
-            match err {
-                SocketAddressError::NotFound => {
-                    log::error!("Failed to find socket address in Cytube socket config");
-                }
-                SocketAddressError::Request(e) => {
-                    log::error!("Failed to fetch Cytube socket config: {}", e);
-                }
-                SocketAddressError::Parse(e) => {
-                    log::error!("Failed to parse Cytube socket config: {}", e);
-                }
-            }
-            std::process::exit(1);
-        }
+    if args.from_dump.is_none() {
+        check_bind_address(&args)?;
+    }
+
+    let dump = match &args.from_dump {
+        Some(path) => Some(
+            tokio::fs::read_to_string(path)
+                .await
+                .with_context(|| format!("Could not read dump file {}", path.display()))?,
+        ),
+        None => None,
+    };
+    let http_client = match &dump {
+        Some(_) => None,
+        None => Some(build_http_client(&args)?),
+    };
+    let mut socket_address = match &http_client {
+        Some(http_client) => Some(resolve_socket_address(&args, http_client).await?),
+        None => None,
     };
 
-    let file = create_chat_log_file(&args.channel).await;
-    let mut file_buffer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, file);
+    #[cfg(feature = "metrics")]
+    if let Some(addr) = args.metrics_addr {
+        metrics::serve(addr)
+            .await
+            .with_context(|| format!("Could not serve metrics on {}", addr))?;
+    }
 
-    let (tx, mut rx) = channel::mpsc_channel();
+    #[cfg(not(unix))]
+    if args.file_mode.is_some() {
+        log::warn!("--file-mode is ignored, as file permissions are only supported on Unix");
+    }
+    let chat_log_options = sink::ChatLogOptions {
+        domain: args.domain.to_string(),
+        channel: args.channel.clone(),
+        format: args.format,
+        header_comment: args.header_comment,
+        path: args.output.clone(),
+        hash_chain: args.hash_chain,
+        atomic: args.atomic,
+        archive: args.archive,
+        delete_archived: args.archive_delete,
+        batch_size: args.batch_size.map(|size| size as usize),
+        file_mode: args.file_mode,
+        link_hosts: args.link_host.clone(),
+    };
+    let mut sink_kinds = args.sink.clone();
+    sink_kinds.sort();
+    sink_kinds.dedup();
+    let mut sink = sink::MultiSink::default();
+    for kind in sink_kinds {
+        match kind {
+            #[cfg(unix)]
+            sink::SinkKind::File
+                if let Some(path) = &args.output
+                    && sink::is_fifo(path) =>
+            {
+                sink.push(Box::new(
+                    sink::FifoSink::open(path.clone(), chat_log_options.clone()).await?,
+                ))
+            }
+            sink::SinkKind::File => sink.push(Box::new(
+                sink::FileSink::create(chat_log_options.clone()).await?,
+            )),
+            sink::SinkKind::Stdout => {
+                sink.push(Box::new(sink::StdoutSink::create(&chat_log_options).await?))
+            }
+        }
+    }
+    if let Some(command) = args.exec.clone() {
+        sink.push(Box::new(exec::Exec::spawn(
+            command,
+            args.format,
+            args.link_host.clone(),
+            args.exec_restart,
+        )?));
+    }
+    // The webhook gets its own client to not leak the --header values.
+    let webhook = match args.webhook.clone() {
+        Some(url) => {
+            let client = http_client_builder(None, &[])
+                .build()
+                .context("Failed to create webhook client")?;
+            Some(webhook::Webhook::spawn(client, url, args.link_host.clone()))
+        }
+        None => None,
+    };
+    // With the broadcast feature, the webhook reads the event stream next to
+    // the chat log instead of receiving the logged messages.
+    #[cfg(not(feature = "broadcast"))]
+    if let Some(webhook) = webhook {
+        sink.push(Box::new(webhook));
+    }
+    let mut media_file = match args.log_media {
+        true => Some(open_media_log_file(&args.channel).await?),
+        false => None,
+    };
+    let mut usercount_file = match args.log_usercount {
+        true => Some(create_log_file("usercount", &args.channel, "txt").await?),
+        false => None,
+    };
+    let mut skew_file = match args.log_skew {
+        true => Some(create_log_file("skew", &args.channel, "txt").await?),
+        false => None,
+    };
+    let mut playback_file = match args.log_playback {
+        true => Some(create_log_file("playback", &args.channel, "ndjson").await?),
+        false => None,
+    };
+    let mut voteskip_file = match args.log_voteskip {
+        true => Some(create_log_file("voteskip", &args.channel, "ndjson").await?),
+        false => None,
+    };
+    let mut whisper_file = match args.log_whispers {
+        true => {
+            let filename = log_file_name("whispers", &args.channel, "ndjson");
+            let file = OpenOptions::from(utils::create_options(args.file_mode))
+                .open(&filename)
+                .await
+                .with_context(|| format!("Could not create output file {}", filename))?;
+            log::info!("Created whispers log file {}", filename);
+            Some(file)
+        }
+        false => None,
+    };
+    let mut raw_file = match &args.raw_log {
+        Some(path) => {
+            let file = OpenOptions::from(utils::create_options(args.file_mode))
+                .open(path)
+                .await
+                .with_context(|| format!("Could not create raw log file {}", path.display()))?;
+            Some(BufWriter::with_capacity(WRITE_BUFFER_SIZE, file))
+        }
+        None => None,
+    };
+    let mut usercount_throttle =
+        utils::SampleThrottle::new(Duration::from_secs(args.usercount_interval));
+    let mut playback_throttle =
+        utils::SampleThrottle::new(Duration::from_secs(args.playback_interval));
 
-    let chat_tx = tx.clone();
-    let disconnect_tx = tx.clone();
-    let login_tx = tx.clone();
+    let (tx, rx) = channel::mpsc_channel();
+    #[cfg(feature = "broadcast")]
+    let (rx, webhook_rx) = channel::fan_out(rx);
+    let mut rx = rx;
 
     // Set up log rotation if --rotate-file is used.
     let cancellation_token = CancellationToken::new();
+    #[cfg(feature = "broadcast")]
+    let webhook_task =
+        webhook.map(|webhook| webhook.forward(webhook_rx, cancellation_token.clone()));
     let rotate_task = match args.rotate_file {
         Some(hours) => {
             let future = rotate_file_loop(cancellation_token.clone(), tx.clone(), hours);
@@ -210,89 +2202,203 @@ async fn main() {
         None => None,
     };
 
-    let channel_name = args.channel.clone();
-    let socket = ClientBuilder::new(socket_address)
-        .transport_type(TransportType::Any)
-        .on(rust_socketio::Event::Connect, move |_, client| {
-            let channel_name = channel_name.clone();
-            let guest_login = args.guest_login.clone();
-            async move {
-                log::info!("Connected to server");
-                join_channel(&client, &channel_name).await;
-                if let Some(username) = guest_login {
-                    login_as_guest(&client, &username).await;
-                }
-            }
-            .boxed()
-        })
-        .on(rust_socketio::Event::Close, move |payload, _| {
-            let tx_ = disconnect_tx.clone();
-            async move {
-                match payload {
-                    Payload::Text(values) => {
-                        for value in values {
-                            log::warn!("Disconnect: {:?}", value);
+    let mut state = match &args.state_file {
+        Some(_) if args.reset_state => {
+            log::info!("Resetting the state file");
+            Some(state::State::default())
+        }
+        Some(path) => Some(state::State::load(path)?),
+        None => None,
+    };
+    #[cfg(feature = "metrics")]
+    if let Some(state) = &state {
+        metrics::METRICS.restore_messages(state.messages);
+    }
+
+    // Messages from before the first connection are replayed channel history,
+    // as are messages up to the last one written in a previous session.
+    let mut history_cutoff = match (args.skip_history, &dump) {
+        (true, Some(content)) => dump_start(content).unwrap_or(0),
+        (true, None) => Utc::now().timestamp_millis() as u64,
+        (false, _) => 0,
+    };
+    if let Some(state) = &state
+        && state.last_timestamp > 0
+    {
+        log::info!(
+            "Resuming after the last message at {} from the state file",
+            state.last_timestamp
+        );
+        history_cutoff = history_cutoff.max(state.last_timestamp + 1);
+    }
+    let login_name: LoginName = Arc::new(std::sync::Mutex::new(
+        args.username.clone().or(args.guest_login.clone()),
+    ));
+    let mut socket = match &socket_address {
+        Some(address) => Some(
+            connect_socket_with_retries(address, &args, &login_name, &tx)
+                .await
+                .context(error::Error::Connection)?,
+        ),
+        None => None,
+    };
+    if let Some(content) = dump {
+        tokio::spawn(replay_dump(content, tx.clone()));
+    }
+    let capture_deadline = args.duration.map(|duration| Instant::now() + duration);
+
+    let reconnect = Arc::new(Notify::new());
+    let reconnect_request = reconnect.clone();
+    // Set with a reconnect request to look up the socket address again first.
+    let lookup = Arc::new(AtomicBool::new(false));
+    let lookup_request = lookup.clone();
+    // Notified to log in again, with the next name after a guest name was
+    // taken or as --username after the session expired.
+    let relogin = Arc::new(Notify::new());
+    let relogin_request = relogin.clone();
+    // Notified to mark the logged-in user as AFK with --afk.
+    let afk = Arc::new(Notify::new());
+    let afk_request = afk.clone();
+    let mark_afk = args.afk;
+    let idle_timeout = args.idle_timeout;
+    let exit_on_disconnect = args.exit_on_disconnect;
+    let mark_reconnects = args.mark_reconnects;
+    let max_reconnects = args.max_reconnects;
+    let join_timeout = args.join_timeout;
+    let with_seq = args.with_seq;
+    let batch_interval = args.batch_size.map(|_| args.batch_interval);
+    let heartbeat_interval = args.heartbeat_line;
+    let pretty = args.pretty;
+    let line_template = args.line_template.clone();
+    let apply_filters = args.apply_filters;
+    let expand_emotes = args.expand_emotes;
+    let log_rate_limits = args.log_rate_limits;
+    let log_deletions = args.log_deletions;
+    let log_announcements = args.log_announcements;
+    let log_command_acks = args.log_command_acks;
+    let max_text_len = args.max_text_len;
+    let normalize = args.normalize;
+    let no_summary = args.no_summary;
+    let no_dedup = args.no_dedup;
+    let max_messages = args.max_messages;
+    let count_patterns = args.count_pattern.clone();
+    let quiet = args.quiet;
+    let dry_run = args.dry_run;
+    let state_file = args.state_file.clone();
+    // Bytes of previous sessions, as the sink only counts this session.
+    let previous_bytes = state.as_ref().map_or(0, |state| state.bytes);
+    let manager_login_name = login_name.clone();
+    let guest_login = args.guest_login.is_some();
+    let login_retries = args.login_retries;
+    let reauthenticate = args.username.is_some() && !args.no_reauthenticate;
+    let skip_classes: Vec<String> = args
+        .skip_class
+        .iter()
+        .filter(|class| !(args.keep_server_whispers && *class == "server-whisper"))
+        .cloned()
+        .collect();
+    let command_prefix = args.skip_commands.then(|| args.command_prefix.clone());
+    let skip_bots = args.skip_bot.clone();
+    let colors = args.color.for_stderr();
+    #[cfg(feature = "tracing")]
+    let capture_span = tracing::info_span!("capture", channel = %args.channel, server = socket_address.as_deref().unwrap_or("dump"));
+    let manager_task = async move {
+        let mut replay_filter = utils::ReplayFilter::new(!no_dedup);
+        let mut reconnect_budget =
+            utils::ReconnectBudget::new(max_reconnects, RECONNECT_RESET_PERIOD);
+        let mut last_media: Option<data::MediaEntry> = None;
+        let mut last_event = Instant::now();
+        let mut last_backlog_report: Option<Instant> = None;
+        let mut summary = summary::Summary::with_patterns(count_patterns);
+        let mut seq: u64 = 0;
+        let mut joined = false;
+        let mut connected = false;
+        let mut login_attempts: u32 = 0;
+        let mut session = data::Session::default();
+        // Latest state of the open poll, for its final counts when it closes.
+        let mut poll: Option<data::Poll> = None;
+        // Known AFK state of the logged-in user, so that --afk does not toggle it back.
+        let mut own_afk = false;
+        // Events that were buffered when terminating, processed before exiting.
+        let mut drained: Option<std::vec::IntoIter<Event>> = None;
+        let mut user_ranks: HashMap<String, data::Rank> = HashMap::new();
+        let mut chat_filters: Vec<filters::CompiledFilter> = Vec::new();
+        let mut emote_images = file_emotes.clone();
+        let join_deadline = Instant::now() + Duration::from_secs(join_timeout.unwrap_or_default());
+        let mut batch_ticker = utils::Ticker::new(batch_interval);
+        let mut heartbeat_ticker = utils::Ticker::new(heartbeat_interval);
+        let outcome = loop {
+            let idle_deadline = last_event + idle_timeout.unwrap_or_default();
+            let event = match &mut drained {
+                Some(events) => events.next(),
+                None => select! {
+                    event = channel::read_event(&mut rx) => event,
+                    _ = tokio::time::sleep_until(idle_deadline), if idle_timeout.is_some() => {
+                        log::warn!(
+                            "No events received in {}, reconnecting",
+                            humantime::format_duration(idle_timeout.unwrap_or_default())
+                        );
+                        if !reconnect_budget.reconnect(std::time::Instant::now()) {
+                            log::error!("Giving up after {} reconnects", max_reconnects);
+                            break Err(error::Error::Connection);
                         }
+                        reconnect_request.notify_one();
+                        last_event = Instant::now();
+                        continue;
                     }
-                    other => {
-                        log::warn!("Disconnect: {:?}", other);
+                    _ = tokio::time::sleep_until(join_deadline), if !joined && join_timeout.is_some() => {
+                        break Err(error::Error::JoinTimeout(join_timeout.unwrap_or_default()));
                     }
-                }
-                tx_.send(Event::Disconnect)
-                    .await
-                    .expect("Could not send disconnect to channel");
-            }
-            .boxed()
-        })
-        .on("error", |err, _| {
-            async move {
-                match err {
-                    Payload::Text(values) => {
-                        for value in values {
-                            log::error!("Received error: {}", value);
-                        }
+                    _ = batch_ticker.tick() => {
+                        // Write batches that did not fill up within the interval.
+                        sink.flush().await;
+                        continue;
                     }
-                    other => {
-                        log::error!("Received error: {:?}", other);
+                    _ = heartbeat_ticker.tick() => {
+                        write_heartbeat(&mut sink, heartbeat_interval.unwrap_or_default()).await;
+                        continue;
                     }
-                }
+                },
+            };
+            let Some(event) = event else {
+                break Ok(());
+            };
+            #[cfg(feature = "metrics")]
+            metrics::METRICS.set_buffer_depth(channel::buffer_depth(&rx));
+            if let Some((depth, capacity)) = channel::buffer_backlog(&rx)
+                && last_backlog_report.is_none_or(|t| t.elapsed() >= BUFFER_REPORT_INTERVAL)
+            {
+                log::debug!(
+                    "Event buffer is {}/{} full, socket handlers may be blocked",
+                    depth,
+                    capacity
+                );
+                last_backlog_report = Some(Instant::now());
             }
-            .boxed()
-        })
-        .on("chatMsg", move |payload, _| {
-            let tx_ = chat_tx.clone();
-            async move {
-                if let Payload::Text(values) = payload {
-                    tx_.send(Event::Chat(values))
-                        .await
-                        .expect("Could not send chat payload to channel");
-                }
+            if !matches!(event, Event::RotateLog) {
+                last_event = Instant::now();
             }
-            .boxed()
-        })
-        .on("login", move |payload, _| {
-            let tx_ = login_tx.clone();
-            async move {
-                if let Payload::Text(values) = payload {
-                    tx_.send(Event::Login(values))
-                        .await
-                        .expect("Could not send login payload to channel");
-                }
+            if !joined && event.is_channel_event() {
+                log::debug!("Received first channel event");
+                joined = true;
             }
-            .boxed()
-        })
-        .connect()
-        .await
-        .expect("Connection failed");
-
-    let channel_name = args.channel.clone();
-    let manager = tokio::spawn(async move {
-        let mut last_timestamp: u64 = 0;
-        while let Some(event) = channel::read_event(&mut rx).await {
             match event {
-                Event::Chat(values) => {
+                Event::Raw(record) => {
+                    if let Some(file) = &mut raw_file {
+                        let line = serde_json::to_string(&record).unwrap_or_default();
+                        if let Err(e) = file.write_all(format!("{}\n", line).as_bytes()).await {
+                            log::warn!("Failed to write raw payload to file: {}", e);
+                        }
+                    }
+                }
+                Event::ChangeMedia(values) => {
+                    if let Some(file) = &mut media_file {
+                        handle_change_media_event(values, file, &mut last_media).await;
+                    }
+                }
+                Event::Chat(values, received) => {
                     for value in values {
-                        let chat: data::ChatMessage = match serde_json::from_value(value) {
+                        let mut chat = match data::ChatMessage::from_value(value) {
                             Ok(v) => v,
                             Err(e) => {
                                 log::error!("Could not parse chat message: {}", e);
@@ -300,76 +2406,522 @@ async fn main() {
                             }
                         };
 
+                        if chat.time < history_cutoff {
+                            log::debug!(
+                                "Dropping replayed history message: {}",
+                                chat.short_format()
+                            );
+                            summary.history += 1;
+                            continue;
+                        }
+
                         // Reconnecting makes the server return the last N messages, meaning
                         // that messages may be duplicated if we don't ignore old timestamps.
-                        if last_timestamp >= chat.time {
+                        if replay_filter.is_replay(chat.time, &chat.username, chat.text()) {
+                            log::debug!(
+                                "Dropping replayed message ({} <= {}): {}",
+                                chat.time,
+                                replay_filter.last_timestamp(),
+                                chat.short_format()
+                            );
+                            summary.dropped_old += 1;
                             continue;
                         }
-                        last_timestamp = chat.time;
+
+                        let skew = received - chat.time as i64;
+                        log::debug!("Message received {} ms after its server timestamp", skew);
+                        if let Some(file) = &mut skew_file {
+                            let line = format!("{}\t{}\t{}\n", chat.time, received, skew);
+                            if let Err(e) = file.write_all(line.as_bytes()).await {
+                                log::warn!("Failed to write skew to file: {}", e);
+                            }
+                        }
+
+                        if let Some(file) = &mut voteskip_file
+                            && let Some(voteskip) = data::Voteskip::from_chat(&chat)
+                        {
+                            write_voteskip(file, &voteskip).await;
+                        }
+
+                        if chat.rank().is_none()
+                            && let Some(rank) = user_ranks.get(&chat.username)
+                        {
+                            chat.set_rank(*rank);
+                        }
+
+                        if apply_filters {
+                            chat.map_text(|text| filters::apply(&chat_filters, text));
+                        }
+                        if expand_emotes {
+                            chat.map_text(|text| emotes::expand(&emote_images, text));
+                        }
+                        if let Some(form) = normalize {
+                            chat.map_text(|text| form.apply(text));
+                        }
+                        if let Some(max_chars) = max_text_len {
+                            chat.map_text(|text| utils::truncate(text, max_chars));
+                        }
+
+                        if pretty {
+                            match &line_template {
+                                Some(template) => eprintln!("{}", chat.format_with(template)),
+                                None => eprintln!("{}", pretty::format_message(&chat, colors)),
+                            }
+                        }
 
                         // Ignore special messages.
-                        if chat.should_be_skipped() {
+                        if chat.should_be_skipped(&skip_classes)
+                            || command_prefix
+                                .as_deref()
+                                .is_some_and(|p| chat.is_command(p))
+                            || chat.is_from_any(&skip_bots)
+                        {
                             log::debug!("Ignoring message: {}", chat.short_format());
+                            summary.skipped += 1;
+                            #[cfg(feature = "metrics")]
+                            metrics::METRICS.record_skipped();
                             continue;
                         }
 
-                        match file_buffer
-                            .write_all(format!("{}\n", chat).as_bytes())
-                            .await
-                        {
-                            Ok(_) => log::debug!("{}", chat),
-                            Err(e) => {
-                                log::warn!("Failed to write '{}' to file buffer: {}", chat, e)
+                        match sink.write(&chat, with_seq.then_some(seq + 1)).await {
+                            Ok(_) => {
+                                seq += 1;
+                                match (dry_run, &line_template) {
+                                    (true, Some(template)) => {
+                                        log::info!("{}", chat.format_with(template))
+                                    }
+                                    (true, None) => log::info!("{}", chat.short_format()),
+                                    (false, Some(template)) => {
+                                        log::debug!("{}", chat.format_with(template))
+                                    }
+                                    (false, None) => log::debug!("{}", chat),
+                                }
+                                summary.messages += 1;
+                                if let Some(state) = &mut state {
+                                    state.record_message(chat.time);
+                                }
+                                let team = chat.team_name().unwrap_or("NULL").to_string();
+                                *summary.teams.entry(team).or_default() += 1;
+                                summary.count_patterns(chat.text());
+                                #[cfg(feature = "metrics")]
+                                metrics::METRICS.record_message(chat.time);
                             }
+                            Err(e) => log::warn!("{:#}", e),
                         };
+                        if summary.limit_reached(max_messages) {
+                            break;
+                        }
+                    }
+                    if summary.limit_reached(max_messages) {
+                        log::info!("Reached the limit of {} messages", summary.messages);
+                        break Ok(());
+                    }
+                }
+                Event::Cooldown(values) => {
+                    let rate_limits = values
+                        .iter()
+                        .filter_map(Value::as_u64)
+                        .map(data::RateLimit::from_cooldown)
+                        .collect();
+                    handle_rate_limits(rate_limits, log_rate_limits.then_some(&mut sink)).await;
+                }
+                Event::SpamFiltered(values) => {
+                    let rate_limits = values
+                        .into_iter()
+                        .filter_map(|value| serde_json::from_value(value).ok())
+                        .collect();
+                    handle_rate_limits(rate_limits, log_rate_limits.then_some(&mut sink)).await;
+                }
+                Event::Connect => {
+                    own_afk = false;
+                    if connected && mark_reconnects {
+                        mark_connection(&mut sink, "reconnect", "Reconnected to server").await;
+                    }
+                    connected = true;
+                    reconnect_budget.connected(std::time::Instant::now());
+                }
+                Event::Disconnect(reason) => {
+                    log::warn!("Client disconnected from server: {}", reason);
+                    if mark_reconnects {
+                        let detail = format!("Disconnected from server: {}", reason);
+                        mark_connection(&mut sink, "disconnect", &detail).await;
+                    }
+                    if exit_on_disconnect {
+                        log::info!("Exiting after disconnect");
+                        break Ok(());
+                    }
+                    if !reconnect_budget.disconnected(std::time::Instant::now()) {
+                        log::error!("Giving up after {} reconnects", max_reconnects);
+                        break Err(error::Error::Connection);
+                    }
+                    // The Socket.IO client only reconnects by itself after
+                    // connection losses, not when the server disconnects us.
+                    if reason.needs_lookup() {
+                        log::info!("Looking up the socket server again before reconnecting");
+                        lookup_request.store(true, Ordering::Relaxed);
+                    }
+                    reconnect_request.notify_one();
+                    // Nothing new arrives while disconnected, so persist what we have.
+                    sink.flush().await;
+                }
+                Event::Reconnecting => {
+                    log::warn!("Connection lost, reconnecting");
+                    if !reconnect_budget.reconnect(std::time::Instant::now()) {
+                        log::error!("Giving up after {} reconnects", max_reconnects);
+                        break Err(error::Error::Connection);
+                    }
+                }
+                Event::ReconnectFailed => {
+                    if !reconnect_budget.reconnect(std::time::Instant::now()) {
+                        log::error!("Giving up after {} reconnects", max_reconnects);
+                        break Err(error::Error::Connection);
+                    }
+                    lookup_request.store(true, Ordering::Relaxed);
+                    reconnect_request.notify_one();
+                }
+                Event::ChatFilters(values) => {
+                    if apply_filters && let Some(compiled) = handle_chat_filters_event(values) {
+                        chat_filters = compiled;
+                    }
+                }
+                Event::EmoteList(values) => {
+                    if expand_emotes && let Some(channel_emotes) = handle_emote_list_event(values) {
+                        emote_images = emotes::merge(&file_emotes, channel_emotes);
+                    }
+                }
+                Event::Error(values) => {
+                    if let Err(e) = handle_error_event(values) {
+                        break Err(e);
+                    }
+                }
+                Event::Announcement(values) => {
+                    handle_announcement_event(values, log_announcements.then_some(&mut sink)).await
+                }
+                Event::Delete(values) => {
+                    handle_delete_event(values, log_deletions.then_some(&mut sink)).await
+                }
+                Event::ErrorMsg(values) => handle_error_msg_event(values),
+                Event::MediaUpdate(values) => {
+                    if let Some(file) = &mut playback_file {
+                        handle_media_update_event(values, file, &mut playback_throttle).await;
                     }
                 }
-                Event::Disconnect => {
-                    log::warn!("Client disconnected from server");
+                Event::SetMotd => log::debug!("Received channel MOTD"),
+                Event::UserList(values) => handle_user_list_event(values, &mut user_ranks),
+                Event::AddUser(values) => handle_add_user_event(values, &mut user_ranks),
+                Event::UserLeave(values) => handle_user_leave_event(values, &mut user_ranks),
+                Event::SetUserRank(values) => handle_set_user_rank_event(values, &mut user_ranks),
+                Event::NewPoll(values) if log_command_acks => {
+                    poll = parse_poll(values);
+                    if let Some(poll) = &poll {
+                        logger::command_ack(format_args!("Poll opened: {}", poll));
+                    }
+                }
+                Event::UpdatePoll(values) if log_command_acks => {
+                    if let Some(update) = parse_poll(values) {
+                        log::debug!("Poll updated: {}", update);
+                        poll = Some(update);
+                    }
+                }
+                Event::ClosePoll if log_command_acks => match poll.take() {
+                    Some(poll) => logger::command_ack(format_args!("Poll closed: {}", poll)),
+                    None => logger::command_ack(format_args!("Poll closed")),
+                },
+                Event::NewPoll(_) | Event::UpdatePoll(_) | Event::ClosePoll => {}
+                Event::SetAfk(values) => {
+                    let name = current_login_name(&manager_login_name);
+                    if let Some(afk) = handle_set_afk_event(values, name.as_deref()) {
+                        own_afk = afk;
+                    }
+                }
+                Event::Whisper(values) => {
+                    if let Some(file) = &mut whisper_file {
+                        let name = current_login_name(&manager_login_name);
+                        handle_whisper_event(values, name.as_deref(), file).await;
+                    }
+                }
+                Event::SetUserMeta(values) => handle_set_user_meta_event(
+                    values,
+                    current_login_name(&manager_login_name).as_deref(),
+                ),
+                Event::Kick(values) => {
+                    if let Err(e) = handle_kick_event(values) {
+                        break Err(e);
+                    }
+                }
+                Event::Login(values) => {
+                    let result = handle_login_event(values, &mut session);
+                    if result.is_ok() && mark_afk && !own_afk && session != data::Session::Anonymous
+                    {
+                        afk_request.notify_one();
+                    }
+                    match result {
+                        Ok(true) if reauthenticate => {
+                            log::warn!(
+                                "Session expired and was downgraded to a guest, logging in again as {}",
+                                current_login_name(&manager_login_name).unwrap_or_default()
+                            );
+                            relogin_request.notify_one();
+                        }
+                        Ok(true) => log::warn!("Session expired and was downgraded to a guest"),
+                        Err(error::Error::Login(reason))
+                            if guest_login
+                                && data::is_name_taken(&reason)
+                                && login_attempts < login_retries =>
+                        {
+                            login_attempts += 1;
+                            let mut name = manager_login_name
+                                .lock()
+                                .unwrap_or_else(std::sync::PoisonError::into_inner);
+                            let taken = name.take().unwrap_or_default();
+                            let next = utils::next_guest_name(&taken);
+                            log::warn!(
+                                "Login as {} failed: {} Retrying as {} ({}/{})",
+                                taken,
+                                reason,
+                                next,
+                                login_attempts,
+                                login_retries
+                            );
+                            *name = Some(next);
+                            relogin_request.notify_one();
+                        }
+                        Err(e) => break Err(e),
+                        Ok(false) => {}
+                    }
                 }
-                Event::Login(values) => handle_login_event(values),
                 Event::RotateLog => {
                     log::info!("Rotating log file...");
-                    match file_buffer.flush().await {
-                        Ok(()) => log::debug!("File buffer flushed"),
-                        Err(e) => log::error!("Failed to flush file buffer: {}", e),
-                    };
-                    let file = create_chat_log_file(&channel_name).await;
-                    file_buffer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, file);
+                    if let Err(e) = sink.rotate().await {
+                        log::error!("{:#}", e);
+                    }
+                    if let (Some(state), Some(path)) = (&mut state, &state_file) {
+                        state.bytes = previous_bytes + sink.bytes_written();
+                        if let Err(e) = state.save(path) {
+                            log::error!("{:#}", e);
+                        }
+                    }
                 }
                 Event::Terminate => {
+                    if drained.is_some() {
+                        continue;
+                    }
                     log::info!("Terminating cupcake");
-                    cancellation_token.cancel();
-                    break;
+                    let events = channel::drain_buffered(&mut rx);
+                    log::debug!("Processing {} buffered events", events.len());
+                    drained = Some(events.into_iter());
+                }
+                Event::UserCount(count) => {
+                    log::debug!("User count: {}", count);
+                    if let Some(file) = &mut usercount_file
+                        && usercount_throttle.should_log(count, std::time::Instant::now())
+                    {
+                        let line = format!("{}\t{}\n", Utc::now().timestamp_millis(), count);
+                        if let Err(e) = file.write_all(line.as_bytes()).await {
+                            log::warn!("Failed to write user count to file: {}", e);
+                        }
+                    }
                 }
             }
+        };
+        sink.close().await?;
+        log::debug!("File buffer flushed");
+        if let (Some(state), Some(path)) = (&mut state, &state_file) {
+            state.bytes = previous_bytes + sink.bytes_written();
+            if let Err(e) = state.save(path) {
+                log::error!("{:#}", e);
+            }
         }
-        match file_buffer.flush().await {
-            Ok(()) => log::debug!("File buffer flushed"),
-            Err(e) => log::error!("Failed to flush file buffer: {}", e),
+        for file in [
+            &mut media_file,
+            &mut usercount_file,
+            &mut playback_file,
+            &mut voteskip_file,
+            &mut skew_file,
+            &mut whisper_file,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if let Err(e) = file.flush().await {
+                log::error!("Failed to flush file: {}", e);
+            }
+        }
+        if let Some(file) = &mut raw_file
+            && let Err(e) = file.flush().await
+        {
+            log::error!("Failed to flush raw log file: {}", e);
+        }
+        if !no_summary {
+            // With --quiet, the summary bypasses the log level to still be shown.
+            let lines = std::iter::once(summary.to_string())
+                .chain(summary.team_breakdown())
+                .chain(summary.pattern_breakdown())
+                .chain(state.as_ref().map(state::State::totals));
+            for line in lines {
+                match quiet {
+                    true => eprintln!("{}", line),
+                    false => log::info!("{}", line),
+                }
+            }
         }
-    });
+        outcome.map_err(anyhow::Error::from)
+    };
+    #[cfg(feature = "tracing")]
+    let manager_task = manager_task.instrument(capture_span);
+    let mut manager = tokio::spawn(manager_task);
 
-    // Wait for SIGINT (Ctrl-C) to end the client.
-    match signal::ctrl_c().await {
-        Ok(()) => log::debug!("Received SIGINT"),
-        Err(err) => {
-            log::error!("Unable to listen to shutdown signal: {}", err);
+    // Wait for SIGINT (Ctrl-C), SIGTERM or the end of --duration to end the client,
+    // unless the manager task ends first.
+    let result = loop {
+        select! {
+            result = &mut manager => break result,
+            signal = shutdown_signal() => {
+                match signal {
+                    Ok(name) => log::debug!("Received {}", name),
+                    Err(err) => {
+                        log::error!("Unable to listen to shutdown signal: {}", err);
+                    }
+                }
+            }
+            _ = tokio::time::sleep_until(capture_deadline.unwrap_or_else(Instant::now)), if capture_deadline.is_some() => {
+                log::info!("Capture duration elapsed");
+            }
+            _ = afk.notified() => {
+                if let Some(socket) = &socket {
+                    set_afk(socket).await;
+                }
+                continue;
+            }
+            _ = relogin.notified() => {
+                if let Some(socket) = &socket
+                    && let Some(name) = current_login_name(&login_name)
+                {
+                    login(socket, &name, args.password.as_ref()).await;
+                }
+                continue;
+            }
+            _ = reconnect.notified() => {
+                if lookup.swap(false, Ordering::Relaxed)
+                    && let Some(http_client) = &http_client
+                {
+                    match resolve_socket_address(&args, http_client).await {
+                        Ok(address) => socket_address = Some(address),
+                        Err(e) => log::error!("{:#}", e),
+                    }
+                }
+                if let Some(socket) = socket.take()
+                    && let Err(e) = socket.disconnect().await
+                {
+                    log::warn!("Failed to disconnect from server: {}", e);
+                }
+                if let Some(address) = &socket_address {
+                    match connect_socket_with_retries(address, &args, &login_name, &tx).await {
+                        Ok(client) => socket = Some(client),
+                        Err(e) => {
+                            log::error!("Reconnection failed: {}", e);
+                            // Let the manager retry or give up according to --max-reconnects.
+                            if let Err(e) = tx.send(Event::ReconnectFailed).await {
+                                log::error!("Could not send reconnect failure to channel: {}", e);
+                            }
+                        }
+                    }
+                }
+                continue;
+            }
         }
-    }
-    if let Err(e) = tx.send(Event::Terminate).await {
-        log::error!("Could not send termination signal: {}", e);
-    }
+        if let Err(e) = tx.send(Event::Terminate).await {
+            log::error!("Could not send termination signal: {}", e);
+        }
+        break manager.await;
+    };
 
-    manager.await.unwrap();
+    cancellation_token.cancel();
     if let Some(rotate_task) = rotate_task {
-        rotate_task.await.unwrap();
+        rotate_task.await.context("Log rotation task failed")?;
+    }
+    #[cfg(feature = "broadcast")]
+    if let Some(webhook_task) = webhook_task {
+        webhook_task.await.context("Webhook task failed")?;
     }
 
     // Disconnect the WebSocket client.
     log::info!("Disconnecting client");
-    socket
-        .disconnect()
-        .await
-        .expect("Failed to disconnect from server");
+    let disconnected = match socket {
+        Some(socket) => socket
+            .disconnect()
+            .await
+            .context("Failed to disconnect from server"),
+        None => Ok(()),
+    };
+
+    result.context("Manager task failed")??;
+    disconnected
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+    use test_case::test_case;
+
+    use super::{Event, channel};
+
+    const DUMP: &str = concat!(
+        "not json\n",
+        r#"{"event":"usercount","time":1760634889000,"payload":[3]}"#,
+        "\n\n",
+        r#"{"event":"chatMsg","time":1760634889806,"payload":[{"username":"Dog","msg":"woof","meta":{},"time":1760634889800}]}"#,
+        "\n",
+        r#"{"event":"unknownEvent","time":1760634889900,"payload":[]}"#,
+        "\n",
+    );
+
+    #[test]
+    fn event_from_raw_chat() {
+        let event = Event::from_raw("chatMsg", vec![json!({"username": "Dog"})], 1760634889806);
+        assert!(matches!(event, Some(Event::Chat(values, 1760634889806)) if values.len() == 1));
+    }
+
+    #[test]
+    fn event_from_raw_unhandled() {
+        assert!(Event::from_raw("unknownEvent", Vec::new(), 0).is_none());
+    }
+
+    #[test]
+    fn dump_start() {
+        assert_eq!(super::dump_start(DUMP), Some(1760634889000));
+        assert_eq!(super::dump_start("not json\n"), None);
+    }
+
+    #[tokio::test]
+    async fn replay_dump() {
+        let (tx, mut rx) = channel::mpsc_channel();
+        tokio::spawn(super::replay_dump(DUMP.to_string(), tx));
+        let mut events = Vec::new();
+        while let Some(event) = channel::read_event(&mut rx).await {
+            let terminate = matches!(event, Event::Terminate);
+            events.push(event);
+            if terminate {
+                break;
+            }
+        }
+        assert_eq!(events.len(), 3);
+        assert!(matches!(events[0], Event::UserCount(3)));
+        assert!(matches!(events[1], Event::Chat(_, 1760634889806)));
+        assert!(matches!(events[2], Event::Terminate));
+    }
+
+    #[test_case(json!(42), Some(42) ; "count")]
+    #[test_case(json!(4294967295u64), Some(u32::MAX) ; "largest count")]
+    #[test_case(json!(4294967296u64), None ; "too large")]
+    #[test_case(json!(-1), None ; "negative")]
+    #[test_case(json!("42"), None ; "string")]
+    fn event_from_raw_user_count(value: Value, expected: Option<u32>) {
+        let count = match Event::from_raw("usercount", vec![value], 0) {
+            Some(Event::UserCount(count)) => Some(count),
+            Some(other) => panic!("Unexpected event {:?}", other),
+            None => None,
+        };
+        assert_eq!(count, expected);
+    }
 }
@@ -0,0 +1,167 @@
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+pub static METRICS: Metrics = Metrics::new();
+
+/// Counters exported in the Prometheus text format.
+pub struct Metrics {
+    messages: AtomicU64,
+    messages_skipped: AtomicU64,
+    connects: AtomicU64,
+    buffer_depth: AtomicU64,
+    last_message_timestamp: AtomicU64,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            messages: AtomicU64::new(0),
+            messages_skipped: AtomicU64::new(0),
+            connects: AtomicU64::new(0),
+            buffer_depth: AtomicU64::new(0),
+            last_message_timestamp: AtomicU64::new(0),
+        }
+    }
+
+    /// Record a message written to the chat log.
+    pub fn record_message(&self, time: u64) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+        self.last_message_timestamp.store(time, Ordering::Relaxed);
+    }
+
+    /// Count messages written in previous sessions, restored from the state file.
+    pub fn restore_messages(&self, count: u64) {
+        self.messages.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Record a message that was skipped instead of being written.
+    pub fn record_skipped(&self) {
+        self.messages_skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a successful connection to the server.
+    pub fn record_connect(&self) {
+        self.connects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the number of events waiting in the channel buffer.
+    pub fn set_buffer_depth(&self, depth: usize) {
+        self.buffer_depth.store(depth as u64, Ordering::Relaxed);
+    }
+
+    /// Render the metrics in the Prometheus text exposition format.
+    fn render(&self) -> String {
+        let reconnects = self.connects.load(Ordering::Relaxed).saturating_sub(1);
+        let metrics = [
+            (
+                "cupcake_messages_total",
+                "counter",
+                "Chat messages written to the log.",
+                self.messages.load(Ordering::Relaxed),
+            ),
+            (
+                "cupcake_messages_skipped_total",
+                "counter",
+                "Chat messages skipped instead of being written.",
+                self.messages_skipped.load(Ordering::Relaxed),
+            ),
+            (
+                "cupcake_reconnects_total",
+                "counter",
+                "Reconnections to the server.",
+                reconnects,
+            ),
+            (
+                "cupcake_channel_buffer_depth",
+                "gauge",
+                "Events waiting in the channel buffer.",
+                self.buffer_depth.load(Ordering::Relaxed),
+            ),
+            (
+                "cupcake_last_message_timestamp",
+                "gauge",
+                "Server timestamp of the last written message in milliseconds.",
+                self.last_message_timestamp.load(Ordering::Relaxed),
+            ),
+        ];
+        let mut output = String::new();
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} {}", name, kind);
+            let _ = writeln!(output, "{} {}", name, value);
+        }
+        output
+    }
+}
+
+/// Bind the metrics endpoint and serve requests in a background task.
+pub async fn serve(addr: SocketAddr) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    log::info!("Serving metrics on http://{}/metrics", addr);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle_request(stream));
+                }
+                Err(e) => log::warn!("Failed to accept metrics connection: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn handle_request(mut stream: TcpStream) {
+    let mut buffer = [0u8; 1024];
+    let read = match stream.read(&mut buffer).await {
+        Ok(n) => n,
+        Err(e) => {
+            log::debug!("Failed to read metrics request: {}", e);
+            return;
+        }
+    };
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let response = match request.split_whitespace().nth(1) {
+        Some("/metrics") => {
+            let body = METRICS.render();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        _ => {
+            String::from("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+        }
+    };
+    if let Err(e) = stream.write_all(response.as_bytes()).await {
+        log::debug!("Failed to write metrics response: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metrics;
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::new();
+        metrics.record_connect();
+        metrics.record_connect();
+        metrics.record_message(1760634889806);
+        metrics.record_skipped();
+        metrics.set_buffer_depth(3);
+        let output = metrics.render();
+        assert!(output.contains("\ncupcake_messages_total 1\n"));
+        assert!(output.contains("\ncupcake_messages_skipped_total 1\n"));
+        assert!(output.contains("\ncupcake_reconnects_total 1\n"));
+        assert!(output.contains("\ncupcake_channel_buffer_depth 3\n"));
+        assert!(output.contains("\ncupcake_last_message_timestamp 1760634889806\n"));
+        assert!(output.contains("# TYPE cupcake_messages_total counter\n"));
+    }
+}
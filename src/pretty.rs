@@ -0,0 +1,197 @@
+use std::ffi::OsStr;
+use std::io::IsTerminal;
+
+use chrono::DateTime;
+use owo_colors::{AnsiColors, OwoColorize};
+
+use crate::data::ChatMessage;
+
+const PALETTE: [AnsiColors; 12] = [
+    AnsiColors::Red,
+    AnsiColors::Green,
+    AnsiColors::Yellow,
+    AnsiColors::Blue,
+    AnsiColors::Magenta,
+    AnsiColors::Cyan,
+    AnsiColors::BrightRed,
+    AnsiColors::BrightGreen,
+    AnsiColors::BrightYellow,
+    AnsiColors::BrightBlue,
+    AnsiColors::BrightMagenta,
+    AnsiColors::BrightCyan,
+];
+
+/// When to color the pretty chat output and cupcake's own logs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// Color if stderr is a terminal and `NO_COLOR` is not set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Decide whether to color, following the NO_COLOR standard: a non-empty
+    /// `NO_COLOR` value disables automatic colors.
+    pub fn enabled(self, no_color: Option<&OsStr>, terminal: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => terminal && no_color.is_none_or(OsStr::is_empty),
+        }
+    }
+
+    /// Decide whether to color output written to stderr.
+    pub fn for_stderr(self) -> bool {
+        self.enabled(
+            std::env::var_os("NO_COLOR").as_deref(),
+            std::io::stderr().is_terminal(),
+        )
+    }
+}
+
+/// Pick a stable color for a name using FNV-1a hashing.
+fn name_color(name: &str) -> AnsiColors {
+    let mut hash: u32 = 0x811c9dc5;
+    for byte in name.bytes() {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    PALETTE[hash as usize % PALETTE.len()]
+}
+
+/// Format a chat message for the live terminal view.
+pub fn format_message(chat: &ChatMessage, colors: bool) -> String {
+    let line = format_body(chat, colors);
+    match (chat.is_edited(), colors) {
+        (false, _) => line,
+        (true, false) => format!("{} (edited)", line),
+        (true, true) => format!("{} {}", line, "(edited)".dimmed()),
+    }
+}
+
+fn format_body(chat: &ChatMessage, colors: bool) -> String {
+    let time = DateTime::from_timestamp_millis(chat.time as i64)
+        .map(|t| t.format("%H:%M:%S").to_string())
+        .unwrap_or_default();
+    let team = chat
+        .team_name()
+        .map(|name| format!("[{}] ", name))
+        .unwrap_or_default();
+
+    if !colors {
+        if chat.is_action() {
+            return format!("{} {}* {} {}", time, team, chat.username, chat.text());
+        }
+        return format!("{} {}<{}> {}", time, team, chat.username, chat.text());
+    }
+    if chat.is_server_whisper() {
+        return format_body(chat, false).dimmed().to_string();
+    }
+    let team = match chat.team_name() {
+        Some(name) => team.color(name_color(name)).to_string(),
+        None => team,
+    };
+    // Site-wide administrators are always shown in red, like on Cytube.
+    let username_color = match chat.is_from_superadmin() {
+        true => AnsiColors::Red,
+        false => name_color(&chat.username),
+    };
+    let username = chat.username.color(username_color).bold().to_string();
+    if chat.is_action() {
+        return format!(
+            "{} {}* {} {}",
+            time.dimmed(),
+            team,
+            username,
+            chat.text().italic()
+        );
+    }
+    let text = match chat.is_shout() {
+        true => chat.text().bold().to_string(),
+        false => chat.text().to_string(),
+    };
+    format!("{} {}<{}> {}", time.dimmed(), team, username, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use std::ffi::OsStr;
+
+    use test_case::test_case;
+
+    use super::{ColorChoice, format_message, name_color};
+    use crate::data::ChatMessage;
+
+    #[test_case(ColorChoice::Auto, None, true, true; "auto on terminal")]
+    #[test_case(ColorChoice::Auto, None, false, false; "auto on pipe")]
+    #[test_case(ColorChoice::Auto, Some("1"), true, false; "auto with no color")]
+    #[test_case(ColorChoice::Auto, Some(""), true, true; "auto with empty no color")]
+    #[test_case(ColorChoice::Always, Some("1"), false, true; "always")]
+    #[test_case(ColorChoice::Never, None, true, false; "never")]
+    fn color_choice(choice: ColorChoice, no_color: Option<&str>, terminal: bool, expected: bool) {
+        assert_eq!(choice.enabled(no_color.map(OsStr::new), terminal), expected);
+    }
+
+    #[test]
+    fn format_message_plain() {
+        let chat: ChatMessage = serde_json::from_value(json!({
+            "username": "PotF",
+            "msg": "&gt;XD <span style=\"display:none\" class=\"teamColorSpan\">-teamwg-</span>",
+            "meta": {},
+            "time": 1760634672025u64
+        }))
+        .unwrap();
+        assert_eq!(format_message(&chat, false), "17:11:12 [wg] <PotF> &gt;XD");
+    }
+
+    #[test]
+    fn format_message_no_team() {
+        let chat: ChatMessage = serde_json::from_value(json!({
+            "username": "Yuu",
+            "msg": "It's hip to be square.",
+            "meta": {},
+            "time": 1760631669671u64
+        }))
+        .unwrap();
+        assert_eq!(
+            format_message(&chat, false),
+            "16:21:09 <Yuu> It's hip to be square."
+        );
+    }
+
+    #[test]
+    fn format_message_action() {
+        let chat: ChatMessage = serde_json::from_value(json!({
+            "username": "Dog",
+            "msg": "barks",
+            "meta": {"addClass": "action"},
+            "time": 1760634889806u64
+        }))
+        .unwrap();
+        assert_eq!(format_message(&chat, false), "17:14:49 * Dog barks");
+    }
+
+    #[test]
+    fn format_message_edited() {
+        let chat: ChatMessage = serde_json::from_value(json!({
+            "username": "Yuu",
+            "msg": "It's hip to be square.",
+            "meta": {"edited": true},
+            "time": 1760631669671u64
+        }))
+        .unwrap();
+        assert_eq!(
+            format_message(&chat, false),
+            "16:21:09 <Yuu> It's hip to be square. (edited)"
+        );
+    }
+
+    #[test]
+    fn name_color_stable() {
+        assert_eq!(name_color("ChetBaker"), name_color("ChetBaker"));
+    }
+}
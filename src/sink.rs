@@ -0,0 +1,1047 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use futures_util::FutureExt;
+use futures_util::future::BoxFuture;
+use tokio::fs::File;
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter, Stdout};
+
+use crate::archive::Manifest;
+use crate::chain::HashChain;
+use crate::data::ChatMessage;
+use crate::format::{FileMeta, OutputFormat};
+
+/// Destination for logged chat messages.
+///
+/// Methods return boxed futures so that sinks can be combined as trait objects.
+pub trait MessageSink: Send {
+    /// Write a chat message, with its sequence number if numbering is enabled.
+    fn write<'a>(
+        &'a mut self,
+        chat: &'a ChatMessage,
+        seq: Option<u64>,
+    ) -> BoxFuture<'a, anyhow::Result<()>>;
+
+    /// Write a notice about a non-chat event, such as rate limiting.
+    fn write_notice<'a>(
+        &'a mut self,
+        time: i64,
+        event: &'a str,
+        detail: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<()>>;
+
+    /// Start a new output segment when the log is rotated.
+    fn rotate(&mut self) -> BoxFuture<'_, anyhow::Result<()>> {
+        async { Ok(()) }.boxed()
+    }
+
+    /// Flush buffered output, logging any errors.
+    fn flush(&mut self) -> BoxFuture<'_, ()>;
+
+    /// Finish the output, e.g. close a JSON array, and flush it.
+    fn close(&mut self) -> BoxFuture<'_, anyhow::Result<()>>;
+
+    /// Bytes written to chat log files so far.
+    fn bytes_written(&self) -> u64 {
+        0
+    }
+}
+
+/// Chat output selectable on the command line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum SinkKind {
+    /// Timestamped chat log files.
+    File,
+    /// Standard output.
+    Stdout,
+}
+
+/// Settings for creating chat log files.
+#[derive(Clone)]
+pub struct ChatLogOptions {
+    pub domain: String,
+    pub channel: String,
+    pub format: OutputFormat,
+    pub header_comment: bool,
+    /// Fixed path of the chat log instead of a timestamped file.
+    pub path: Option<PathBuf>,
+    /// Add a running hash chain to every line.
+    pub hash_chain: bool,
+    /// Write chat log files under a temporary name until they are finished.
+    pub atomic: bool,
+    /// Compress finished chat log files into `.tar.gz` archives.
+    pub archive: bool,
+    /// Delete chat log files once they are archived.
+    pub delete_archived: bool,
+    /// Collect this many records before writing them at once.
+    pub batch_size: Option<usize>,
+    /// Unix permission bits of new chat log files.
+    pub file_mode: Option<u32>,
+    /// Hosts whose links are listed in JSON records.
+    pub link_hosts: Vec<String>,
+}
+
+/// Records collected to be written with a single write.
+struct Batch {
+    records: String,
+    len: usize,
+    size: usize,
+}
+
+/// Buffered chat log in the chosen output format.
+struct ChatLog<W: AsyncWrite + Unpin> {
+    buffer: BufWriter<W>,
+    format: OutputFormat,
+    link_hosts: Vec<String>,
+    empty: bool,
+    chain: Option<HashChain>,
+    written: u64,
+    /// Reused buffer for formatting chat message records.
+    scratch: String,
+    batch: Option<Batch>,
+}
+
+impl<W: AsyncWrite + Unpin> ChatLog<W> {
+    /// Start the chat log, with the metadata header if requested. A hash
+    /// chain continues from the seed hash if given.
+    async fn start(
+        writer: W,
+        options: &ChatLogOptions,
+        seed: Option<String>,
+    ) -> anyhow::Result<Self> {
+        let mut chat_log = Self {
+            buffer: BufWriter::with_capacity(crate::WRITE_BUFFER_SIZE, writer),
+            format: options.format,
+            link_hosts: options.link_hosts.clone(),
+            empty: true,
+            chain: options.hash_chain.then(|| HashChain::new(seed)),
+            written: 0,
+            scratch: String::new(),
+            batch: options.batch_size.map(|size| Batch {
+                records: String::new(),
+                len: 0,
+                size,
+            }),
+        };
+        let opening = options.format.opening();
+        chat_log
+            .buffer
+            .write_all(opening.as_bytes())
+            .await
+            .context("Could not write to chat log")?;
+        chat_log.written += opening.len() as u64;
+        if options.header_comment {
+            let meta = FileMeta::new(&options.domain, &options.channel);
+            chat_log
+                .write_record(&options.format.format_meta(&meta))
+                .await
+                .context("Could not write chat log header")?;
+        }
+        Ok(chat_log)
+    }
+
+    /// Write a record with its delimiters, copying it only to seal it with the hash chain.
+    async fn write_record(&mut self, record: &str) -> std::io::Result<()> {
+        let sealed = self.chain.as_mut().map(|chain| chain.seal(record));
+        let record = sealed.as_deref().unwrap_or(record);
+        let (prefix, suffix) = self.format.delimiters(self.empty);
+        self.empty = false;
+        if let Some(batch) = &mut self.batch {
+            for part in [prefix, record, suffix] {
+                batch.records.push_str(part);
+            }
+            batch.len += 1;
+            if batch.len >= batch.size {
+                self.write_batch().await?;
+            }
+            return Ok(());
+        }
+        for part in [prefix, record, suffix] {
+            self.buffer.write_all(part.as_bytes()).await?;
+            self.written += part.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Write the collected records, if any.
+    async fn write_batch(&mut self) -> std::io::Result<()> {
+        let Some(batch) = &mut self.batch else {
+            return Ok(());
+        };
+        if batch.len == 0 {
+            return Ok(());
+        }
+        self.buffer.write_all(batch.records.as_bytes()).await?;
+        self.written += batch.records.len() as u64;
+        batch.records.clear();
+        batch.len = 0;
+        Ok(())
+    }
+
+    /// Records are waiting in the batch, so live outputs need not flush yet.
+    fn has_batched(&self) -> bool {
+        self.batch.as_ref().is_some_and(|batch| batch.len > 0)
+    }
+
+    async fn write_chat(&mut self, chat: &ChatMessage, seq: Option<u64>) -> anyhow::Result<()> {
+        let mut record = std::mem::take(&mut self.scratch);
+        record.clear();
+        self.format
+            .write_chat(&mut record, chat, seq, &self.link_hosts);
+        let result = self.write_record(&record).await;
+        self.scratch = record;
+        result.with_context(|| format!("Failed to write '{}' to file buffer", chat))
+    }
+
+    async fn write_notice(&mut self, time: i64, event: &str, detail: &str) -> anyhow::Result<()> {
+        let record = self.format.format_notice(time, event, detail);
+        self.write_record(&record)
+            .await
+            .with_context(|| format!("Failed to write {} notice to file buffer", event))
+    }
+
+    async fn flush(&mut self) {
+        if let Err(e) = self.write_batch().await {
+            log::error!("Failed to write batched records: {}", e);
+        }
+        match self.buffer.flush().await {
+            Ok(()) => log::debug!("File buffer flushed"),
+            Err(e) => log::error!("Failed to flush file buffer: {}", e),
+        }
+    }
+
+    async fn finish(&mut self) -> anyhow::Result<()> {
+        self.write_batch()
+            .await
+            .context("Failed to write batched records")?;
+        let closing = self.format.closing();
+        self.buffer
+            .write_all(closing.as_bytes())
+            .await
+            .context("Failed to write to file buffer")?;
+        self.written += closing.len() as u64;
+        self.buffer
+            .flush()
+            .await
+            .context("Failed to flush file buffer")
+    }
+}
+
+/// Chat log file written under a temporary name until it is finished.
+struct PendingRename {
+    temp: PathBuf,
+    path: PathBuf,
+}
+
+impl PendingRename {
+    fn new(path: PathBuf) -> Self {
+        let mut temp = path.clone().into_os_string();
+        temp.push(".tmp");
+        Self {
+            temp: temp.into(),
+            path,
+        }
+    }
+
+    /// Move the finished file into place. If that fails, the chat log is
+    /// left in the temporary file.
+    async fn finish(&self) -> anyhow::Result<()> {
+        tokio::fs::rename(&self.temp, &self.path)
+            .await
+            .with_context(|| {
+                format!(
+                    "Could not rename {} to {}, the chat log is kept in the former",
+                    self.temp.display(),
+                    self.path.display()
+                )
+            })?;
+        log::info!("Finished chat log file {}", self.path.display());
+        Ok(())
+    }
+}
+
+/// Writes chat messages into timestamped chat log files.
+pub struct FileSink {
+    chat_log: ChatLog<File>,
+    /// Final path of the current chat log file.
+    path: PathBuf,
+    pending: Option<PendingRename>,
+    /// Metadata and number of messages of the current file for its archive.
+    meta: FileMeta,
+    messages: u64,
+    /// Bytes written to previous chat log files.
+    rotated_bytes: u64,
+    options: ChatLogOptions,
+}
+
+impl FileSink {
+    pub async fn create(options: ChatLogOptions) -> anyhow::Result<Self> {
+        let (chat_log, path, pending) = Self::open(&options).await?;
+        Ok(Self {
+            chat_log,
+            path,
+            pending,
+            meta: FileMeta::new(&options.domain, &options.channel),
+            messages: 0,
+            rotated_bytes: 0,
+            options,
+        })
+    }
+
+    /// Path of a new chat log file.
+    fn new_path(options: &ChatLogOptions) -> PathBuf {
+        match &options.path {
+            Some(path) => path.clone(),
+            None => {
+                let extension = options.format.extension();
+                crate::log_file_name("chat", &options.channel, extension).into()
+            }
+        }
+    }
+
+    /// Open a new chat log, under a temporary name in atomic mode. Returns
+    /// the chat log and its final path.
+    async fn open(
+        options: &ChatLogOptions,
+    ) -> anyhow::Result<(ChatLog<File>, PathBuf, Option<PendingRename>)> {
+        let path = Self::new_path(options);
+        if !options.atomic {
+            let chat_log = Self::create_chat_log(&path, options).await?;
+            return Ok((chat_log, path, None));
+        }
+        let pending = PendingRename::new(path.clone());
+        let chat_log = match options.hash_chain && pending.path.exists() {
+            true => {
+                tokio::fs::copy(&pending.path, &pending.temp)
+                    .await
+                    .with_context(|| {
+                        format!(
+                            "Could not copy output file {} to {}",
+                            pending.path.display(),
+                            pending.temp.display()
+                        )
+                    })?;
+                Self::append_chat_log(&pending.temp, options).await?
+            }
+            false => {
+                let file = Self::create_file(&pending.temp, options).await?;
+                log::info!("Created chat log file {}", pending.temp.display());
+                ChatLog::start(file, options, None).await?
+            }
+        };
+        Ok((chat_log, path, Some(pending)))
+    }
+
+    /// Finish the current chat log, move it into place in atomic mode and
+    /// archive it if requested.
+    async fn finish(&mut self) -> anyhow::Result<()> {
+        self.chat_log.finish().await?;
+        if let Some(pending) = self.pending.take() {
+            pending.finish().await?;
+        }
+        if self.options.archive {
+            self.archive().await?;
+        }
+        Ok(())
+    }
+
+    /// Compress the finished chat log file, deleting it afterwards if requested.
+    async fn archive(&mut self) -> anyhow::Result<()> {
+        let path = self.path.clone();
+        let manifest = Manifest {
+            meta: std::mem::replace(
+                &mut self.meta,
+                FileMeta::new(&self.options.domain, &self.options.channel),
+            ),
+            file: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            messages: std::mem::take(&mut self.messages),
+        };
+        let mode = self.options.file_mode;
+        let archive =
+            tokio::task::spawn_blocking(move || crate::archive::create(&path, &manifest, mode))
+                .await
+                .context("Archiving task failed")??;
+        log::info!("Archived chat log to {}", archive.display());
+        if self.options.delete_archived {
+            tokio::fs::remove_file(&self.path).await.with_context(|| {
+                format!("Could not delete archived file {}", self.path.display())
+            })?;
+            log::info!("Deleted archived chat log file {}", self.path.display());
+        }
+        Ok(())
+    }
+
+    async fn create_chat_log(
+        path: &Path,
+        options: &ChatLogOptions,
+    ) -> anyhow::Result<ChatLog<File>> {
+        if options.hash_chain && options.path.is_some() && path.exists() {
+            return Self::append_chat_log(path, options).await;
+        }
+        let file = Self::create_file(path, options).await?;
+        log::info!("Created chat log file {}", path.display());
+        ChatLog::start(file, options, None).await
+    }
+
+    /// Create a chat log file with the permissions of --file-mode.
+    async fn create_file(path: &Path, options: &ChatLogOptions) -> anyhow::Result<File> {
+        tokio::fs::OpenOptions::from(crate::utils::create_options(options.file_mode))
+            .open(path)
+            .await
+            .with_context(|| format!("Could not create output file {}", path.display()))
+    }
+
+    /// Append to an existing hash-chained chat log, continuing its chain.
+    async fn append_chat_log(
+        path: &Path,
+        options: &ChatLogOptions,
+    ) -> anyhow::Result<ChatLog<File>> {
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Could not read output file {}", path.display()))?;
+        let seed = match content.is_empty() {
+            true => None,
+            false => Some(crate::chain::last_hash(&content).with_context(|| {
+                format!(
+                    "Output file {} does not end with a hash-chained line",
+                    path.display()
+                )
+            })?),
+        };
+        let file = File::options()
+            .append(true)
+            .open(path)
+            .await
+            .with_context(|| format!("Could not open output file {}", path.display()))?;
+        log::info!("Appending to chat log file {}", path.display());
+        ChatLog::start(file, options, seed).await
+    }
+}
+
+impl MessageSink for FileSink {
+    fn write<'a>(
+        &'a mut self,
+        chat: &'a ChatMessage,
+        seq: Option<u64>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            self.chat_log.write_chat(chat, seq).await?;
+            self.messages += 1;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn write_notice<'a>(
+        &'a mut self,
+        time: i64,
+        event: &'a str,
+        detail: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        self.chat_log.write_notice(time, event, detail).boxed()
+    }
+
+    fn rotate(&mut self) -> BoxFuture<'_, anyhow::Result<()>> {
+        async move {
+            if let Err(e) = self.finish().await {
+                log::error!("{:#}", e);
+            }
+            self.rotated_bytes += self.chat_log.written;
+            (self.chat_log, self.path, self.pending) = Self::open(&self.options).await?;
+            self.meta = FileMeta::new(&self.options.domain, &self.options.channel);
+            self.messages = 0;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn flush(&mut self) -> BoxFuture<'_, ()> {
+        self.chat_log.flush().boxed()
+    }
+
+    fn close(&mut self) -> BoxFuture<'_, anyhow::Result<()>> {
+        self.finish().boxed()
+    }
+
+    fn bytes_written(&self) -> u64 {
+        self.rotated_bytes + self.chat_log.written
+    }
+}
+
+/// Writes chat messages to standard output, e.g. for piping into another program.
+pub struct StdoutSink {
+    chat_log: ChatLog<Stdout>,
+}
+
+impl StdoutSink {
+    pub async fn create(options: &ChatLogOptions) -> anyhow::Result<Self> {
+        let chat_log = ChatLog::start(tokio::io::stdout(), options, None).await?;
+        Ok(Self { chat_log })
+    }
+}
+
+impl MessageSink for StdoutSink {
+    fn write<'a>(
+        &'a mut self,
+        chat: &'a ChatMessage,
+        seq: Option<u64>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            self.chat_log.write_chat(chat, seq).await?;
+            if self.chat_log.has_batched() {
+                return Ok(());
+            }
+            // Flush every message or batch so that readers see the chat live.
+            self.chat_log
+                .buffer
+                .flush()
+                .await
+                .context("Failed to flush standard output")
+        }
+        .boxed()
+    }
+
+    fn write_notice<'a>(
+        &'a mut self,
+        time: i64,
+        event: &'a str,
+        detail: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        self.chat_log.write_notice(time, event, detail).boxed()
+    }
+
+    fn flush(&mut self) -> BoxFuture<'_, ()> {
+        self.chat_log.flush().boxed()
+    }
+
+    fn close(&mut self) -> BoxFuture<'_, anyhow::Result<()>> {
+        self.chat_log.finish().boxed()
+    }
+}
+
+/// Path is a named pipe (FIFO).
+#[cfg(unix)]
+pub fn is_fifo(path: &std::path::Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_fifo())
+}
+
+/// Writes chat messages into a named pipe for streaming into another process.
+///
+/// Writes are paused while no reader is attached and resumed once one
+/// attaches again; messages in between are not written.
+#[cfg(unix)]
+pub struct FifoSink {
+    path: PathBuf,
+    options: ChatLogOptions,
+    chat_log: Option<ChatLog<File>>,
+}
+
+#[cfg(unix)]
+impl FifoSink {
+    /// Open the FIFO for writing, waiting until a reader attaches.
+    pub async fn open(path: PathBuf, options: ChatLogOptions) -> anyhow::Result<Self> {
+        log::info!("Waiting for a reader on FIFO {}", path.display());
+        // Opening a FIFO must not truncate, and blocks until there is a reader.
+        let file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("Could not open FIFO {}", path.display()))?;
+        log::info!("Opened FIFO {}", path.display());
+        let chat_log = Some(ChatLog::start(file, &options, None).await?);
+        Ok(Self {
+            path,
+            options,
+            chat_log,
+        })
+    }
+
+    /// Chat log to write to, reopening the FIFO if writes are paused and a
+    /// reader has attached since.
+    async fn resume(&mut self) -> anyhow::Result<Option<&mut ChatLog<File>>> {
+        if self.chat_log.is_none() && self.has_reader() {
+            let file = File::options()
+                .write(true)
+                .open(&self.path)
+                .await
+                .with_context(|| format!("Could not open FIFO {}", self.path.display()))?;
+            log::info!("Reader attached to FIFO, resuming writes");
+            self.chat_log = Some(ChatLog::start(file, &self.options, None).await?);
+        }
+        Ok(self.chat_log.as_mut())
+    }
+
+    /// FIFO has a reader. Opening for writing without blocking fails if not.
+    fn has_reader(&self) -> bool {
+        use std::os::unix::fs::OpenOptionsExt;
+        std::fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.path)
+            .is_ok()
+    }
+
+    /// Pause writes if the reader has disconnected instead of failing.
+    fn pause_on_broken_pipe(&mut self, result: anyhow::Result<()>) -> anyhow::Result<()> {
+        let Err(e) = result else {
+            return Ok(());
+        };
+        let broken_pipe = e
+            .chain()
+            .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+            .any(|cause| cause.kind() == std::io::ErrorKind::BrokenPipe);
+        if !broken_pipe {
+            return Err(e);
+        }
+        log::warn!("Reader of FIFO disconnected, pausing writes");
+        self.chat_log = None;
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+impl MessageSink for FifoSink {
+    fn write<'a>(
+        &'a mut self,
+        chat: &'a ChatMessage,
+        seq: Option<u64>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let Some(chat_log) = self.resume().await? else {
+                return Ok(());
+            };
+            let result = match chat_log.write_chat(chat, seq).await {
+                Ok(()) if chat_log.has_batched() => Ok(()),
+                // Flush every message or batch so that the reader sees the chat live.
+                Ok(()) => chat_log
+                    .buffer
+                    .flush()
+                    .await
+                    .context("Failed to flush FIFO"),
+                Err(e) => Err(e),
+            };
+            self.pause_on_broken_pipe(result)
+        }
+        .boxed()
+    }
+
+    fn write_notice<'a>(
+        &'a mut self,
+        time: i64,
+        event: &'a str,
+        detail: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let Some(chat_log) = self.resume().await? else {
+                return Ok(());
+            };
+            let result = chat_log.write_notice(time, event, detail).await;
+            self.pause_on_broken_pipe(result)
+        }
+        .boxed()
+    }
+
+    fn flush(&mut self) -> BoxFuture<'_, ()> {
+        async move {
+            if let Some(chat_log) = &mut self.chat_log {
+                chat_log.flush().await;
+            }
+        }
+        .boxed()
+    }
+
+    fn close(&mut self) -> BoxFuture<'_, anyhow::Result<()>> {
+        async move {
+            let result = match &mut self.chat_log {
+                Some(chat_log) => chat_log.finish().await,
+                None => Ok(()),
+            };
+            self.pause_on_broken_pipe(result)
+        }
+        .boxed()
+    }
+}
+
+/// Writes chat messages to all of its sinks in order.
+///
+/// Failing sinks do not prevent writing to the others; the first error is returned.
+#[derive(Default)]
+pub struct MultiSink {
+    sinks: Vec<Box<dyn MessageSink>>,
+}
+
+impl MultiSink {
+    pub fn push(&mut self, sink: Box<dyn MessageSink>) {
+        self.sinks.push(sink);
+    }
+}
+
+/// Keep the first error of the results.
+fn first_error(results: Vec<anyhow::Result<()>>) -> anyhow::Result<()> {
+    results.into_iter().find(Result::is_err).unwrap_or(Ok(()))
+}
+
+impl MessageSink for MultiSink {
+    fn write<'a>(
+        &'a mut self,
+        chat: &'a ChatMessage,
+        seq: Option<u64>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let mut results = Vec::with_capacity(self.sinks.len());
+            for sink in &mut self.sinks {
+                results.push(sink.write(chat, seq).await);
+            }
+            first_error(results)
+        }
+        .boxed()
+    }
+
+    fn write_notice<'a>(
+        &'a mut self,
+        time: i64,
+        event: &'a str,
+        detail: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let mut results = Vec::with_capacity(self.sinks.len());
+            for sink in &mut self.sinks {
+                results.push(sink.write_notice(time, event, detail).await);
+            }
+            first_error(results)
+        }
+        .boxed()
+    }
+
+    fn rotate(&mut self) -> BoxFuture<'_, anyhow::Result<()>> {
+        async move {
+            let mut results = Vec::with_capacity(self.sinks.len());
+            for sink in &mut self.sinks {
+                results.push(sink.rotate().await);
+            }
+            first_error(results)
+        }
+        .boxed()
+    }
+
+    fn flush(&mut self) -> BoxFuture<'_, ()> {
+        async move {
+            for sink in &mut self.sinks {
+                sink.flush().await;
+            }
+        }
+        .boxed()
+    }
+
+    fn close(&mut self) -> BoxFuture<'_, anyhow::Result<()>> {
+        async move {
+            let mut results = Vec::with_capacity(self.sinks.len());
+            for sink in &mut self.sinks {
+                results.push(sink.close().await);
+            }
+            first_error(results)
+        }
+        .boxed()
+    }
+
+    fn bytes_written(&self) -> u64 {
+        self.sinks.iter().map(|sink| sink.bytes_written()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use futures_util::FutureExt;
+    use futures_util::future::BoxFuture;
+    use serde_json::json;
+    use tokio::select;
+
+    use super::{MessageSink, MultiSink};
+    use crate::data::ChatMessage;
+
+    /// Sink that records what was written into a shared list.
+    struct RecordingSink {
+        name: &'static str,
+        records: Arc<Mutex<Vec<String>>>,
+        fail: bool,
+    }
+
+    impl RecordingSink {
+        fn record(&self, record: String) -> anyhow::Result<()> {
+            self.records.lock().unwrap().push(record);
+            match self.fail {
+                true => Err(anyhow::anyhow!("{} failed", self.name)),
+                false => Ok(()),
+            }
+        }
+    }
+
+    impl MessageSink for RecordingSink {
+        fn write<'a>(
+            &'a mut self,
+            chat: &'a ChatMessage,
+            _seq: Option<u64>,
+        ) -> BoxFuture<'a, anyhow::Result<()>> {
+            let result = self.record(format!("{}: {}", self.name, chat.text()));
+            async move { result }.boxed()
+        }
+
+        fn write_notice<'a>(
+            &'a mut self,
+            _time: i64,
+            event: &'a str,
+            _detail: &'a str,
+        ) -> BoxFuture<'a, anyhow::Result<()>> {
+            let result = self.record(format!("{}: {}", self.name, event));
+            async move { result }.boxed()
+        }
+
+        fn flush(&mut self) -> BoxFuture<'_, ()> {
+            async {}.boxed()
+        }
+
+        fn close(&mut self) -> BoxFuture<'_, anyhow::Result<()>> {
+            let result = self.record(format!("{}: close", self.name));
+            async move { result }.boxed()
+        }
+    }
+
+    fn chat(text: &str) -> ChatMessage {
+        serde_json::from_value(json!({
+            "username": "Yuu",
+            "msg": text,
+            "meta": {},
+            "time": 1760631669671u64
+        }))
+        .unwrap()
+    }
+
+    fn multi_sink(records: &Arc<Mutex<Vec<String>>>, failing: &str) -> MultiSink {
+        let mut sink = MultiSink::default();
+        for name in ["first", "second"] {
+            sink.push(Box::new(RecordingSink {
+                name,
+                records: records.clone(),
+                fail: name == failing,
+            }));
+        }
+        sink
+    }
+
+    #[tokio::test]
+    async fn multi_sink_order() {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let mut sink = multi_sink(&records, "");
+        sink.write(&chat("one"), None).await.unwrap();
+        sink.write_notice(0, "rateLimit", "").await.unwrap();
+        sink.write(&chat("two"), None).await.unwrap();
+        sink.close().await.unwrap();
+        assert_eq!(
+            *records.lock().unwrap(),
+            vec![
+                "first: one",
+                "second: one",
+                "first: rateLimit",
+                "second: rateLimit",
+                "first: two",
+                "second: two",
+                "first: close",
+                "second: close",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn multi_sink_error() {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let mut sink = multi_sink(&records, "first");
+        let result = sink.write(&chat("one"), None).await;
+        assert_eq!(result.unwrap_err().to_string(), "first failed");
+        assert_eq!(*records.lock().unwrap(), vec!["first: one", "second: one"]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn fifo_sink_pauses_without_reader() {
+        use std::io::Read;
+        use std::os::unix::ffi::OsStrExt;
+
+        use super::{ChatLogOptions, FifoSink, is_fifo};
+        use crate::format::OutputFormat;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chat.fifo");
+        let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+        assert!(is_fifo(&path));
+
+        let reader_path = path.clone();
+        let reader = std::thread::spawn(move || {
+            let mut file = std::fs::File::open(reader_path).unwrap();
+            let mut line = [0u8; 64];
+            let read = file.read(&mut line).unwrap();
+            String::from_utf8_lossy(&line[..read]).into_owned()
+        });
+        let options = ChatLogOptions {
+            domain: "cytu.be".into(),
+            channel: "test".into(),
+            format: OutputFormat::Tsv,
+            header_comment: false,
+            path: Some(path.clone()),
+            hash_chain: false,
+            atomic: false,
+            archive: false,
+            delete_archived: false,
+            batch_size: None,
+            file_mode: None,
+            link_hosts: Vec::new(),
+        };
+        let mut sink = FifoSink::open(path.clone(), options).await.unwrap();
+        sink.write(&chat("one"), None).await.unwrap();
+        assert_eq!(reader.join().unwrap(), "1760631669671\tNULL\tYuu\tone\n");
+
+        // The reader is gone, so writes are paused instead of failing.
+        sink.write(&chat("two"), None).await.unwrap();
+        assert!(sink.chat_log.is_none());
+        sink.write(&chat("three"), None).await.unwrap();
+        sink.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn file_sink_atomic() {
+        use super::{ChatLogOptions, FileSink};
+        use crate::format::OutputFormat;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("atomic.tsv");
+        let temp = dir.path().join("atomic.tsv.tmp");
+        let options = ChatLogOptions {
+            domain: "cytu.be".into(),
+            channel: "test".into(),
+            format: OutputFormat::Tsv,
+            header_comment: false,
+            path: Some(path.clone()),
+            hash_chain: false,
+            atomic: true,
+            archive: false,
+            delete_archived: false,
+            batch_size: None,
+            file_mode: None,
+            link_hosts: Vec::new(),
+        };
+        let mut sink = FileSink::create(options).await.unwrap();
+        sink.write(&chat("one"), None).await.unwrap();
+        sink.flush().await;
+        assert!(temp.exists());
+        assert!(!path.exists());
+
+        sink.close().await.unwrap();
+        assert!(!temp.exists());
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content, "1760631669671\tNULL\tYuu\tone\n");
+    }
+
+    /// Notices such as heartbeats are written but not counted as messages.
+    #[tokio::test]
+    async fn file_sink_notice_not_counted() {
+        use super::{ChatLogOptions, FileSink};
+        use crate::format::OutputFormat;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heartbeat.tsv");
+        let options = ChatLogOptions {
+            domain: "cytu.be".into(),
+            channel: "test".into(),
+            format: OutputFormat::Tsv,
+            header_comment: false,
+            path: Some(path.clone()),
+            hash_chain: false,
+            atomic: false,
+            archive: false,
+            delete_archived: false,
+            batch_size: None,
+            file_mode: None,
+            link_hosts: Vec::new(),
+        };
+        let mut sink = FileSink::create(options).await.unwrap();
+        sink.write(&chat("one"), None).await.unwrap();
+        sink.write_notice(0, "heartbeat", "1m").await.unwrap();
+        assert_eq!(sink.messages, 1);
+        sink.close().await.unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            content,
+            "1760631669671\tNULL\tYuu\tone\n# 0\theartbeat\t1m\n"
+        );
+    }
+
+    fn batch_options(batch_size: usize) -> super::ChatLogOptions {
+        super::ChatLogOptions {
+            domain: "cytu.be".into(),
+            channel: "test".into(),
+            format: crate::format::OutputFormat::Tsv,
+            header_comment: false,
+            path: None,
+            hash_chain: false,
+            atomic: false,
+            archive: false,
+            delete_archived: false,
+            batch_size: Some(batch_size),
+            file_mode: None,
+            link_hosts: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn chat_log_batch_full() {
+        let mut chat_log = super::ChatLog::start(Vec::new(), &batch_options(2), None)
+            .await
+            .unwrap();
+        chat_log.write_chat(&chat("one"), None).await.unwrap();
+        assert!(chat_log.buffer.get_ref().is_empty());
+        assert!(chat_log.has_batched());
+        chat_log.write_chat(&chat("two"), None).await.unwrap();
+        assert!(!chat_log.has_batched());
+        chat_log.flush().await;
+        assert_eq!(
+            String::from_utf8_lossy(chat_log.buffer.get_ref()),
+            "1760631669671\tNULL\tYuu\tone\n1760631669671\tNULL\tYuu\ttwo\n"
+        );
+    }
+
+    /// The batch interval flushes a batch that is not full yet, like the
+    /// `--batch-interval` branch of the manager.
+    #[tokio::test(start_paused = true)]
+    async fn chat_log_batch_interval() {
+        let mut chat_log = super::ChatLog::start(Vec::new(), &batch_options(100), None)
+            .await
+            .unwrap();
+        let mut ticker = crate::utils::Ticker::new(Some(Duration::from_secs(1)));
+        chat_log.write_chat(&chat("one"), None).await.unwrap();
+        chat_log.write_notice(0, "rateLimit", "").await.unwrap();
+        loop {
+            select! {
+                _ = ticker.tick() => {
+                    chat_log.flush().await;
+                    break;
+                }
+                _ = tokio::time::sleep(Duration::from_millis(100)) => {
+                    assert!(chat_log.buffer.get_ref().is_empty());
+                }
+            }
+        }
+        assert!(!chat_log.has_batched());
+        assert_eq!(
+            String::from_utf8_lossy(chat_log.buffer.get_ref()),
+            "1760631669671\tNULL\tYuu\tone\n# 0\trateLimit\t\n"
+        );
+        assert_eq!(chat_log.written, chat_log.buffer.get_ref().len() as u64);
+    }
+}
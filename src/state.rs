@@ -0,0 +1,107 @@
+use std::path::Path;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// Capture progress kept across restarts in the `--state-file`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct State {
+    /// Time of the last written message in milliseconds.
+    #[serde(default)]
+    pub last_timestamp: u64,
+    /// Messages written in all sessions.
+    #[serde(default)]
+    pub messages: u64,
+    /// Bytes written to chat log files in all sessions.
+    #[serde(default)]
+    pub bytes: u64,
+}
+
+impl State {
+    /// Load the state, starting from zero if the file does not exist yet.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Could not read state file {}", path.display()));
+            }
+        };
+        serde_json::from_str(&content)
+            .with_context(|| format!("Could not parse state file {}", path.display()))
+    }
+
+    /// Save the state, replacing the file only once it is fully written.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let content = serde_json::to_string(self)?;
+        std::fs::write(&temp, content)
+            .and_then(|()| std::fs::rename(&temp, path))
+            .with_context(|| format!("Could not write state file {}", path.display()))
+    }
+
+    /// Record a message written to the chat log.
+    pub fn record_message(&mut self, time: u64) {
+        self.messages += 1;
+        self.last_timestamp = self.last_timestamp.max(time);
+    }
+
+    /// Totals of all sessions for the shutdown summary.
+    pub fn totals(&self) -> String {
+        format!(
+            "Captured {} messages and {} bytes in all sessions",
+            self.messages, self.bytes
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::State;
+
+    fn state() -> State {
+        State {
+            last_timestamp: 1760634889806,
+            messages: 1200,
+            bytes: 65536,
+        }
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let json = serde_json::to_string(&state()).unwrap();
+        assert_eq!(
+            json,
+            "{\"last_timestamp\":1760634889806,\"messages\":1200,\"bytes\":65536}"
+        );
+        assert_eq!(serde_json::from_str::<State>(&json).unwrap(), state());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        state().save(&path).unwrap();
+        let loaded = State::load(&path).unwrap();
+        assert_eq!(loaded, state());
+    }
+
+    #[test]
+    fn load_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        assert_eq!(State::load(&path).unwrap(), State::default());
+    }
+
+    #[test]
+    fn record_message() {
+        let mut state = state();
+        state.record_message(1760634889000);
+        assert_eq!(state.messages, 1201);
+        assert_eq!(state.last_timestamp, 1760634889806);
+        state.record_message(1760634890000);
+        assert_eq!(state.last_timestamp, 1760634890000);
+    }
+}
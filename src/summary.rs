@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use regex::Regex;
+
+/// Statistics of the capture session, logged on shutdown.
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// Messages written to the chat log.
+    pub messages: u64,
+    /// Messages skipped due to their message class.
+    pub skipped: u64,
+    /// Messages dropped for being older than the last message or repeating
+    /// a message with the same timestamp.
+    /// Includes genuinely out-of-order messages and replayed history that
+    /// was not already dropped with `--skip-history`.
+    pub dropped_old: u64,
+    /// Replayed history messages from before joining, dropped with `--skip-history`.
+    pub history: u64,
+    /// Written messages per team, keyed by the team's display name.
+    pub teams: HashMap<String, u64>,
+    /// Written messages matching each `--count-pattern`, in the given order.
+    pub pattern_matches: Vec<(Regex, u64)>,
+}
+
+impl Summary {
+    /// Summary that tallies messages matching the given patterns.
+    pub fn with_patterns(patterns: Vec<Regex>) -> Self {
+        Self {
+            pattern_matches: patterns.into_iter().map(|pattern| (pattern, 0)).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Count the message text towards every pattern it matches.
+    pub fn count_patterns(&mut self, text: &str) {
+        for (pattern, count) in &mut self.pattern_matches {
+            if pattern.is_match(text) {
+                *count += 1;
+            }
+        }
+    }
+
+    /// Message counts per pattern. Omitted if no patterns were given.
+    pub fn pattern_breakdown(&self) -> Option<String> {
+        if self.pattern_matches.is_empty() {
+            return None;
+        }
+        let patterns: Vec<String> = self
+            .pattern_matches
+            .iter()
+            .map(|(pattern, count)| format!("/{}/ {}", pattern, count))
+            .collect();
+        Some(format!("Messages per pattern: {}", patterns.join(", ")))
+    }
+
+    /// Written messages have reached the limit of `--max-messages`, if any.
+    pub fn limit_reached(&self, max_messages: Option<u64>) -> bool {
+        max_messages.is_some_and(|max| self.messages >= max)
+    }
+
+    /// Message counts per team, most active team first. Omitted if no
+    /// messages were sent by named teams.
+    pub fn team_breakdown(&self) -> Option<String> {
+        if self.teams.keys().all(|team| team == "NULL") {
+            return None;
+        }
+        let mut teams: Vec<(&String, &u64)> = self.teams.iter().collect();
+        teams.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let teams: Vec<String> = teams
+            .into_iter()
+            .map(|(team, count)| format!("{} {}", team, count))
+            .collect();
+        Some(format!("Messages per team: {}", teams.join(", ")))
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Captured {} messages ({} skipped, {} dropped as older than the last message",
+            self.messages, self.skipped, self.dropped_old
+        )?;
+        if self.history > 0 {
+            write!(
+                f,
+                ", {} dropped as history from before joining",
+                self.history
+            )?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use regex::Regex;
+    use test_case::test_case;
+
+    use super::Summary;
+
+    #[test]
+    fn summary_display() {
+        let summary = Summary {
+            messages: 120,
+            skipped: 3,
+            dropped_old: 7,
+            history: 0,
+            ..Default::default()
+        };
+        assert_eq!(
+            format!("{}", summary),
+            "Captured 120 messages (3 skipped, 7 dropped as older than the last message)"
+        );
+    }
+
+    #[test]
+    fn summary_display_history() {
+        let summary = Summary {
+            messages: 120,
+            skipped: 3,
+            dropped_old: 7,
+            history: 42,
+            ..Default::default()
+        };
+        assert_eq!(
+            format!("{}", summary),
+            "Captured 120 messages (3 skipped, 7 dropped as older than the last message, \
+             42 dropped as history from before joining)"
+        );
+    }
+
+    #[test_case(10, None, false ; "no limit")]
+    #[test_case(9, Some(10), false ; "below limit")]
+    #[test_case(10, Some(10), true ; "at limit")]
+    fn limit_reached(messages: u64, max_messages: Option<u64>, expected: bool) {
+        let summary = Summary {
+            messages,
+            ..Default::default()
+        };
+        assert_eq!(summary.limit_reached(max_messages), expected);
+    }
+
+    #[test]
+    fn team_breakdown() {
+        let summary = Summary {
+            teams: HashMap::from([
+                ("NULL".to_string(), 5),
+                ("wg".to_string(), 10),
+                ("a".to_string(), 5),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            summary.team_breakdown().as_deref(),
+            Some("Messages per team: wg 10, NULL 5, a 5")
+        );
+    }
+
+    #[test]
+    fn pattern_breakdown() {
+        let mut summary = Summary::with_patterns(vec![
+            Regex::new("(?i)carlos").unwrap(),
+            Regex::new("^XD$").unwrap(),
+            Regex::new("woof").unwrap(),
+        ]);
+        summary.count_patterns("Carlos :carlos:");
+        summary.count_patterns("XD");
+        summary.count_patterns("carlos XD");
+        assert_eq!(
+            summary.pattern_breakdown().as_deref(),
+            Some("Messages per pattern: /(?i)carlos/ 2, /^XD$/ 1, /woof/ 0")
+        );
+    }
+
+    #[test]
+    fn pattern_breakdown_no_patterns() {
+        assert_eq!(Summary::default().pattern_breakdown(), None);
+    }
+
+    #[test]
+    fn team_breakdown_no_teams() {
+        let summary = Summary {
+            teams: HashMap::from([("NULL".to_string(), 5)]),
+            ..Default::default()
+        };
+        assert_eq!(summary.team_breakdown(), None);
+    }
+}
@@ -1,28 +1,806 @@
-/// Parse host from plain domain name or URL.
-pub fn parse_domain(s: &str) -> Result<url::Host, String> {
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use reqwest::header::{HeaderName, HeaderValue};
+use unicode_normalization::UnicodeNormalization;
+
+/// Throttle for periodically sampled values. Changed values are always
+/// let through, unchanged values at most once per interval.
+pub struct SampleThrottle<T> {
+    interval: Duration,
+    last: Option<(T, Instant)>,
+}
+
+impl<T: PartialEq> SampleThrottle<T> {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: None,
+        }
+    }
+
+    /// Check if the value should be logged and record it if so.
+    pub fn should_log(&mut self, value: T, now: Instant) -> bool {
+        if let Some((last_value, last_time)) = &self.last
+            && *last_value == value
+            && now.duration_since(*last_time) < self.interval
+        {
+            return false;
+        }
+        self.last = Some((value, now));
+        true
+    }
+}
+
+/// Budget of reconnects before giving up on a server. Reconnects are counted
+/// until the connection stays up for the reset period, so that occasional
+/// drops over a long capture do not exhaust it.
+pub struct ReconnectBudget {
+    max: u32,
+    reset_after: Duration,
+    used: u32,
+    connected_since: Option<Instant>,
+}
+
+impl ReconnectBudget {
+    /// Budget of `max` reconnects, or unlimited if 0.
+    pub fn new(max: u32, reset_after: Duration) -> Self {
+        Self {
+            max,
+            reset_after,
+            used: 0,
+            connected_since: None,
+        }
+    }
+
+    /// Record that the connection was established.
+    pub fn connected(&mut self, now: Instant) {
+        self.connected_since = Some(now);
+    }
+
+    /// Record a disconnect by the server. Repeated disconnects without
+    /// connecting in between are counted once. Returns false once the
+    /// budget is exhausted.
+    pub fn disconnected(&mut self, now: Instant) -> bool {
+        match self.connected_since {
+            Some(_) => self.reconnect(now),
+            None => self.within_budget(),
+        }
+    }
+
+    /// Record a reconnect attempt, either initiated by cupcake or by the
+    /// Socket.IO client after losing the connection. Returns false once the
+    /// budget is exhausted.
+    pub fn reconnect(&mut self, now: Instant) -> bool {
+        if let Some(since) = self.connected_since.take()
+            && now.duration_since(since) >= self.reset_after
+        {
+            self.used = 0;
+        }
+        self.used += 1;
+        self.within_budget()
+    }
+
+    fn within_budget(&self) -> bool {
+        self.max == 0 || self.used <= self.max
+    }
+}
+
+/// Recurring deadline for a `select!` branch, such as writing batches after
+/// `--batch-interval`. Never fires without a period.
+pub struct Ticker {
+    period: Option<Duration>,
+    deadline: tokio::time::Instant,
+}
+
+impl Ticker {
+    pub fn new(period: Option<Duration>) -> Self {
+        Self {
+            period,
+            deadline: tokio::time::Instant::now() + period.unwrap_or_default(),
+        }
+    }
+
+    /// Wait for the deadline and schedule the next one a period later.
+    /// Cancel safe, as the deadline only moves once it was reached.
+    pub async fn tick(&mut self) {
+        let Some(period) = self.period else {
+            return std::future::pending().await;
+        };
+        tokio::time::sleep_until(self.deadline).await;
+        self.deadline = tokio::time::Instant::now() + period;
+    }
+}
+
+/// Filter for chat messages that the server replays when reconnecting,
+/// detected by their timestamp being older than the last message. Messages
+/// sharing the last timestamp are replays only if the same user already sent
+/// the same text at that time, as fast channels have distinct messages
+/// within the same millisecond.
+pub struct ReplayFilter {
+    enabled: bool,
+    last_timestamp: u64,
+    last_messages: HashSet<(String, String)>,
+}
+
+impl ReplayFilter {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            last_timestamp: 0,
+            last_messages: HashSet::new(),
+        }
+    }
+
+    /// Timestamp of the newest message let through.
+    pub fn last_timestamp(&self) -> u64 {
+        self.last_timestamp
+    }
+
+    /// Check if the message is a replay and record it as the newest message if not.
+    pub fn is_replay(&mut self, time: u64, username: &str, text: &str) -> bool {
+        if self.enabled && time < self.last_timestamp {
+            return true;
+        }
+        if time > self.last_timestamp {
+            self.last_timestamp = time;
+            self.last_messages.clear();
+        }
+        let key = (username.to_string(), text.to_string());
+        if time == self.last_timestamp && !self.last_messages.insert(key) {
+            return self.enabled;
+        }
+        false
+    }
+}
+
+/// Sensitive value such as a password that is never printed.
+#[derive(Clone)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret(***)")
+    }
+}
+
+impl std::str::FromStr for Secret {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Secret(s.to_string()))
+    }
+}
+
+/// Read a secret from the named environment variable.
+pub fn secret_from_env(name: &str) -> Result<Secret, String> {
+    std::env::var(name)
+        .map(Secret)
+        .map_err(|_| format!("Environment variable {} is not set", name))
+}
+
+/// Truncate text to at most the given number of characters, appending an
+/// ellipsis if anything was cut off.
+pub fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((index, _)) => format!("{}…", &text[..index]),
+        None => text.to_string(),
+    }
+}
+
+/// Maximum length of a Cytube user name.
+const MAX_NAME_LENGTH: usize = 20;
+
+/// Guest name to try after the given one was taken, incrementing a numeric
+/// suffix like Cytube does: `cupcake`, `cupcake1`, `cupcake2` and so on.
+/// The name is shortened to fit the user name length limit if needed.
+pub fn next_guest_name(name: &str) -> String {
+    let base = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    let number = name[base.len()..].parse::<u64>().map_or(1, |n| n + 1);
+    let suffix = number.to_string();
+    let base: String = base
+        .chars()
+        .take(MAX_NAME_LENGTH.saturating_sub(suffix.len()))
+        .collect();
+    format!("{}{}", base, suffix)
+}
+
+/// Unicode normalization form for message text.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum Normalization {
+    /// Canonical composition.
+    Nfc,
+    /// Compatibility composition, also folding e.g. full-width characters.
+    Nfkc,
+}
+
+impl Normalization {
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            Normalization::Nfc => text.nfc().collect(),
+            Normalization::Nfkc => text.nfkc().collect(),
+        }
+    }
+}
+
+/// Parse HTTP header from a `NAME:VALUE` string.
+pub fn parse_header(s: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let Some((name, value)) = s.split_once(':') else {
+        return Err(String::from("Header must be in the form NAME:VALUE"));
+    };
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|_| format!("Invalid header name '{}'", name.trim()))?;
+    let value = HeaderValue::from_str(value.trim())
+        .map_err(|_| format!("Invalid value for header '{}'", name))?;
+    Ok((name, value))
+}
+
+/// Parse the path of an existing Unix domain socket.
+#[cfg(unix)]
+pub fn parse_unix_socket(s: &str) -> Result<std::path::PathBuf, String> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let path = std::path::PathBuf::from(s);
+    match std::fs::metadata(&path) {
+        Ok(metadata) if metadata.file_type().is_socket() => Ok(path),
+        Ok(_) => Err(format!("'{}' is not a Unix socket", s)),
+        Err(e) => Err(format!("Cannot access '{}': {}", s, e)),
+    }
+}
+
+/// Load a TLS client identity from a PEM certificate chain and PKCS#8 PEM private key.
+pub fn load_identity(cert: &Path, key: &Path) -> anyhow::Result<reqwest::Identity> {
+    let cert_pem = std::fs::read(cert)
+        .with_context(|| format!("Could not read client certificate {}", cert.display()))?;
+    let key_pem = std::fs::read(key)
+        .with_context(|| format!("Could not read client key {}", key.display()))?;
+    reqwest::Identity::from_pkcs8_pem(&cert_pem, &key_pem).with_context(|| {
+        format!(
+            "Could not load client certificate {} with key {}; both must be PEM encoded and the key in PKCS#8 format",
+            cert.display(),
+            key.display()
+        )
+    })
+}
+
+/// Parse HTTP Basic auth credentials from a `USER:PASS` string.
+pub fn parse_basic_auth(s: &str) -> Result<(String, Secret), String> {
+    match s.split_once(':') {
+        Some((user, password)) if !user.is_empty() => {
+            Ok((user.to_string(), Secret(password.to_string())))
+        }
+        _ => Err(String::from("Credentials must be in the form USER:PASS")),
+    }
+}
+
+/// Parse Unix file permission bits given in octal, such as `600`.
+pub fn parse_file_mode(s: &str) -> Result<u32, String> {
+    match u32::from_str_radix(s.trim_start_matches("0o"), 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(String::from(
+            "File mode must be octal permission bits such as 600",
+        )),
+    }
+}
+
+/// Parse a recurring interval such as `30s`, which cannot be zero.
+pub fn parse_interval(s: &str) -> Result<Duration, String> {
+    match humantime::parse_duration(s) {
+        Ok(interval) if interval.is_zero() => Err(String::from("Interval must be longer than 0")),
+        Ok(interval) => Ok(interval),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Options for creating a file, truncating an existing one. On Unix, new
+/// files get the given permission bits, still limited by the umask.
+pub fn create_options(mode: Option<u32>) -> std::fs::OpenOptions {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        options.mode(mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    options
+}
+
+/// Parse a Socket.IO namespace, which must start with a slash.
+pub fn parse_namespace(s: &str) -> Result<String, String> {
+    match s.starts_with('/') {
+        true => Ok(s.to_string()),
+        false => Err(String::from("Namespace must start with /")),
+    }
+}
+
+/// Parse host rewrite rule from a `FROM=TO` string.
+pub fn parse_host_rewrite(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((from, to)) if !from.is_empty() && !to.is_empty() => {
+            Ok((from.to_string(), to.to_string()))
+        }
+        _ => Err(String::from("Host rewrite must be in the form FROM=TO")),
+    }
+}
+
+/// Replace substrings of the URL's host according to the rewrite rules,
+/// preserving the rest of the URL.
+pub fn rewrite_host(address: &str, rewrites: &[(String, String)]) -> String {
+    let Ok(mut url) = url::Url::parse(address) else {
+        return address.to_string();
+    };
+    let Some(host) = url.host_str() else {
+        return address.to_string();
+    };
+    let rewritten = rewrites
+        .iter()
+        .fold(host.to_string(), |host, (from, to)| host.replace(from, to));
+    if rewritten == host || url.set_host(Some(&rewritten)).is_err() {
+        return address.to_string();
+    }
+    url.to_string()
+}
+
+/// Cytube server address given on the command line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Domain {
+    pub scheme: Option<String>,
+    pub host: url::Host,
+    pub port: Option<u16>,
+    /// Channel from a channel URL such as `https://cytu.be/r/test` or `/c/test`.
+    pub channel: Option<String>,
+}
+
+impl Domain {
+    /// Base URL of the server. Defaults to HTTPS when no scheme was given.
+    pub fn base_url(&self) -> String {
+        let scheme = self.scheme.as_deref().unwrap_or("https");
+        match self.port {
+            Some(port) => format!("{}://{}:{}", scheme, self.host, port),
+            None => format!("{}://{}", scheme, self.host),
+        }
+    }
+}
+
+/// Server as given by the user: the bare host for the default HTTPS server,
+/// otherwise the base URL with its scheme and port.
+impl std::fmt::Display for Domain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.scheme, self.port) {
+            (None, None) => write!(f, "{}", self.host),
+            _ => f.write_str(&self.base_url()),
+        }
+    }
+}
+
+/// Expand the `{channel}` placeholder of a socket config path template into
+/// a path relative to the server root.
+pub fn expand_config_path(template: &str, channel: &str) -> String {
+    let path = template.replace("{channel}", channel);
+    match path.starts_with('/') {
+        true => path,
+        false => format!("/{}", path),
+    }
+}
+
+/// Parse a channel name, stripping the `/r/` or `/c/` path prefix of a
+/// channel URL so that the channel is always joined by its bare name.
+pub fn parse_channel(s: &str) -> Result<String, String> {
+    let path = s.trim_matches('/');
+    let name = ["r/", "c/"]
+        .iter()
+        .find_map(|prefix| path.strip_prefix(prefix))
+        .unwrap_or(path);
+    match name.contains('/') {
+        true => Err(String::from("Not a valid channel name")),
+        false => Ok(name.to_string()),
+    }
+}
+
+/// Parse host from plain domain name or URL, retaining an explicit scheme and
+/// port, and the channel of a `/r/<channel>` or `/c/<channel>` URL path.
+pub fn parse_domain(s: &str) -> Result<Domain, String> {
     if let Ok(host) = url::Host::parse(s) {
-        return Ok(host);
+        return Ok(Domain {
+            scheme: None,
+            host,
+            port: None,
+            channel: None,
+        });
     };
     if let Ok(domain) = url::Url::parse(s)
         && let Some(host) = domain.host()
     {
-        return Ok(host.to_owned());
+        let channel = domain.path_segments().and_then(|mut segments| {
+            match (segments.next(), segments.next()) {
+                (Some("r" | "c"), Some(channel)) if !channel.is_empty() => {
+                    Some(channel.to_string())
+                }
+                _ => None,
+            }
+        });
+        return Ok(Domain {
+            scheme: Some(domain.scheme().to_string()),
+            host: host.to_owned(),
+            port: domain.port(),
+            channel,
+        });
     };
     Err(String::from("Not a valid domain or URL"))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::{Duration, Instant};
+
+    use reqwest::header::{HeaderName, HeaderValue};
     use test_case::test_case;
 
-    #[test_case("cytu.be", Some("cytu.be"); "plain domain")]
-    #[test_case("https://cytu.be", Some("cytu.be"); "URL")]
+    use super::{
+        Domain, Normalization, ReconnectBudget, ReplayFilter, SampleThrottle, Secret, Ticker,
+    };
+
+    #[test_case("600", Ok(0o600); "owner only")]
+    #[test_case("0640", Ok(0o640); "leading zero")]
+    #[test_case("0o600", Ok(0o600); "rust prefix")]
+    #[test_case("777", Ok(0o777); "everyone")]
+    #[test_case("1777", Err(()); "sticky bit")]
+    #[test_case("680", Err(()); "not octal")]
+    #[test_case("", Err(()); "empty")]
+    fn parse_file_mode(input: &str, expected: Result<u32, ()>) {
+        assert_eq!(super::parse_file_mode(input).map_err(|_| ()), expected);
+    }
+
+    #[test_case("30s", Ok(Duration::from_secs(30)); "seconds")]
+    #[test_case("500ms", Ok(Duration::from_millis(500)); "milliseconds")]
+    #[test_case("0s", Err(()); "zero")]
+    #[test_case("soon", Err(()); "invalid")]
+    fn parse_interval(input: &str, expected: Result<Duration, ()>) {
+        assert_eq!(super::parse_interval(input).map_err(|_| ()), expected);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn create_options_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mode.txt");
+        super::create_options(Some(0o600)).open(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test_case("Cookie:cf_clearance=abc", "cookie", "cf_clearance=abc"; "no whitespace")]
+    #[test_case("X-Forwarded-For: 127.0.0.1", "x-forwarded-for", "127.0.0.1"; "whitespace")]
+    #[test_case("Referer:https://cytu.be/", "referer", "https://cytu.be/"; "colon in value")]
+    fn parse_header(input: &str, name: &str, value: &str) {
+        let expected = (
+            HeaderName::from_bytes(name.as_bytes()).unwrap(),
+            HeaderValue::from_str(value).unwrap(),
+        );
+        assert_eq!(super::parse_header(input), Ok(expected));
+    }
+
+    #[test_case("Cookie", "Header must be in the form NAME:VALUE"; "missing separator")]
+    #[test_case(":value", "Invalid header name ''"; "empty name")]
+    #[test_case("Bad Name:value", "Invalid header name 'Bad Name'"; "invalid name")]
+    #[test_case("Cookie:a\nb", "Invalid value for header 'cookie'"; "invalid value")]
+    fn parse_header_invalid(input: &str, expected: &str) {
+        assert_eq!(super::parse_header(input), Err(String::from(expected)));
+    }
+
+    #[test_case("short", 10, "short"; "shorter than limit")]
+    #[test_case("exactly", 7, "exactly"; "at limit")]
+    #[test_case("too long", 3, "too…"; "longer than limit")]
+    #[test_case("ääkkönen", 3, "ääk…"; "multibyte")]
+    #[test_case("🧁🧁🧁", 2, "🧁🧁…"; "emoji")]
+    fn truncate(input: &str, max_chars: usize, expected: &str) {
+        assert_eq!(super::truncate(input, max_chars), expected);
+    }
+
+    #[test_case("cupcake", "cupcake1"; "no suffix")]
+    #[test_case("cupcake1", "cupcake2"; "suffix")]
+    #[test_case("cupcake9", "cupcake10"; "carry")]
+    #[test_case("abcdefghijklmnopqrst", "abcdefghijklmnopqrs1"; "at length limit")]
+    #[test_case("abcdefghijklmnopqrs9", "abcdefghijklmnopqr10"; "longer suffix at length limit")]
+    fn next_guest_name(name: &str, expected: &str) {
+        assert_eq!(super::next_guest_name(name), expected);
+    }
+
+    #[test_case(Normalization::Nfc, "e\u{301}", "\u{e9}"; "nfc from nfd")]
+    #[test_case(Normalization::Nfc, "\u{e9}", "\u{e9}"; "nfc unchanged")]
+    #[test_case(Normalization::Nfkc, "e\u{301}", "\u{e9}"; "nfkc from nfd")]
+    #[test_case(Normalization::Nfc, "\u{ff21}", "\u{ff21}"; "nfc keeps full-width")]
+    #[test_case(Normalization::Nfkc, "\u{ff21}", "A"; "nfkc folds full-width")]
+    fn normalization(form: Normalization, input: &str, expected: &str) {
+        assert_eq!(form.apply(input), expected);
+    }
+
+    #[test]
+    fn secret_debug() {
+        let secret: Secret = "hunter2".parse().unwrap();
+        assert_eq!(format!("{:?}", secret), "Secret(***)");
+        assert_eq!(secret.expose(), "hunter2");
+    }
+
+    #[test]
+    fn secret_from_env_unset() {
+        assert_eq!(
+            super::secret_from_env("CUPCAKE_TEST_UNSET").unwrap_err(),
+            "Environment variable CUPCAKE_TEST_UNSET is not set"
+        );
+    }
+
+    #[test]
+    fn sample_throttle() {
+        let start = Instant::now();
+        let mut throttle = SampleThrottle::new(Duration::from_secs(60));
+        assert!(throttle.should_log(10, start));
+        assert!(!throttle.should_log(10, start + Duration::from_secs(30)));
+        assert!(throttle.should_log(11, start + Duration::from_secs(31)));
+        assert!(!throttle.should_log(11, start + Duration::from_secs(90)));
+        assert!(throttle.should_log(11, start + Duration::from_secs(91)));
+    }
+
+    #[test]
+    fn reconnect_budget() {
+        let start = Instant::now();
+        let mut budget = ReconnectBudget::new(2, Duration::from_secs(300));
+        budget.connected(start);
+        assert!(budget.disconnected(start + Duration::from_secs(10)));
+        // Only the first disconnect while not connected counts.
+        assert!(budget.disconnected(start + Duration::from_secs(11)));
+        assert_eq!(budget.used, 1);
+        assert!(budget.reconnect(start + Duration::from_secs(20)));
+        assert!(!budget.reconnect(start + Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn reconnect_budget_reset() {
+        let start = Instant::now();
+        let mut budget = ReconnectBudget::new(2, Duration::from_secs(300));
+        budget.connected(start);
+        assert!(budget.disconnected(start + Duration::from_secs(10)));
+        budget.connected(start + Duration::from_secs(20));
+        assert!(budget.disconnected(start + Duration::from_secs(60)));
+        assert_eq!(budget.used, 2);
+        // Staying connected for the reset period restores the budget.
+        budget.connected(start + Duration::from_secs(70));
+        assert!(budget.disconnected(start + Duration::from_secs(370)));
+        assert_eq!(budget.used, 1);
+    }
+
+    #[test]
+    fn reconnect_budget_failed_attempts() {
+        let start = Instant::now();
+        let mut budget = ReconnectBudget::new(3, Duration::from_secs(300));
+        budget.connected(start);
+        // Attempts to reconnect after a lost connection all count, as none succeed.
+        assert!(budget.reconnect(start + Duration::from_secs(600)));
+        assert!(budget.reconnect(start + Duration::from_secs(601)));
+        assert!(budget.reconnect(start + Duration::from_secs(603)));
+        assert!(!budget.reconnect(start + Duration::from_secs(607)));
+    }
+
+    #[test]
+    fn reconnect_budget_unlimited() {
+        let start = Instant::now();
+        let mut budget = ReconnectBudget::new(0, Duration::from_secs(300));
+        for i in 0..100 {
+            assert!(budget.reconnect(start + Duration::from_secs(i)));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ticker() {
+        let start = tokio::time::Instant::now();
+        let mut ticker = Ticker::new(Some(Duration::from_secs(1)));
+        ticker.tick().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+        // A tick that was cancelled keeps its deadline.
+        let cancelled = tokio::time::timeout(Duration::from_millis(500), ticker.tick()).await;
+        assert!(cancelled.is_err());
+        ticker.tick().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ticker_disabled() {
+        let mut ticker = Ticker::new(None);
+        let tick = tokio::time::timeout(Duration::from_secs(3600), ticker.tick()).await;
+        assert!(tick.is_err());
+    }
+
+    #[test]
+    fn replay_filter() {
+        let mut filter = ReplayFilter::new(true);
+        assert!(!filter.is_replay(100, "Dog", "woof"));
+        assert!(filter.is_replay(99, "Dog", "bark"));
+        assert!(!filter.is_replay(101, "Dog", "bark"));
+        assert_eq!(filter.last_timestamp(), 101);
+    }
+
+    #[test]
+    fn replay_filter_same_millisecond_distinct() {
+        let mut filter = ReplayFilter::new(true);
+        assert!(!filter.is_replay(100, "Dog", "woof"));
+        assert!(!filter.is_replay(100, "Cat", "woof"));
+        assert!(!filter.is_replay(100, "Dog", "bark"));
+    }
+
+    #[test]
+    fn replay_filter_same_millisecond_duplicate() {
+        let mut filter = ReplayFilter::new(true);
+        assert!(!filter.is_replay(100, "Dog", "woof"));
+        assert!(!filter.is_replay(100, "Cat", "meow"));
+        assert!(filter.is_replay(100, "Dog", "woof"));
+        assert!(filter.is_replay(100, "Cat", "meow"));
+    }
+
+    #[test]
+    fn replay_filter_disabled() {
+        let mut filter = ReplayFilter::new(false);
+        assert!(!filter.is_replay(100, "Dog", "woof"));
+        assert!(!filter.is_replay(100, "Dog", "woof"));
+        assert!(!filter.is_replay(99, "Dog", "woof"));
+        assert_eq!(filter.last_timestamp(), 100);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parse_unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cupcake.sock");
+        let _listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        assert_eq!(super::parse_unix_socket(path.to_str().unwrap()), Ok(path));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parse_unix_socket_invalid() {
+        assert_eq!(
+            super::parse_unix_socket("Cargo.toml"),
+            Err(String::from("'Cargo.toml' is not a Unix socket"))
+        );
+        assert!(super::parse_unix_socket("/nonexistent/cupcake.sock").is_err());
+    }
+
+    #[test]
+    fn load_identity_missing() {
+        let err = super::load_identity(
+            std::path::Path::new("/nonexistent/cert.pem"),
+            std::path::Path::new("/nonexistent/key.pem"),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Could not read client certificate /nonexistent/cert.pem"
+        );
+    }
+
+    #[test]
+    fn load_identity_malformed() {
+        let path = std::path::Path::new("Cargo.toml");
+        let err = super::load_identity(path, path).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Could not load client certificate Cargo.toml with key Cargo.toml")
+        );
+    }
+
+    #[test_case("cupcake:hunter2", Ok(("cupcake", "hunter2")); "valid")]
+    #[test_case("cupcake:pass:word", Ok(("cupcake", "pass:word")); "colon in password")]
+    #[test_case("cupcake:", Ok(("cupcake", "")); "empty password")]
+    #[test_case("cupcake", Err(()); "missing separator")]
+    #[test_case(":hunter2", Err(()); "empty user")]
+    fn parse_basic_auth(input: &str, expected: Result<(&str, &str), ()>) {
+        let parsed = super::parse_basic_auth(input);
+        let parsed = parsed
+            .as_ref()
+            .map(|(user, password)| (user.as_str(), password.expose()))
+            .map_err(|e| e.as_str());
+        let expected = expected.map_err(|_| "Credentials must be in the form USER:PASS");
+        assert_eq!(parsed, expected);
+    }
+
+    #[test_case("/", Ok("/"); "root")]
+    #[test_case("/chat", Ok("/chat"); "namespace")]
+    #[test_case("chat", Err("Namespace must start with /"); "missing slash")]
+    fn parse_namespace(input: &str, expected: Result<&str, &str>) {
+        let expected = expected.map(String::from).map_err(String::from);
+        assert_eq!(super::parse_namespace(input), expected);
+    }
+
+    #[test_case("internal.cytu.be=cytu.be", Ok(("internal.cytu.be", "cytu.be")); "valid")]
+    #[test_case("cytu.be", Err(()); "missing separator")]
+    #[test_case("=cytu.be", Err(()); "empty from")]
+    fn parse_host_rewrite(input: &str, expected: Result<(&str, &str), ()>) {
+        let expected = expected
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .map_err(|_| String::from("Host rewrite must be in the form FROM=TO"));
+        assert_eq!(super::parse_host_rewrite(input), expected);
+    }
+
+    #[test_case("https://node1.internal:8443/socket.io", "https://node1.cytu.be:8443/socket.io"; "rewritten")]
+    #[test_case("https://cytu.be:8443", "https://cytu.be:8443"; "no match")]
+    fn rewrite_host(input: &str, expected: &str) {
+        let rewrites = vec![(String::from("internal"), String::from("cytu.be"))];
+        assert_eq!(super::rewrite_host(input, &rewrites), expected);
+    }
+
+    #[test_case("cytu.be", Some((None, "cytu.be", None)); "plain domain")]
+    #[test_case("https://cytu.be", Some((Some("https"), "cytu.be", None)); "URL")]
+    #[test_case("http://localhost:8080", Some((Some("http"), "localhost", Some(8080))); "URL with port")]
     #[test_case("@t!", None; "invalid characters")]
-    fn parse_domain(input: &str, expected: Option<&str>) {
+    fn parse_domain(input: &str, expected: Option<(Option<&str>, &str, Option<u16>)>) {
         let expected = match expected {
-            Some(s) => Ok(url::Host::Domain(s.to_string())),
+            Some((scheme, host, port)) => Ok(Domain {
+                scheme: scheme.map(String::from),
+                host: url::Host::Domain(host.to_string()),
+                port,
+                channel: None,
+            }),
             None => Err(String::from("Not a valid domain or URL")),
         };
         assert_eq!(super::parse_domain(input), expected);
     }
+
+    #[test_case("test", Ok("test"); "bare name")]
+    #[test_case("/r/test", Ok("test"); "registered path")]
+    #[test_case("c/test/", Ok("test"); "legacy path")]
+    #[test_case("", Ok(""); "empty")]
+    #[test_case("a/b", Err("Not a valid channel name"); "other path")]
+    fn parse_channel(input: &str, expected: Result<&str, &str>) {
+        let expected = expected.map(String::from).map_err(String::from);
+        assert_eq!(super::parse_channel(input), expected);
+    }
+
+    #[test_case("https://cytu.be/r/test", Some("test"); "channel URL")]
+    #[test_case("https://cytu.be/r/test/", Some("test"); "trailing slash")]
+    #[test_case("http://localhost:8080/r/test?x=1", Some("test"); "port and query")]
+    #[test_case("https://cytu.be/c/test", Some("test"); "legacy channel URL")]
+    #[test_case("https://cytu.be/r/", None; "empty channel")]
+    #[test_case("https://cytu.be/test", None; "no /r/ path")]
+    #[test_case("https://cytu.be", None; "no path")]
+    #[test_case("cytu.be", None; "plain domain")]
+    fn parse_domain_channel(input: &str, expected: Option<&str>) {
+        let domain = super::parse_domain(input).unwrap();
+        assert_eq!(domain.channel.as_deref(), expected);
+    }
+
+    #[test_case("cytu.be", "https://cytu.be"; "plain domain")]
+    #[test_case("http://localhost:8080", "http://localhost:8080"; "scheme and port")]
+    #[test_case("https://cytu.be:443/r/test", "https://cytu.be"; "default port")]
+    #[test_case("127.0.0.1", "https://127.0.0.1"; "IP address")]
+    fn domain_base_url(input: &str, expected: &str) {
+        let domain = super::parse_domain(input).unwrap();
+        assert_eq!(domain.base_url(), expected);
+    }
+
+    #[test_case("cytu.be", "cytu.be"; "plain domain")]
+    #[test_case("http://localhost:8080", "http://localhost:8080"; "scheme and port")]
+    #[test_case("https://cytu.be/r/test", "https://cytu.be"; "channel URL")]
+    fn domain_display(input: &str, expected: &str) {
+        let domain = super::parse_domain(input).unwrap();
+        assert_eq!(domain.to_string(), expected);
+    }
+
+    #[test_case("/socketconfig/{channel}.json", "/socketconfig/test.json"; "default")]
+    #[test_case("api/{channel}/socket.json", "/api/test/socket.json"; "relative")]
+    #[test_case("/socketconfig.json", "/socketconfig.json"; "no placeholder")]
+    fn expand_config_path(template: &str, expected: &str) {
+        assert_eq!(super::expand_config_path(template, "test"), expected);
+    }
 }
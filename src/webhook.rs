@@ -0,0 +1,275 @@
+use std::time::Duration;
+
+use futures_util::FutureExt;
+use futures_util::future::BoxFuture;
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+#[cfg(feature = "broadcast")]
+use crate::Event;
+#[cfg(feature = "broadcast")]
+use crate::channel::{self, BroadcastRx};
+use crate::data::ChatMessage;
+use crate::sink::MessageSink;
+use crate::utils::Secret;
+
+const WEBHOOK_QUEUE_SIZE: usize = 256;
+/// How long closing waits for the queued payloads to be sent.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Forwards logged chat messages to a webhook without blocking the manager task.
+pub struct Webhook {
+    link_hosts: Vec<String>,
+    tx: Option<mpsc::Sender<Value>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl Webhook {
+    pub fn spawn(client: reqwest::Client, url: Secret, link_hosts: Vec<String>) -> Self {
+        let (tx, mut rx) = mpsc::channel::<Value>(WEBHOOK_QUEUE_SIZE);
+        let task = tokio::spawn(async move {
+            while let Some(payload) = rx.recv().await {
+                let response = client.post(url.expose()).json(&payload).send().await;
+                // The URL may contain a token, so it is left out of the logs.
+                if let Err(e) = response.and_then(|r| r.error_for_status()) {
+                    log::warn!("Webhook request failed: {}", e.without_url());
+                }
+            }
+            log::debug!("Ending webhook task");
+        });
+        Self {
+            link_hosts,
+            tx: Some(tx),
+            task: Some(task),
+        }
+    }
+
+    /// Forward the chat messages of a broadcast event stream, independently of
+    /// the chat log, until the stream is terminated or the token is cancelled.
+    #[cfg(feature = "broadcast")]
+    pub fn forward(
+        mut self,
+        mut rx: BroadcastRx,
+        token: tokio_util::sync::CancellationToken,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    biased;
+                    event = channel::read_broadcast_event(&mut rx) => event,
+                    _ = token.cancelled() => None,
+                };
+                match event {
+                    Some(Event::Chat(values, _)) => {
+                        for value in values {
+                            match ChatMessage::from_value(value) {
+                                Ok(chat) => self.send(chat.to_json_with_links(&self.link_hosts)),
+                                Err(e) => log::warn!("Could not parse chat message: {}", e),
+                            }
+                        }
+                    }
+                    Some(Event::Terminate) | None => break,
+                    Some(_) => {}
+                }
+            }
+            if let Err(e) = self.close().await {
+                log::error!("{:#}", e);
+            }
+        })
+    }
+
+    /// Queue a payload for sending, dropping it if the webhook has fallen behind.
+    fn send(&self, payload: Value) {
+        if let Some(tx) = &self.tx
+            && tx.try_send(payload).is_err()
+        {
+            log::warn!("Webhook queue is full, dropping message");
+        }
+    }
+}
+
+impl MessageSink for Webhook {
+    fn write<'a>(
+        &'a mut self,
+        chat: &'a ChatMessage,
+        seq: Option<u64>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        let mut payload = chat.to_json_with_links(&self.link_hosts);
+        if let Some(seq) = seq {
+            payload["seq"] = Value::from(seq);
+        }
+        self.send(payload);
+        async { Ok(()) }.boxed()
+    }
+
+    /// Notices are not forwarded to the webhook.
+    fn write_notice<'a>(
+        &'a mut self,
+        _time: i64,
+        _event: &'a str,
+        _detail: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        async { Ok(()) }.boxed()
+    }
+
+    fn flush(&mut self) -> BoxFuture<'_, ()> {
+        async {}.boxed()
+    }
+
+    /// Send the queued payloads and end the webhook task. Payloads that are
+    /// not sent within `CLOSE_TIMEOUT` are dropped.
+    fn close(&mut self) -> BoxFuture<'_, anyhow::Result<()>> {
+        async move {
+            drop(self.tx.take());
+            if let Some(mut task) = self.task.take() {
+                match tokio::time::timeout(CLOSE_TIMEOUT, &mut task).await {
+                    Ok(result) => {
+                        result.map_err(|e| anyhow::anyhow!("Webhook task failed: {}", e))?
+                    }
+                    Err(_) => {
+                        log::warn!(
+                            "Webhook did not finish sending in time, dropping queued messages"
+                        );
+                        task.abort();
+                    }
+                }
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    use super::Webhook;
+    use crate::data::ChatMessage;
+    use crate::sink::MessageSink;
+
+    fn chat(text: &str) -> ChatMessage {
+        serde_json::from_value(json!({
+            "username": "Yuu",
+            "msg": text,
+            "meta": {},
+            "time": 1760631669671u64
+        }))
+        .unwrap()
+    }
+
+    /// Answer one request with each of the given statuses on a local port,
+    /// returning the webhook URL and a task that yields the request bodies.
+    async fn serve(statuses: Vec<&'static str>) -> (String, JoinHandle<Vec<Value>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let task = tokio::spawn(async move {
+            let mut bodies = Vec::new();
+            for status in statuses {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = BufReader::new(stream);
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    stream.read_line(&mut line).await.unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':')
+                        && name.eq_ignore_ascii_case("content-length")
+                    {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                stream.read_exact(&mut body).await.unwrap();
+                bodies.push(serde_json::from_slice(&body).unwrap());
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+            bodies
+        });
+        (url, task)
+    }
+
+    #[tokio::test]
+    async fn webhook_posts_messages() {
+        let (url, server) = serve(vec!["200 OK"]).await;
+        let mut webhook = Webhook::spawn(reqwest::Client::new(), url.parse().unwrap(), Vec::new());
+        webhook.write(&chat("one"), Some(5)).await.unwrap();
+        webhook.close().await.unwrap();
+        let bodies = server.await.unwrap();
+        assert_eq!(bodies.len(), 1);
+        assert_eq!(bodies[0]["username"], "Yuu");
+        assert_eq!(bodies[0]["text"], "one");
+        assert_eq!(bodies[0]["seq"], 5);
+    }
+
+    /// A failed request is logged and the following messages are still sent.
+    #[tokio::test]
+    async fn webhook_continues_after_error() {
+        let (url, server) = serve(vec!["500 Internal Server Error", "200 OK"]).await;
+        let mut webhook = Webhook::spawn(reqwest::Client::new(), url.parse().unwrap(), Vec::new());
+        webhook.write(&chat("one"), None).await.unwrap();
+        webhook.write(&chat("two"), None).await.unwrap();
+        webhook.close().await.unwrap();
+        let texts: Vec<Value> = server
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|body| body["text"].clone())
+            .collect();
+        assert_eq!(texts, ["one", "two"]);
+    }
+
+    #[tokio::test]
+    async fn webhook_unreachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        drop(listener);
+        let mut webhook = Webhook::spawn(reqwest::Client::new(), url.parse().unwrap(), Vec::new());
+        webhook.write(&chat("one"), None).await.unwrap();
+        webhook.close().await.unwrap();
+    }
+
+    /// Closing does not wait forever on a server that never responds.
+    #[tokio::test(start_paused = true)]
+    async fn webhook_close_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let mut webhook = Webhook::spawn(reqwest::Client::new(), url.parse().unwrap(), Vec::new());
+        webhook.write(&chat("one"), None).await.unwrap();
+        webhook.close().await.unwrap();
+        assert!(webhook.task.is_none());
+        drop(listener);
+    }
+    #[cfg(feature = "broadcast")]
+    #[tokio::test]
+    async fn webhook_forward() {
+        use crate::Event;
+        use crate::channel::broadcast_channel;
+
+        let (url, server) = serve(vec!["200 OK"]).await;
+        let webhook = Webhook::spawn(reqwest::Client::new(), url.parse().unwrap(), Vec::new());
+        let (tx, rx) = broadcast_channel();
+        let task = webhook.forward(rx, tokio_util::sync::CancellationToken::new());
+        tx.send(Event::UserCount(3)).unwrap();
+        tx.send(Event::Chat(
+            vec![json!({"username": "Yuu", "msg": "one", "meta": {}, "time": 1760631669671u64})],
+            0,
+        ))
+        .unwrap();
+        tx.send(Event::Terminate).unwrap();
+        task.await.unwrap();
+        let bodies = server.await.unwrap();
+        assert_eq!(bodies.len(), 1);
+        assert_eq!(bodies[0]["text"], "one");
+    }
+}